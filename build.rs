@@ -0,0 +1,32 @@
+//generates the gRPC service code of the 'grpc' feature. Service is described by hand, as the messages are
+//written in src/grpc.rs to match proto/stegegg.proto, so protoc isn't needed for the build.
+//The 'node' feature sets up linking of the Node.js native module.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+
+        let method = |name: &str, route: &str, message: &str| Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("crate::grpc::{}Request", message))
+            .output_type(format!("crate::grpc::{}Response", message))
+            .codec_path("tonic::codec::ProstCodec")
+            .build();
+
+        let service = Service::builder()
+            .name("Stegegg")
+            .package("stegegg")
+            .method(method("embed", "Embed", "Embed"))
+            .method(method("extract", "Extract", "Extract"))
+            .method(method("capacity", "Capacity", "Capacity"))
+            .build();
+
+        Builder::new().build_client(false).compile(&[service]);
+    }
+}
@@ -0,0 +1,36 @@
+/* C interface of the stegegg library (libstegegg.so / stegegg.dll / libstegegg.dylib).
+ * Images are encoded files (PNG, BMP, ...), stegegg_hide returns the stego image as PNG.
+ * Output buffers are allocated by the library and must be released with stegegg_free. */
+#ifndef STEGEGG_H
+#define STEGEGG_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define STEGEGG_OK 0
+#define STEGEGG_ERROR 1 /* invalid input, decoding or internal error, stegegg_last_error has the message */
+#define STEGEGG_NO_PAYLOAD 2 /* no message for the key (wrong key or clean image) */
+
+/* hide the message into the image, the stego PNG is returned in out / out_len */
+int stegegg_hide(const uint8_t *image, size_t image_len, const uint8_t *key, size_t key_len,
+                 const uint8_t *message, size_t message_len, uint8_t **out, size_t *out_len);
+
+/* extract the message from the image into out / out_len */
+int stegegg_extract(const uint8_t *image, size_t image_len, const uint8_t *key, size_t key_len,
+                    uint8_t **out, size_t *out_len);
+
+/* release the buffer returned by stegegg_hide or stegegg_extract */
+void stegegg_free(uint8_t *data, size_t len);
+
+/* message of the last error in the calling thread, valid until the next call from the same thread */
+const char *stegegg_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,29 @@
+# Messages of the stegegg command line. Other locales translate the same ids,
+# missing ones fall back to English.
+
+input-not-specified = Input file not specified.
+output-not-specified = Output file not specified.
+message-not-specified = Input data / message not specified!
+    Please specify it with -m or -M parameter.
+message-hidden = Message hidden in the '{ $file }'.
+bytes-written = { $count } bytes written to '{ $file }'
+bytes-written-stdout = { $count } bytes written to stdout
+file-error = Error accessing the file '{ $file }'. { $error }
+output-exists = '{ $file }' already exists, writing into '{ $other }'. Use --force to overwrite.
+same-file = Input and output are the same file '{ $file }'.
+    Writing into the input would corrupt it.
+binary-to-terminal = Extracted data is binary and the output is a terminal.
+    Redirect the output or use --force-binary.
+message-decrypted = Message decrypted into { $count } bytes.
+output-size = Output size { $size } bytes ({ $delta }% compared to the cover).
+output-larger = output is much larger than the cover, which may draw attention.
+key-prompt = Key:{ " " }
+passphrase-prompt = Passphrase for '{ $file }':{ " " }
+passphrase-new = Passphrase:{ " " }
+passphrase-repeat = Repeat the passphrase:{ " " }
+key-not-locked = can't lock the key in memory, it may be swapped out. { $error }
+# the detail explains why the header didn't match
+no-payload = { $detail }
+diag-error = Error
+diag-warning = Warning
+diag-hint = Hint
@@ -0,0 +1,27 @@
+# Mensajes de la línea de comandos de stegegg.
+
+input-not-specified = No se ha indicado el archivo de entrada.
+output-not-specified = No se ha indicado el archivo de salida.
+message-not-specified = ¡No se han indicado los datos / el mensaje!
+    Indícalo con el parámetro -m o -M.
+message-hidden = Mensaje oculto en '{ $file }'.
+bytes-written = { $count } bytes escritos en '{ $file }'
+bytes-written-stdout = { $count } bytes escritos en la salida estándar
+file-error = Error al acceder al archivo '{ $file }'. { $error }
+output-exists = '{ $file }' ya existe, se escribe en '{ $other }'. Usa --force para sobrescribirlo.
+same-file = La entrada y la salida son el mismo archivo '{ $file }'.
+    Escribir en la entrada la dañaría.
+binary-to-terminal = Los datos extraídos son binarios y la salida es una terminal.
+    Redirige la salida o usa --force-binary.
+message-decrypted = Mensaje descifrado en { $count } bytes.
+output-size = Tamaño de la salida { $size } bytes ({ $delta } % respecto a la imagen original).
+output-larger = la salida es mucho más grande que la imagen original, lo que puede llamar la atención.
+key-prompt = Clave:{ " " }
+passphrase-prompt = Frase de contraseña para '{ $file }':{ " " }
+passphrase-new = Frase de contraseña:{ " " }
+passphrase-repeat = Repita la frase de contraseña:{ " " }
+key-not-locked = no se puede bloquear la clave en memoria, podría escribirse en el archivo de intercambio. { $error }
+no-payload = No hay ningún mensaje oculto para esta clave. ¿Has usado la clave correcta?
+diag-error = Error
+diag-warning = Aviso
+diag-hint = Sugerencia
@@ -0,0 +1,124 @@
+//! Authenticated encryption of the payload with ChaCha20-Poly1305. The encryption key is derived from the password
+//! and the random salt stored in front of the ciphertext by the Kdf, so every message has its own key and the nonce
+//! is fixed. The wrong key fails on the tag instead of producing garbage.
+//! Envelope is: version (1 byte), cipher id (1), Kdf id (1), coding id (1), salt (16), ciphertext with the tag.
+//! The ids tell the extraction how the message was sealed, so it needs no options, and new algorithms get new ids
+//! instead of a new format. The header is authenticated with the ciphertext.
+
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+use crate::{scrypt_key, ExtractError, Kdf};
+
+const ENVELOPE_VERSION: u8 = 1;
+
+//id of ChaCha20-Poly1305, the only cipher so far
+const CIPHER_CHACHA20_POLY1305: u8 = 1;
+
+//id of the plaintext coding, 0 is the message as it is. Other ids are reserved for the future codings.
+const CODING_PLAIN: u8 = 0;
+
+//length of the algorithm ids in front of the salt
+const HEADER_LEN: usize = 4;
+
+/// Length of the salt in front of the ciphertext
+pub const PAYLOAD_SALT_LEN: usize = 16;
+
+/// Bytes added to the message by the encryption, the header, the salt and the tag
+pub const OVERHEAD: usize = HEADER_LEN + PAYLOAD_SALT_LEN + 16;
+
+//encryption key from the password and the payload salt. Sha256 is the single hash of the legacy messages.
+fn derive_key(key: &[u8], salt: &[u8], kdf: Kdf) -> Result<Zeroizing<Vec<u8>>, String> {
+    match kdf {
+        Kdf::Sha256 => {
+            let mut hasher = Sha256::new();
+            hasher.update(b"stegegg payload key");
+            hasher.update((key.len() as u64).to_le_bytes());
+            hasher.update(key);
+            hasher.update(salt);
+            Ok(Zeroizing::new(hasher.finalize().to_vec()))
+        },
+        Kdf::Scrypt => scrypt_key(key, &[b"stegegg payload key".as_slice(), salt].concat()),
+    }
+}
+
+fn cipher(key: &[u8], salt: &[u8], kdf: Kdf) -> Result<ChaCha20Poly1305, String> {
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&derive_key(key, salt, kdf)?)))
+}
+
+/// Encrypt the message by the key derived with the kdf, returns the envelope with the ids, the salt, the ciphertext and the tag
+pub fn seal(key: &[u8], message: &[u8], kdf: Kdf) -> Result<Vec<u8>, String> {
+    let mut data = vec![ENVELOPE_VERSION, CIPHER_CHACHA20_POLY1305, kdf.id(), CODING_PLAIN];
+    let mut salt = [0; PAYLOAD_SALT_LEN];
+    getrandom::fill(&mut salt).map_err(|s| format!("Can't generate random salt. {}", s))?;
+    data.extend_from_slice(&salt);
+    let payload = Payload { msg: message, aad: &data[..HEADER_LEN] };
+    let ciphertext = cipher(key, &salt, kdf)?.encrypt(Nonce::from_slice(&[0; 12]), payload).map_err(|_| "Can't encrypt the message.".to_string())?;
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+
+/// Decrypt and authenticate the message by the algorithms named in its envelope
+pub fn open(key: &[u8], data: &[u8]) -> Result<Vec<u8>, ExtractError> {
+    let failed = || ExtractError::NoPayload("Authentication of the message failed. Wrong key, or the message was modified.".to_string());
+    if data.len() < OVERHEAD {
+        return Err(failed());
+    }
+    let (header, rest) = data.split_at(HEADER_LEN);
+    let kdf = match Kdf::from_id(header[2]) {
+        Some(kdf) if header[0] <= ENVELOPE_VERSION && header[1] == CIPHER_CHACHA20_POLY1305 && header[3] == CODING_PLAIN => kdf,
+        _ => return Err(ExtractError::Decode(format!("Encrypted message has envelope version {}, cipher {}, key derivation {} and coding {}, which this stegegg doesn't support. Please update stegegg.",
+            header[0], header[1], header[2], header[3]))),
+    };
+    let (salt, ciphertext) = rest.split_at(PAYLOAD_SALT_LEN);
+    let payload = Payload { msg: ciphertext, aad: header };
+    cipher(key, salt, kdf)?.decrypt(Nonce::from_slice(&[0; 12]), payload).map_err(|_| failed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_round_trip() {
+        for kdf in [Kdf::Sha256, Kdf::Scrypt] {
+            let sealed = seal(b"key", b"secret message", kdf).unwrap();
+            assert_eq!(sealed.len(), b"secret message".len() + OVERHEAD);
+            assert_eq!(sealed[..HEADER_LEN], [ENVELOPE_VERSION, CIPHER_CHACHA20_POLY1305, kdf.id(), CODING_PLAIN]);
+            assert_eq!(open(b"key", &sealed).unwrap(), b"secret message");
+        }
+    }
+
+    #[test]
+    fn salt_makes_every_envelope_different() {
+        assert_ne!(seal(b"key", b"same", Kdf::Sha256).unwrap(), seal(b"key", b"same", Kdf::Sha256).unwrap());
+    }
+
+    #[test]
+    fn wrong_key_fails_authentication() {
+        let sealed = seal(b"key", b"secret", Kdf::Sha256).unwrap();
+        assert!(matches!(open(b"other key", &sealed), Err(ExtractError::NoPayload(_))));
+    }
+
+    #[test]
+    fn modified_envelope_fails_authentication() {
+        let sealed = seal(b"key", b"secret", Kdf::Sha256).unwrap();
+        for i in [HEADER_LEN, HEADER_LEN + PAYLOAD_SALT_LEN, sealed.len() - 1] {
+            let mut modified = sealed.clone();
+            modified[i] ^= 1;
+            assert!(matches!(open(b"key", &modified), Err(ExtractError::NoPayload(_))), "byte {} modified", i);
+        }
+        assert!(matches!(open(b"key", &sealed[..OVERHEAD - 1]), Err(ExtractError::NoPayload(_))));
+    }
+
+    #[test]
+    fn unknown_algorithms_are_refused() {
+        let sealed = seal(b"key", b"secret", Kdf::Sha256).unwrap();
+        for (i, id) in [(0, ENVELOPE_VERSION + 1), (1, 2), (2, 0xff), (3, 1)] {
+            let mut modified = sealed.clone();
+            modified[i] = id;
+            assert!(matches!(open(b"key", &modified), Err(ExtractError::Decode(_))), "header byte {} is {}", i, id);
+        }
+    }
+}
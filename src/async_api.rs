@@ -0,0 +1,52 @@
+//! Async variants of the bytes in / bytes out API for tokio servers. Decoding, embedding and encoding are CPU bound
+//! and run on the blocking thread pool, sources and sinks are read and written asynchronously.
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use zeroize::Zeroizing;
+use crate::{buffer, ExtractError};
+
+//run the CPU bound work on the blocking pool
+async fn blocking<T: Send + 'static, F: FnOnce() -> T + Send + 'static>(work: F) -> Result<T, String> {
+    tokio::task::spawn_blocking(work).await.map_err(|e| format!("Background task failed. {}", e))
+}
+
+/// Hide the message into the encoded image and return the stego image as PNG
+pub async fn embed_image(image: Vec<u8>, key: Vec<u8>, message: Vec<u8>) -> Result<Vec<u8>, String> {
+    let (key, message) = (Zeroizing::new(key), Zeroizing::new(message));
+    blocking(move || buffer::embed_image(&image, &key, &message)).await?
+}
+
+/// Extract the message from the encoded image
+pub async fn extract_image(image: Vec<u8>, key: Vec<u8>) -> Result<Vec<u8>, ExtractError> {
+    let key = Zeroizing::new(key);
+    blocking(move || buffer::extract_image(&image, &key)).await.map_err(ExtractError::Failed)?
+}
+
+/// Width, height and capacity in bytes of the encoded image
+pub async fn image_capacity(image: Vec<u8>) -> Result<(u32, u32, usize), String> {
+    blocking(move || buffer::image_capacity(&image)).await?
+}
+
+/// Read the encoded image and the message from the sources and write the stego PNG into the sink. Returns its size.
+pub async fn embed_stream<I, M, W>(image: &mut I, key: &[u8], message: &mut M, out: &mut W) -> Result<usize, String>
+where I: AsyncRead + Unpin, M: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    let mut image_data = Vec::new();
+    image.read_to_end(&mut image_data).await.map_err(|s| format!("Can't read the image. {}", s))?;
+    let mut msg = Zeroizing::new(Vec::new());
+    message.read_to_end(&mut msg).await.map_err(|s| format!("Can't read the message. {}", s))?;
+
+    let png = embed_image(image_data, key.to_vec(), msg.to_vec()).await?;
+    out.write_all(&png).await.and(out.flush().await).map_err(|s| s.to_string())?;
+    Ok(png.len())
+}
+
+/// Read the encoded image from the source and write the extracted message into the sink. Returns its size.
+pub async fn extract_stream<I, W>(image: &mut I, key: &[u8], out: &mut W) -> Result<usize, ExtractError>
+where I: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    let mut image_data = Vec::new();
+    image.read_to_end(&mut image_data).await.map_err(|s| ExtractError::Decode(format!("Can't read the image. {}", s)))?;
+
+    let msg = Zeroizing::new(extract_image(image_data, key.to_vec()).await?);
+    out.write_all(&msg).await.and(out.flush().await).map_err(|s| ExtractError::Failed(s.to_string()))?;
+    Ok(msg.len())
+}
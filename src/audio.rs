@@ -0,0 +1,127 @@
+//! 16-bit PCM WAV audio as the carrier. Each sample (of all channels interleaved) is one hidding spot in its LSB,
+//! the salt is in the first samples and the rest is shuffled by the key as the image spots are.
+
+use std::path::Path;
+use crate::*;
+
+/// Decoded samples and the format of the audio
+pub struct AudioCarrier {
+    /// Format of the WAV file, written back on save
+    pub spec: hound::WavSpec,
+    /// Samples of all channels interleaved
+    pub samples: Vec<i16>,
+}
+
+impl AudioCarrier {
+
+    /// Decode the WAV file, only 16-bit PCM is supported
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AudioCarrier, String> {
+        let mut reader = hound::WavReader::open(path).map_err(|s| s.to_string())?;
+        let spec = reader.spec();
+        if spec.sample_format != hound::SampleFormat::Int || spec.bits_per_sample != 16 {
+            return Err("Only 16-bit PCM WAV carriers are supported.".to_string());
+        }
+        let samples = reader.samples::<i16>().collect::<Result<Vec<i16>, _>>().map_err(|s| s.to_string())?;
+        Ok(AudioCarrier { spec, samples })
+    }
+
+    /// Encode the samples into the WAV file in the format of the cover
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let mut writer = hound::WavWriter::create(path, self.spec).map_err(|s| s.to_string())?;
+        for &s in &self.samples {
+            writer.write_sample(s).map_err(|s| s.to_string())?;
+        }
+        writer.finalize().map_err(|s| s.to_string())
+    }
+
+    /// Hidding spots after the salt in the key, salt and context dependent order
+    pub fn spots(&self, key: &[u8], salt: &[u8], context: &[u8]) -> Vec<u32> {
+        let mut prng_state = init_prng(key, salt, context);
+        shuffled_spots(self.spot_count(), RESERVED_SPOTS.min(self.spot_count()), &mut prng_state)
+    }
+
+    /// Capacity of the audio in message bytes
+    pub fn capacity(&self) -> usize {
+        message_capacity(self.spot_count().saturating_sub(RESERVED_SPOTS) as usize)
+    }
+}
+
+impl Carrier for AudioCarrier {
+    fn spot_count(&self) -> u32 {
+        self.samples.len().min(u32::MAX as usize) as u32
+    }
+
+    fn read_bit(&self, spot_idx: u32) -> u8 {
+        (self.samples[spot_idx as usize] & 1) as u8
+    }
+}
+
+impl CarrierMut for AudioCarrier {
+    fn write_bit(&mut self, spot_idx: u32, bit: u8) {
+        let s = &mut self.samples[spot_idx as usize];
+        *s = *s & !1 | bit as i16;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("stegegg-audio-{}-{}", std::process::id(), name))
+    }
+
+    //stereo tone with the noise in the low bits
+    fn write_cover(path: &Path, spec: hound::WavSpec) {
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for i in 0..8000i32 {
+            let tone = ((i as f32 * 0.05).sin() * 12000.0) as i32 + (i * 7919 % 13);
+            match (spec.sample_format, spec.bits_per_sample) {
+                (hound::SampleFormat::Float, _) => writer.write_sample(tone as f32 / 32768.0).unwrap(),
+                (_, 16) => writer.write_sample(tone as i16).unwrap(),
+                _ => writer.write_sample(tone << 8).unwrap(),
+            }
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn spec(bits_per_sample: u16, sample_format: hound::SampleFormat) -> hound::WavSpec {
+        hound::WavSpec { channels: 2, sample_rate: 44100, bits_per_sample, sample_format }
+    }
+
+    #[test]
+    fn message_round_trip_through_the_wav_file() {
+        let (cover, stego) = (temp_path("cover.wav"), temp_path("stego.wav"));
+        write_cover(&cover, spec(16, hound::SampleFormat::Int));
+        let msg = b"message hidden in the samples";
+
+        let mut audio = AudioCarrier::open(&cover).unwrap();
+        let original = audio.samples.clone();
+        let salt = random_salt().unwrap();
+        let indices = audio.spots(b"key", &salt, b"");
+        carrier::write_salt(&mut audio, &salt);
+        carrier::hide_message(&mut &msg[..], Some(msg.len()), &mut audio, &indices, TrailerParams::default()).unwrap();
+        audio.save(&stego).unwrap();
+
+        let audio = AudioCarrier::open(&stego).unwrap();
+        assert!(audio.samples.iter().zip(&original).all(|(a, b)| (a ^ b) & !1 == 0));
+        let salt = carrier::read_salt(&audio);
+        let indices = audio.spots(b"key", &salt, b"");
+        assert_eq!(carrier::extract_message(&audio, &indices, MAX_MSG_LEN).unwrap(), msg);
+        let indices = audio.spots(b"wrong key", &salt, b"");
+        assert!(matches!(carrier::extract_message(&audio, &indices, MAX_MSG_LEN), Err(ExtractError::NoPayload(_))));
+
+        std::fs::remove_file(cover).unwrap();
+        std::fs::remove_file(stego).unwrap();
+    }
+
+    #[test]
+    fn unsupported_format_is_refused() {
+        for (name, spec) in [("24.wav", spec(24, hound::SampleFormat::Int)), ("float.wav", spec(32, hound::SampleFormat::Float))] {
+            let path = temp_path(name);
+            write_cover(&path, spec);
+            assert_eq!(AudioCarrier::open(&path).err().as_deref(), Some("Only 16-bit PCM WAV carriers are supported."));
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Banded processing of the 8-bit RGB PNG images, which are decoded, modified and encoded row by row instead of being
+//! loaded whole. Spots are in the shuffled order of the whole image modes, or in the keyed Feistel order computed
+//! for each spot (--low-memory), which keeps only the message and one row in memory. The message has the salt, the
+//! header and the checksum trailer as in the other modes.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use crate::*;
+use crate::raw::FeistelPermutation;
+use crate::files::{write_atomic, write_vec_to_file};
+use zeroize::Zeroizing;
+
+/// Layout and spot order of the banded processing
+#[derive(Clone, Copy)]
+pub struct BandedLayout {
+    /// Layout of the spots
+    pub layout: SpotLayout,
+    /// Legacy images have no salt and use all spots for the data
+    pub legacy: bool,
+    /// Spots are ordered by the memoryless keyed permutation instead of the shuffled list of all spots (--low-memory)
+    pub memoryless: bool,
+}
+
+/// Bytes of the plain message extracted in one pass over the image
+pub const PASS_LEN: usize = 1 << 20;
+
+//key dependent order of the payload (header, message and trailer) bits
+enum SpotOrder {
+    //shuffled list of the spots, the order of the whole image modes. It takes 4 bytes per spot, the most memory
+    //of the banded processing.
+    Shuffled(Vec<u32>),
+    //spots after the first one ordered by the permutation computed for each spot
+    Keyed { first: u32, perm: FeistelPermutation },
+}
+
+impl SpotOrder {
+    fn new(banded: BandedLayout, spot_count: u32, key: &[u8], salt: &[u8], context: &[u8]) -> SpotOrder {
+        let first = if banded.legacy { 0 } else { RESERVED_SPOTS };
+        let mut prng_state = init_prng(key, salt, context);
+        if banded.memoryless {
+            SpotOrder::Keyed { first, perm: FeistelPermutation::new(spot_count - first, &mut prng_state) }
+        } else {
+            SpotOrder::Shuffled(shuffled_spots(spot_count, first, &mut prng_state))
+        }
+    }
+
+    //number of the payload spots
+    fn len(&self) -> usize {
+        match self {
+            SpotOrder::Shuffled(indices) => indices.len(),
+            SpotOrder::Keyed { perm, .. } => perm.len() as usize,
+        }
+    }
+
+    //spots of the payload bits in the range
+    fn spots(&self, bits: Range<usize>) -> Vec<u32> {
+        match self {
+            SpotOrder::Shuffled(indices) => indices[bits].to_vec(),
+            SpotOrder::Keyed { first, perm } => bits.map(|i| first + perm.apply(i as u32)).collect(),
+        }
+    }
+}
+
+//open PNG and read its header. Only 8-bit RGB non-interlaced images can be processed in bands.
+fn open_png(path: &Path) -> Result<png::Reader<BufReader<File>>, String> {
+    let f = File::open(path).map_err(|s| s.to_string())?;
+    let reader = png::Decoder::new(BufReader::new(f)).read_info().map_err(|s| s.to_string())?;
+
+    let info = reader.info();
+    if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight || info.interlaced {
+        return Err("Banded processing supports only non-interlaced 8-bit RGB PNG images.".to_string());
+    }
+    Ok(reader)
+}
+
+//next row of the image
+fn next_row(reader: &mut png::Reader<BufReader<File>>) -> Result<&[u8], String> {
+    match reader.next_row() {
+        Ok(Some(r)) => Ok(r.data()),
+        Ok(None) => Err("Unexpected end of image data.".to_string()),
+        Err(s) => Err(s.to_string()),
+    }
+}
+
+//majority vote of the spot samples in the row
+fn row_bit(layout: SpotLayout, row: &[u8], width: u32, spot: u32) -> u8 {
+    let (x, _, colors, _) = layout.position(spot, width);
+    let total = colors.len();
+    let ones = colors.filter(|c| row[x as usize * 3 + c] & 1 == 1).count();
+    (ones * 2 > total) as u8
+}
+
+//read bits from the given spots by streaming the image rows. Bits are returned packed (LSB first) in the order of spots.
+fn read_spots(path: &Path, layout: SpotLayout, spots: &[u32]) -> Result<Vec<u8>, String> {
+
+    let mut reader = open_png(path)?;
+    let width = reader.info().width;
+
+    //spot positions sorted by rows, so each row is visited once
+    let mut order: Vec<usize> = (0..spots.len()).collect();
+    order.sort_by_key(|&i| spots[i]);
+
+    let mut bytes = vec![0u8; spots.len().div_ceil(8)];
+    let mut next = 0;
+    let mut y = 0;
+    while next < order.len() {
+        let row = next_row(&mut reader)?;
+        while next < order.len() && layout.position(spots[order[next]], width).1 == y {
+            bytes[order[next] / 8] |= row_bit(layout, row, width, spots[order[next]]) << (order[next] % 8);
+            next += 1;
+        }
+        y += 1;
+    }
+    Ok(bytes)
+}
+
+//read the payload bits in the range. The keyed order is inverted for every spot of the image, so only the bytes
+//of the range are kept in memory.
+fn read_payload(path: &Path, layout: SpotLayout, order: &SpotOrder, bits: Range<usize>) -> Result<Vec<u8>, String> {
+    let (first, perm) = match order {
+        SpotOrder::Shuffled(indices) => return read_spots(path, layout, &indices[bits]),
+        SpotOrder::Keyed { first, perm } => (*first, perm),
+    };
+
+    let mut reader = open_png(path)?;
+    let (width, height) = (reader.info().width, reader.info().height);
+    let row_spots = layout.spots(width, 1)?;
+    let mut bytes = vec![0u8; bits.len().div_ceil(8)];
+    for y in 0..height {
+        let row = next_row(&mut reader)?;
+        for spot in (y * row_spots).max(first)..(y + 1) * row_spots {
+            let i = perm.invert(spot - first) as usize;
+            if bits.contains(&i) {
+                let n = i - bits.start;
+                bytes[n / 8] |= row_bit(layout, row, width, spot) << (n % 8);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+//append bits of the data (LSB first) for the given spots to the list of modifications
+fn push_bits(mods: &mut Vec<(u32, u8)>, data: &[u8], spots: &[u32]) {
+    for (i, b) in data.iter().enumerate() {
+        for n in 0..8 {
+            mods.push((spots[i * 8 + n], get_bit(*b, n as u8)));
+        }
+    }
+}
+
+/// Hide message into the PNG image processed row by row. The message is read whole, as its bits are scattered over all
+/// rows. The shuffled order keeps the list of all spots (4 bytes each) and the list of modified spots (8 bytes per
+/// payload bit) in memory, the keyed order only the payload.
+pub fn hide_banded<R: Read>(in_path: &Path, out_path: &Path, banded: BandedLayout, key: &[u8], context: &[u8], message: &mut R) -> Result<(), String> {
+
+    let layout = banded.layout;
+    let mut reader = open_png(in_path)?;
+    let (width, height) = (reader.info().width, reader.info().height);
+    let spot_count = layout.spots(width, height)?;
+    if !banded.legacy && spot_count < RESERVED_SPOTS {
+        return Err("Image is too small!".to_string());
+    }
+
+    let salt = if banded.legacy { Vec::new() } else { random_salt()? };
+    let order = SpotOrder::new(banded, spot_count, key, &salt, context);
+
+    let mut msg = Zeroizing::new(Vec::new());
+    message.read_to_end(&mut msg).map_err(|s| s.to_string())?;
+    let capacity = message_capacity(order.len());
+    if msg.len() > capacity {
+        return Err(too_large(msg.len(), capacity));
+    }
+
+    //header, message and the trailer with its checksum, the legacy spots don't depend on the key derivation
+    let params = TrailerParams { kdf: (!banded.legacy).then_some(Kdf::Sha256), ..TrailerParams::default() };
+    let mut payload = Zeroizing::new(vec![(msg.len() & 0xff) as u8, ((msg.len() >> 8) & 0xff) as u8, ((msg.len() >> 16) & 0xff) as u8]);
+    payload.extend_from_slice(&msg);
+    payload.extend_from_slice(&Trailer::new(params, crc32fast::hash(&msg)).to_bytes());
+    drop(msg);
+
+    //bit of each spot visited in raster order, None for the spots which keep their value
+    let salt_bits = salt.len() as u32 * 8;
+    let mut bit_of: Box<dyn FnMut(u32) -> Option<u8>> = match &order {
+        SpotOrder::Shuffled(indices) => {
+            //list of (spot, bit) modifications sorted in raster order
+            let salt_spots: Vec<u32> = (0..salt_bits).collect();
+            let mut mods = Vec::with_capacity((salt.len() + payload.len()) * 8);
+            push_bits(&mut mods, &salt, &salt_spots);
+            push_bits(&mut mods, &payload, indices);
+            mods.sort_unstable_by_key(|m| m.0);
+            let mut next = 0;
+            Box::new(move |spot| {
+                let bit = mods.get(next).filter(|m| m.0 == spot)?.1;
+                next += 1;
+                Some(bit)
+            })
+        },
+        SpotOrder::Keyed { first, perm } => {
+            let (first, salt, payload) = (*first, &salt, &payload);
+            Box::new(move |spot| {
+                if spot < first {
+                    return (spot < salt_bits).then(|| get_bit(salt[spot as usize / 8], (spot % 8) as u8));
+                }
+                let i = perm.invert(spot - first) as usize;
+                (i < payload.len() * 8).then(|| get_bit(payload[i / 8], (i % 8) as u8))
+            })
+        },
+    };
+
+    let row_spots = layout.spots(width, 1)?;
+    write_atomic(out_path, |tmp| {
+        let out = File::create(tmp).map_err(|s| s.to_string())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(out), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|s| s.to_string())?;
+        let mut stream = writer.stream_writer().map_err(|s| s.to_string())?;
+
+        //copy rows and modify spots in each of them
+        for y in 0..height {
+            let mut row = next_row(&mut reader)?.to_vec();
+            for spot in y * row_spots..(y + 1) * row_spots {
+                if let Some(bit) = bit_of(spot) {
+                    let (x, _, colors, _) = layout.position(spot, width);
+                    for c in colors {
+                        let i = x as usize * 3 + c;
+                        row[i] = row[i] & 0xfe | bit;
+                    }
+                }
+            }
+            stream.write_all(&row).map_err(|s| s.to_string())?;
+        }
+        stream.finish().map_err(|s| s.to_string())
+    })
+}
+
+/// Extract message from the PNG image processed row by row. Image is read in several passes (salt, header, trailer,
+/// message), the message is verified by the checksum in its trailer before it is renamed over the output. The shuffled
+/// order keeps the list of all spots (4 bytes each) in memory. Plain messages are written in passes of PASS_LEN bytes,
+/// each decoding the image again, the encrypted and compressed ones are read whole.
+pub fn extract_banded(in_path: &Path, out_path: &Path, banded: BandedLayout, key: &[u8], context: &[u8], max_len: usize) -> Result<usize, ExtractError> {
+    extract_in_passes(in_path, out_path, banded, key, context, max_len, PASS_LEN)
+}
+
+//extract_banded with the plain message written in passes of pass_len bytes
+fn extract_in_passes(in_path: &Path, out_path: &Path, banded: BandedLayout, key: &[u8], context: &[u8], max_len: usize, pass_len: usize) -> Result<usize, ExtractError> {
+
+    let layout = banded.layout;
+    let (width, height) = {
+        let reader = open_png(in_path)?;
+        (reader.info().width, reader.info().height)
+    };
+    let spot_count = layout.spots(width, height).map_err(ExtractError::Decode)?;
+    if !banded.legacy && spot_count < RESERVED_SPOTS {
+        return Err(ExtractError::Decode("Image is too small!".to_string()));
+    }
+
+    let salt = if banded.legacy {
+        Vec::new()
+    } else {
+        let salt_spots: Vec<u32> = (0..RESERVED_SPOTS).collect();
+        read_spots(in_path, layout, &salt_spots)?
+    };
+    let order = SpotOrder::new(banded, spot_count, key, &salt, context);
+    if order.len() < 3 * 8 {
+        return Err(ExtractError::Decode("Image is too small!".to_string()));
+    }
+
+    //try to recover message length and check if is possible to fit it into the image. (may not, when wrong password is used)
+    let header = read_spots(in_path, layout, &order.spots(0..3 * 8))?;
+    let msg_len = header[0] as usize | (header[1] as usize) << 8 | (header[2] as usize) << 16;
+    if (msg_len + 3) * 8 > order.len() {
+        return Err(ExtractError::NoPayload(NO_PAYLOAD.to_string()));
+    }
+    if msg_len > stored_limit(max_len) {
+        return Err(ExtractError::Failed(format!("Message has {}, which is above the extraction limit of {}.", human_size(msg_len), human_size(max_len))));
+    }
+
+    //header and the trailer (with the longest label) behind the message, read as a small carrier of their own
+    let trailer_bits = (3 + msg_len) * 8..order.len().min((3 + msg_len + TRAILER_LEN + 1 + MAX_LABEL_LEN) * 8);
+    let mut bytes = header;
+    bytes.extend(read_spots(in_path, layout, &order.spots(trailer_bits.start..trailer_bits.end / 8 * 8))?);
+    let bits = carrier::Bits(&bytes);
+    let trailer = match carrier::read_trailer(&bits, &(0..bits.spot_count()).collect::<Vec<u32>>(), 0) {
+        Ok(t) => Some(t),
+        //salted images must have the trailer, the legacy ones may be older than it
+        Err(ExtractError::NoPayload(_)) if banded.legacy => None,
+        Err(e) => return Err(e),
+    };
+    if !trailer.as_ref().is_some_and(|t| t.encrypted()) && msg_len > max_len {
+        return Err(ExtractError::Failed(format!("Message has {}, which is above the extraction limit of {}.", human_size(msg_len), human_size(max_len))));
+    }
+
+    let damaged = || "Message checksum doesn't match. The key is right, but the carrier was modified or damaged.".to_string();
+
+    //encrypted and compressed messages are unpacked whole
+    if trailer.as_ref().is_some_and(|t| t.encrypted() || t.codec().is_some()) {
+        let msg = Zeroizing::new(read_payload(in_path, layout, &order, 3 * 8..(3 + msg_len) * 8)?);
+        if let Some(t) = &trailer {
+            let mut hasher = crc32fast::Hasher::new();
+            hasher.update(&msg);
+            hasher.update(&t.label_bytes());
+            if hasher.finalize() != t.crc32 {
+                return Err(ExtractError::Decode(damaged()));
+            }
+        }
+        let msg = unpack_message(key, msg, trailer.as_ref(), max_len)?;
+        return write_vec_to_file(out_path, &msg).map_err(ExtractError::Failed);
+    }
+
+    //plain message is written in passes over the image, so at most pass_len bytes of it are in the memory. The output
+    //is renamed over the target only when the checksum of the whole message matches.
+    let mut mismatch = false;
+    write_atomic(out_path, |tmp| {
+        let mut w = BufWriter::new(File::create(tmp).map_err(|s| s.to_string())?);
+        let mut hasher = crc32fast::Hasher::new();
+        for start in (0..msg_len).step_by(pass_len) {
+            let end = msg_len.min(start + pass_len);
+            let part = Zeroizing::new(read_payload(in_path, layout, &order, (3 + start) * 8..(3 + end) * 8)?);
+            hasher.update(&part);
+            w.write_all(&part).map_err(|s| s.to_string())?;
+        }
+        if let Some(t) = &trailer {
+            hasher.update(&t.label_bytes());
+            if hasher.finalize() != t.crc32 {
+                mismatch = true;
+                return Err(damaged());
+            }
+        }
+        w.flush().map_err(|s| s.to_string())?;
+        Ok(msg_len)
+    }).map_err(|s| if mismatch { ExtractError::Decode(s) } else { ExtractError::Failed(s) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const SHUFFLED: BandedLayout = BandedLayout { layout: SpotLayout { redundant: false, depth: DEFAULT_DEPTH }, legacy: false, memoryless: false };
+    const KEYED: BandedLayout = BandedLayout { memoryless: true, ..SHUFFLED };
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("stegegg-banded-{}-{}", std::process::id(), name))
+    }
+
+    //noisy 8-bit RGB PNG cover
+    fn cover(name: &str) -> PathBuf {
+        let path = temp_path(name);
+        image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([(x * 7 + y) as u8, (x ^ y) as u8, (x * y) as u8])).save(&path).unwrap();
+        path
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len as u32).map(|i| (i * 31 + i / 7) as u8).collect()
+    }
+
+    #[test]
+    fn plain_message_is_extracted_in_several_passes() {
+        let (input, stego, out) = (cover("passes.png"), temp_path("passes-stego.png"), temp_path("passes.bin"));
+        let msg = message(700);
+        hide_banded(&input, &stego, SHUFFLED, b"key", b"", &mut &msg[..]).unwrap();
+
+        //700 bytes in passes of 256 bytes, the last pass is shorter
+        assert_eq!(extract_in_passes(&stego, &out, SHUFFLED, b"key", b"", MAX_MSG_LEN, 256).unwrap(), msg.len());
+        assert_eq!(std::fs::read(&out).unwrap(), msg);
+        assert_eq!(extract_banded(&stego, &out, SHUFFLED, b"key", b"", MAX_MSG_LEN).unwrap(), msg.len());
+        assert_eq!(std::fs::read(&out).unwrap(), msg);
+        for path in [input, stego, out] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn damaged_message_isnt_written() {
+        let (input, stego, out) = (cover("damaged.png"), temp_path("damaged-stego.png"), temp_path("damaged.bin"));
+        let msg = message(500);
+        hide_banded(&input, &stego, SHUFFLED, b"key", b"", &mut &msg[..]).unwrap();
+
+        //flip the bit of the 100th message byte, the header and the trailer stay valid
+        let mut img = image::open(&stego).unwrap().into_rgb8();
+        let salt = read_spots(&stego, SHUFFLED.layout, &(0..RESERVED_SPOTS).collect::<Vec<u32>>()).unwrap();
+        let order = SpotOrder::new(SHUFFLED, SHUFFLED.layout.spots(64, 48).unwrap(), b"key", &salt, b"");
+        let spot = order.spots((3 + 100) * 8..(3 + 100) * 8 + 1)[0];
+        let bit = SHUFFLED.layout.read_bit(&img, spot) ^ 1;
+        SHUFFLED.layout.write_bit(&mut img, spot, bit);
+        img.save(&stego).unwrap();
+
+        assert!(matches!(extract_in_passes(&stego, &out, SHUFFLED, b"key", b"", MAX_MSG_LEN, 128), Err(ExtractError::Decode(_))));
+        assert!(!out.exists());
+        assert!(matches!(extract_banded(&stego, &out, SHUFFLED, b"other key", b"", MAX_MSG_LEN), Err(ExtractError::NoPayload(_))));
+        for path in [input, stego] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn keyed_order_round_trip() {
+        let (input, stego, out) = (cover("keyed.png"), temp_path("keyed-stego.png"), temp_path("keyed.bin"));
+        let msg = message(300);
+        for banded in [KEYED, BandedLayout { layout: SpotLayout { redundant: true, depth: DEFAULT_DEPTH }, ..KEYED }] {
+            hide_banded(&input, &stego, banded, b"key", b"context", &mut &msg[..]).unwrap();
+            assert_eq!(extract_in_passes(&stego, &out, banded, b"key", b"context", MAX_MSG_LEN, 100).unwrap(), msg.len());
+            assert_eq!(std::fs::read(&out).unwrap(), msg);
+        }
+
+        //keyed order is not the shuffled one, and it depends on the key and the context
+        hide_banded(&input, &stego, KEYED, b"key", b"context", &mut &msg[..]).unwrap();
+        for (banded, key, context) in [(SHUFFLED, b"key", b"context"), (KEYED, b"kez", b"context"), (KEYED, b"key", b"contexu")] {
+            assert!(matches!(extract_banded(&stego, &out, banded, key, context, MAX_MSG_LEN), Err(ExtractError::NoPayload(_))));
+        }
+        for path in [input, stego, out] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
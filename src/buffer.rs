@@ -0,0 +1,169 @@
+//! Bytes in / bytes out API for the servers and bindings. Images are encoded files (PNG, BMP, ...) or decoded
+//! RGB images, the message is hidden by StegoEngine in the format of `stegegg` without options (encrypted by the
+//! key, plain with an empty key) and the stego image is returned as PNG. Extraction reads the raw engine images (Kdf::Sha256) too. The custom selectors are used
+//! by the raw engine.
+
+use std::io::Cursor;
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use crate::*;
+
+/// Allocation caps for the untrusted input, so garbage or malicious images can't make huge allocations
+#[derive(Clone, Copy)]
+pub struct Limits {
+    /// Maximal memory allocated by the image decoder
+    pub max_decode_alloc: u64,
+    /// Maximal size of the extracted message
+    pub max_message: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { max_decode_alloc: 512 << 20, max_message: MAX_MSG_LEN }
+    }
+}
+
+//decode the encoded image into rgb8
+fn decode_image(image: &[u8]) -> Result<RgbImage, String> {
+    decode_image_limited(image, Limits::default())
+}
+
+//decode the encoded image into rgb8 with the decoder allocations capped
+fn decode_image_limited(image: &[u8], limits: Limits) -> Result<RgbImage, String> {
+    let mut reader = image::io::Reader::new(Cursor::new(image)).with_guessed_format().map_err(|s| format!("Can't decode the image. {}", s))?;
+    let mut decoder_limits = image::io::Limits::default();
+    decoder_limits.max_alloc = Some(limits.max_decode_alloc);
+    reader.limits(decoder_limits);
+    let img = reader.decode().map_err(|s| format!("Can't decode the image. {}", s))?;
+    Ok(img.into_rgb8())
+}
+
+//error of the raw engine as the extraction error
+fn extract_error(e: raw::RawError) -> ExtractError {
+    match e {
+        raw::RawError::NoPayload => ExtractError::NoPayload(e.to_string()),
+        raw::RawError::TooSmall | raw::RawError::Checksum | raw::RawError::Unsupported(_) => ExtractError::Decode(e.to_string()),
+        e => ExtractError::Failed(e.to_string()),
+    }
+}
+
+/// Hide the message into the decoded image
+pub fn embed_rgb(img: &mut RgbImage, key: &[u8], message: &[u8]) -> Result<(), String> {
+    StegoEngine::new(key).embed(img, message).map_err(|e| e.to_string())
+}
+
+/// Hide the message into the decoded image with the spots ordered by the selector
+pub fn embed_rgb_with<S: raw::SpotSelector + ?Sized>(selector: &S, img: &mut RgbImage, key: &[u8], message: &[u8]) -> Result<(), String> {
+    let salt: [u8; SALT_LEN] = random_salt()?.try_into().unwrap();
+    let mut scratch = vec![0; raw::scratch_len(img.len())];
+    raw::embed_raw_with(selector, img, key, &salt, message, &mut scratch).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Extract the message from the decoded image
+pub fn extract_rgb(img: &RgbImage, key: &[u8]) -> Result<Vec<u8>, ExtractError> {
+    extract_default(img, key, MAX_MSG_LEN)
+}
+
+//extract the message of the default format and, when there is none, of the raw engine
+fn extract_default(img: &RgbImage, key: &[u8], max_message: usize) -> Result<Vec<u8>, ExtractError> {
+    match StegoEngine::new(key).extract_limited(img, max_message) {
+        Err(ExtractError::NoPayload(s)) => match extract_rgb_limited(&raw::XoshiroSelector, img, key, max_message) {
+            Err(ExtractError::NoPayload(_)) => Err(ExtractError::NoPayload(s)),
+            result => result,
+        },
+        result => result,
+    }
+}
+
+/// Extract the message from the decoded image with the spots ordered by the selector
+pub fn extract_rgb_with<S: raw::SpotSelector + ?Sized>(selector: &S, img: &RgbImage, key: &[u8]) -> Result<Vec<u8>, ExtractError> {
+    extract_rgb_limited(selector, img, key, MAX_MSG_LEN)
+}
+
+//extract the message, which must not be larger than max_message, from the decoded image. The header and the trailer
+//are validated before the message is allocated, by its length.
+fn extract_rgb_limited<S: raw::SpotSelector + ?Sized>(selector: &S, img: &RgbImage, key: &[u8], max_message: usize) -> Result<Vec<u8>, ExtractError> {
+    let mut scratch = vec![0; raw::scratch_len(img.len())];
+    let header = raw::read_header_raw_with(selector, img, key, &mut scratch).map_err(extract_error)?;
+    if header.len > max_message {
+        return Err(ExtractError::Failed(format!("Message has {}, which is above the extraction limit of {}.", human_size(header.len), human_size(max_message))));
+    }
+    let mut out = vec![0; header.len];
+    raw::read_message_raw(img, &header, &scratch, &mut out).map_err(extract_error)?;
+    Ok(out)
+}
+
+/// Hide the message into the encoded image and return the stego image as PNG
+pub fn embed_image(image: &[u8], key: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+    let mut img = decode_image(image)?;
+    embed_rgb(&mut img, key, message)?;
+
+    let mut png = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(img).write_to(&mut png, ImageOutputFormat::Png).map_err(|s| s.to_string())?;
+    Ok(png.into_inner())
+}
+
+/// Extract the message from the encoded image
+pub fn extract_image(image: &[u8], key: &[u8]) -> Result<Vec<u8>, ExtractError> {
+    extract_image_limited(image, key, Limits::default())
+}
+
+/// Extract the message from the encoded image with the decoder allocations and the message size capped
+pub fn extract_image_limited(image: &[u8], key: &[u8], limits: Limits) -> Result<Vec<u8>, ExtractError> {
+    let img = decode_image_limited(image, limits).map_err(ExtractError::Decode)?;
+    extract_default(&img, key, limits.max_message)
+}
+
+/// Width, height and capacity in bytes of the encoded image, less the overhead of the encryption
+pub fn image_capacity(image: &[u8]) -> Result<(u32, u32, usize), String> {
+    let img = decode_image(image)?;
+    Ok((img.width(), img.height(), capacity(img.width(), img.height()).saturating_sub(aead::OVERHEAD)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //encoded PNG of the noisy image
+    fn cover(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 7 + y) as u8, (x * 13 + y * 3) as u8, (x + y * 11) as u8]));
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(img).write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn encoded_image_round_trip() {
+        let stego = embed_image(&cover(48, 48), b"key", b"message in the buffer").unwrap();
+        assert_eq!(extract_image(&stego, b"key").unwrap(), b"message in the buffer");
+        assert!(matches!(extract_image(&stego, b"other"), Err(ExtractError::NoPayload(_))));
+        assert!(matches!(extract_image(b"not an image", b"key"), Err(ExtractError::Decode(_))));
+    }
+
+    #[test]
+    fn extraction_limits_are_applied() {
+        let msg = vec![3u8; 100];
+        let stego = embed_image(&cover(48, 48), b"key", &msg).unwrap();
+        let limits = |max_message| Limits { max_message, ..Limits::default() };
+        assert_eq!(extract_image_limited(&stego, b"key", limits(100)).unwrap(), msg);
+        assert!(matches!(extract_image_limited(&stego, b"key", limits(99)), Err(ExtractError::Failed(_))));
+        let tight = Limits { max_decode_alloc: 16, ..Limits::default() };
+        assert!(matches!(extract_image_limited(&stego, b"key", tight), Err(ExtractError::Decode(_))));
+
+        //raw engine images are read by the fallback, with the same limit
+        let mut img = decode_image(&cover(48, 48)).unwrap();
+        embed_rgb_with(&raw::XoshiroSelector, &mut img, b"key", &msg).unwrap();
+        assert_eq!(extract_rgb(&img, b"key").unwrap(), msg);
+        assert!(matches!(extract_default(&img, b"key", 99), Err(ExtractError::Failed(_))));
+    }
+
+    #[test]
+    fn capacity_leaves_out_the_encryption_overhead() {
+        let (width, height, capacity) = image_capacity(&cover(48, 48)).unwrap();
+        assert_eq!((width, height), (48, 48));
+        assert_eq!(capacity, StegoEngine::new(b"key").capacity(&RgbImage::new(48, 48)));
+        let mut img = decode_image(&cover(48, 48)).unwrap();
+        embed_rgb(&mut img, b"key", &vec![1; capacity]).unwrap();
+        assert!(embed_rgb(&mut img, b"key", &vec![1; capacity + 1]).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! Carriers of the hidden bits. A carrier is a sequence of hidding spots, each holding one bit, so the salt,
+//! the header, the message and the trailer are stored the same way in image pixels and in audio samples.
+//! The first RESERVED_SPOTS spots hold the salt in their natural order, the rest is shuffled by the key.
+
+use std::io::{ErrorKind, Read, Write};
+use std::ops::{Deref, DerefMut};
+use image::RgbImage;
+use zeroize::Zeroizing;
+use crate::*;
+
+/// Read access to the hidding spots
+pub trait Carrier {
+    /// Number of all hidding spots
+    fn spot_count(&self) -> u32;
+    /// Read bit from the spot
+    fn read_bit(&self, spot_idx: u32) -> u8;
+}
+
+/// Write access to the hidding spots
+pub trait CarrierMut: Carrier {
+    /// Write bit into the spot
+    fn write_bit(&mut self, spot_idx: u32, bit: u8);
+}
+
+/// Image with the layout of its hidding spots, by reference (&RgbImage or &mut RgbImage)
+pub struct ImageCarrier<I> {
+    /// Image holding the spots
+    pub image: I,
+    /// Layout of the spots in the image
+    pub layout: SpotLayout,
+}
+
+impl<I: Deref<Target = RgbImage>> Carrier for ImageCarrier<I> {
+    //images with more spots than u32 holds are refused before their carrier is used
+    fn spot_count(&self) -> u32 {
+        self.layout.spot_count(&self.image).unwrap_or(0)
+    }
+
+    fn read_bit(&self, spot_idx: u32) -> u8 {
+        self.layout.read_bit(&self.image, spot_idx)
+    }
+}
+
+impl<I: DerefMut<Target = RgbImage>> CarrierMut for ImageCarrier<I> {
+    fn write_bit(&mut self, spot_idx: u32, bit: u8) {
+        self.layout.write_bit(&mut self.image, spot_idx, bit)
+    }
+}
+
+/// Bytes as a read-only carrier, bit n of the byte i is the spot i * 8 + n. The streamed carriers read the header
+/// and the trailer into the bytes first.
+pub struct Bits<'a>(pub &'a [u8]);
+
+impl Carrier for Bits<'_> {
+    fn spot_count(&self) -> u32 {
+        (self.0.len() * 8) as u32
+    }
+
+    fn read_bit(&self, spot_idx: u32) -> u8 {
+        get_bit(self.0[spot_idx as usize / 8], (spot_idx % 8) as u8)
+    }
+}
+
+/// Read random salt from the first spots
+pub fn read_salt<C: Carrier + ?Sized>(carrier: &C) -> Vec<u8> {
+    read_bytes(carrier, &(0..RESERVED_SPOTS).collect::<Vec<u32>>(), 0, SALT_LEN)
+}
+
+/// Write salt into the first spots
+pub fn write_salt<C: CarrierMut + ?Sized>(carrier: &mut C, salt: &[u8]) {
+    for (i, b) in salt.iter().enumerate() {
+        for n in 0..8 {
+            carrier.write_bit((i * 8 + n) as u32, get_bit(*b, n as u8));
+        }
+    }
+}
+
+/// Read len bytes starting at byte offset from the given hidding spots
+pub fn read_bytes<C: Carrier + ?Sized>(carrier: &C, indices: &[u32], offset: usize, len: usize) -> Vec<u8> {
+    let mut data = vec![0; len];
+    for (i, b) in data.iter_mut().enumerate() {
+        for n in 0..8 {
+            *b |= carrier.read_bit(indices[(offset + i) * 8 + n]) << n;
+        }
+    }
+    data
+}
+
+/// Write the data into the given hidding spots. Returns number of used hidding spots.
+pub fn write_bytes<C: CarrierMut + ?Sized>(data: &[u8], carrier: &mut C, indices: &[u32]) -> Result<usize, EmbedError> {
+
+    let hidding_spots = indices.len();
+    if data.len() * 8 > hidding_spots {
+        return Err(EmbedError::TooLarge { len: data.len(), capacity: hidding_spots / 8 });
+    }
+
+    //hide each bit starting with LSB bit
+    let mut iidx: usize = 0;
+    for b in data {
+        for n in 0..8 {
+            carrier.write_bit(indices[iidx], get_bit(*b, n));
+            iidx += 1;
+        }
+    }
+    Ok(iidx)
+}
+
+/// Read header and return message length, which must not be above the limit
+pub fn read_header_limited<C: Carrier + ?Sized>(carrier: &C, indices: &[u32], max_len: usize) -> Result<usize, ExtractError> {
+
+    if indices.len() < 3 * 8 {
+        return Err(ExtractError::Decode("Image is too small!".to_string()));
+    }
+
+    //try to recover message length and check if is possible to fit it into the carrier. (may not, when wrong password is used)
+    let header = read_bytes(carrier, &indices[..3 * 8], 0, 3);
+    let msg_len = header[0] as usize | (header[1] as usize) << 8 | (header[2] as usize) << 16;
+    if (msg_len + 3) * 8 > indices.len() {
+        return Err(ExtractError::NoPayload(NO_PAYLOAD.to_string()));
+    }
+    if msg_len > max_len {
+        return Err(ExtractError::Failed(format!("Message has {}, which is above the extraction limit of {}.", human_size(msg_len), human_size(max_len))));
+    }
+    Ok(msg_len)
+}
+
+/// Read the trailer behind the message of msg_len bytes. Salted carriers always have it, so a missing magic
+/// means the spots were shuffled by another key (or there is no message at all).
+pub fn read_trailer<C: Carrier + ?Sized>(carrier: &C, indices: &[u32], msg_len: usize) -> Result<Trailer, ExtractError> {
+    let no_payload = || ExtractError::NoPayload(NO_PAYLOAD.to_string());
+    let fits = |len: usize| (3 + msg_len + len) * 8 <= indices.len();
+    if !fits(4) {
+        return Err(no_payload());
+    }
+    let t = read_bytes(carrier, &indices[3 * 8..], msg_len, 4);
+    if t[..3] != TRAILER_MAGIC {
+        return Err(no_payload());
+    }
+    let (version, flags) = (t[3] & 0x0f, t[3] & 0xf0);
+    if version > FORMAT_VERSION {
+        return Err(newer_version(version, flags));
+    }
+
+    //version 1 trailer has no Kdf id
+    let len = if version < 2 { TRAILER_LEN - 1 } else { TRAILER_LEN };
+    if !fits(len) {
+        return Err(no_payload());
+    }
+    let t = read_bytes(carrier, &indices[3 * 8..], msg_len, len);
+    let kdf = match t[4] {
+        _ if version < 2 => None,
+        0 => None,
+        id => Some(Kdf::from_id(id).ok_or_else(|| newer_version(version, flags))?),
+    };
+
+    //label follows the trailer, a damaged one fails on the checksum
+    let mut label = None;
+    if flags & FLAG_LABEL != 0 {
+        if !fits(len + 1) {
+            return Err(no_payload());
+        }
+        let label_len = read_bytes(carrier, &indices[3 * 8..], msg_len + len, 1)[0] as usize;
+        if !fits(len + 1 + label_len) {
+            return Err(no_payload());
+        }
+        label = Some(String::from_utf8_lossy(&read_bytes(carrier, &indices[3 * 8..], msg_len + len + 1, label_len)).into_owned());
+    }
+    Ok(Trailer { version, flags, kdf, crc32: u32::from_le_bytes(t[len - 4..].try_into().unwrap()), label })
+}
+
+fn newer_version(version: u8, flags: u8) -> ExtractError {
+    ExtractError::Decode(format!("Message has format version {} (flags {:#04x}), this stegegg supports up to {}. Please update stegegg.", version, flags, FORMAT_VERSION))
+}
+
+/// Check the message of msg_len bytes against the checksum in its trailer before it is extracted
+pub fn verify_payload<C: Carrier + ?Sized>(carrier: &C, indices: &[u32], msg_len: usize) -> Result<Trailer, ExtractError> {
+    let trailer = read_trailer(carrier, indices, msg_len)?;
+
+    const CHUNK: usize = 1 << 16;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut offset = 0;
+    while offset < msg_len {
+        let len = CHUNK.min(msg_len - offset);
+        hasher.update(&Zeroizing::new(read_bytes(carrier, &indices[3 * 8..], offset, len)));
+        offset += len;
+    }
+    hasher.update(&trailer.label_bytes());
+    if hasher.finalize() != trailer.crc32 {
+        return Err(ExtractError::Decode("Message checksum doesn't match. The key is right, but the carrier was modified or damaged.".to_string()));
+    }
+    Ok(trailer)
+}
+
+/// Check the message of the legacy (unsalted) carrier, which may be written before the trailer existed.
+/// Returns None for the carriers without the trailer, those can't tell the wrong key.
+pub fn verify_legacy_payload<C: Carrier + ?Sized>(carrier: &C, indices: &[u32], msg_len: usize) -> Result<Option<Trailer>, ExtractError> {
+    match verify_payload(carrier, indices, msg_len) {
+        Ok(t) => Ok(Some(t)),
+        Err(ExtractError::NoPayload(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Extract the message, verified by its checksum
+pub fn extract_message<C: Carrier + ?Sized>(carrier: &C, indices: &[u32], max_len: usize) -> Result<Vec<u8>, ExtractError> {
+    let msg_len = read_header_limited(carrier, indices, max_len)?;
+    verify_payload(carrier, indices, msg_len)?;
+    Ok(read_bytes(carrier, &indices[3 * 8..], 0, msg_len))
+}
+
+/// Extract the message verified by its checksum and write it into the writer in chunks. Returns number of written bytes.
+pub fn extract_message_to_writer<C: Carrier + ?Sized, W: Write>(carrier: &C, indices: &[u32], writer: &mut W) -> Result<usize, ExtractError> {
+    let msg_len = read_header_limited(carrier, indices, MAX_MSG_LEN)?;
+    verify_payload(carrier, indices, msg_len)?;
+    write_message(carrier, indices, msg_len, writer)?;
+    Ok(msg_len)
+}
+
+/// Read the window of len bytes at the offset of the message of msg_len bytes, clipped at its end. Only the spots of
+/// the window are read, the checksum of the whole message isn't checked.
+pub fn read_range<C: Carrier + ?Sized>(carrier: &C, indices: &[u32], msg_len: usize, offset: usize, len: usize) -> Result<Vec<u8>, ExtractError> {
+    if offset > msg_len {
+        return Err(ExtractError::Failed(format!("Range offset {} is behind the end of the {} bytes message.", offset, msg_len)));
+    }
+    Ok(read_bytes(carrier, &indices[3 * 8..], offset, len.min(msg_len - offset)))
+}
+
+/// Write the message of msg_len bytes (header already read) into the writer in chunks, without any check
+pub fn write_message<C: Carrier + ?Sized, W: Write>(carrier: &C, indices: &[u32], msg_len: usize, writer: &mut W) -> std::io::Result<()> {
+    const CHUNK: usize = 1 << 16;
+    let mut offset = 0;
+    while offset < msg_len {
+        let len = CHUNK.min(msg_len - offset);
+        let data = Zeroizing::new(read_bytes(carrier, &indices[3 * 8..], offset, len));
+        writer.write_all(&data)?;
+        offset += len;
+    }
+    writer.flush()
+}
+
+/// Hide message read from the reader, followed by the trailer with the params (flags, key derivation and label), and
+/// write the header with its length at the end. Returns number of used hidding spots.
+pub fn hide_message<R: Read + ?Sized, C: CarrierMut + ?Sized>(reader: &mut R, len: Option<usize>, carrier: &mut C, indices: &[u32], params: TrailerParams) -> Result<usize, EmbedError> {
+
+    if let Some(label) = &params.label {
+        check_label(label).map_err(EmbedError::Failed)?;
+    }
+    let label = params.label_bytes();
+    let max_len = message_capacity(indices.len()).saturating_sub(label.len());
+    if let Some(len) = len {
+        if len > max_len {
+            return Err(EmbedError::TooLarge { len, capacity: max_len });
+        }
+    }
+
+    //message is stored right behind the header
+    let body = &indices[indices.len().min(3 * 8)..];
+    let mut buf = Zeroizing::new(vec![0; 1 << 16]);
+    let mut msg_len = 0;
+    let mut hasher = crc32fast::Hasher::new();
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(EmbedError::Io(e)),
+        };
+
+        if msg_len + n > max_len {
+            return Err(EmbedError::Overflow { capacity: max_len });
+        }
+        write_bytes(&buf[..n], carrier, &body[msg_len * 8..])?;
+        hasher.update(&buf[..n]);
+        msg_len += n;
+    }
+
+    //trailer with the format version and the checksum of the message and the label behind the message
+    hasher.update(&label);
+    let trailer = Trailer::new(params, hasher.finalize()).to_bytes();
+    write_bytes(&trailer, carrier, &body[msg_len * 8..])?;
+
+    //create 3 byte for message length in little endian format. This limit max message length to 16Mbytes.
+    let header = [(msg_len & 0xff) as u8, ((msg_len >> 8) & 0xff) as u8, ((msg_len >> 16) & 0xff) as u8];
+    write_bytes(&header, carrier, indices)?;
+    Ok((msg_len + 3 + trailer.len()) * 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //carrier of the 32x32 image and its shuffled spots after the salt
+    fn carrier(key: &[u8]) -> (ImageCarrier<Box<RgbImage>>, Vec<u32>) {
+        let carrier = ImageCarrier { image: Box::new(RgbImage::new(32, 32)), layout: SpotLayout { redundant: false, depth: DEFAULT_DEPTH } };
+        let indices = shuffled_spots(carrier.spot_count(), RESERVED_SPOTS, &mut init_prng(key, &[], &[]));
+        (carrier, indices)
+    }
+
+    fn hide(carrier: &mut ImageCarrier<Box<RgbImage>>, indices: &[u32], msg: &[u8], params: TrailerParams) -> usize {
+        hide_message(&mut &msg[..], Some(msg.len()), carrier, indices, params).unwrap()
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let (mut c, indices) = carrier(b"key");
+        let used = hide(&mut c, &indices, b"hello", TrailerParams { flags: 0, kdf: Some(Kdf::Scrypt), label: Some("note".to_string()) });
+        assert_eq!(used, (3 + 5 + TRAILER_LEN + 1 + 4) * 8);
+        assert_eq!(extract_message(&c, &indices, MAX_MSG_LEN).unwrap(), b"hello");
+
+        let trailer = read_trailer(&c, &indices, 5).unwrap();
+        assert_eq!((trailer.version, trailer.flags, trailer.kdf, trailer.label.as_deref()), (FORMAT_VERSION, FLAG_LABEL, Some(Kdf::Scrypt), Some("note")));
+    }
+
+    //carrier recording the spots which were read
+    struct Recording<'a>(&'a ImageCarrier<Box<RgbImage>>, std::cell::RefCell<Vec<u32>>);
+
+    impl Carrier for Recording<'_> {
+        fn spot_count(&self) -> u32 {
+            self.0.spot_count()
+        }
+
+        fn read_bit(&self, spot_idx: u32) -> u8 {
+            self.1.borrow_mut().push(spot_idx);
+            self.0.read_bit(spot_idx)
+        }
+    }
+
+    #[test]
+    fn range_reads_only_the_window() {
+        let (mut c, indices) = carrier(b"key");
+        let msg: Vec<u8> = (0..200u32).map(|i| i as u8).collect();
+        hide(&mut c, &indices, &msg, TrailerParams::default());
+
+        let recording = Recording(&c, Default::default());
+        let msg_len = read_header_limited(&recording, &indices, MAX_MSG_LEN).unwrap();
+        read_trailer(&recording, &indices, msg_len).unwrap();
+        assert_eq!(read_range(&recording, &indices, msg_len, 50, 10).unwrap(), &msg[50..60]);
+
+        let read = recording.1.into_inner();
+        let body = &indices[3 * 8..];
+        for (i, spots) in body[..msg_len * 8].chunks(8).enumerate() {
+            assert_eq!(spots.iter().any(|s| read.contains(s)), (50..60).contains(&i), "byte {}", i);
+        }
+        assert!(matches!(read_range(&c, &indices, msg_len, 201, 1), Err(ExtractError::Failed(_))));
+    }
+
+    #[test]
+    fn damaged_message_fails_checksum() {
+        let (mut c, indices) = carrier(b"key");
+        hide(&mut c, &indices, b"hello", TrailerParams::default());
+        let spot = indices[3 * 8 + 10];
+        c.write_bit(spot, c.read_bit(spot) ^ 1);
+        assert!(matches!(verify_payload(&c, &indices, 5), Err(ExtractError::Decode(_))));
+    }
+
+    #[test]
+    fn damaged_label_fails_checksum() {
+        let (mut c, indices) = carrier(b"key");
+        hide(&mut c, &indices, b"hello", TrailerParams { label: Some("note".to_string()), ..Default::default() });
+        let spot = indices[(3 + 5 + TRAILER_LEN + 1) * 8];
+        c.write_bit(spot, c.read_bit(spot) ^ 1);
+        assert!(matches!(verify_payload(&c, &indices, 5), Err(ExtractError::Decode(_))));
+    }
+
+    #[test]
+    fn wrong_key_has_no_payload() {
+        let (mut c, indices) = carrier(b"key");
+        hide(&mut c, &indices, b"hello", TrailerParams::default());
+        let (_, other) = carrier(b"other key");
+        assert!(matches!(extract_message(&c, &other, MAX_MSG_LEN), Err(ExtractError::NoPayload(_))));
+    }
+
+    #[test]
+    fn newer_trailer_is_refused() {
+        let (mut c, indices) = carrier(b"key");
+        hide(&mut c, &indices, b"hello", TrailerParams::default());
+        write_bytes(&[FORMAT_VERSION + 1], &mut c, &indices[(3 + 5 + 3) * 8..]).unwrap();
+        assert!(matches!(read_trailer(&c, &indices, 5), Err(ExtractError::Decode(_))));
+
+        //newer version is refused whatever its flags
+        write_bytes(&[0x0f | FLAG_ENCRYPTED | FLAG_LABEL], &mut c, &indices[(3 + 5 + 3) * 8..]).unwrap();
+        assert!(matches!(read_trailer(&c, &indices, 5), Err(ExtractError::Decode(_))));
+    }
+
+    #[test]
+    fn too_large_message_is_refused() {
+        let (mut c, indices) = carrier(b"key");
+        let msg = vec![0; message_capacity(indices.len()) + 1];
+        assert!(matches!(hide_message(&mut &msg[..], Some(msg.len()), &mut c, &indices, TrailerParams::default()), Err(EmbedError::TooLarge { .. })));
+        assert!(matches!(hide_message(&mut &msg[..], None, &mut c, &indices, TrailerParams::default()), Err(EmbedError::Overflow { .. })));
+    }
+
+    #[test]
+    fn header_above_limit_is_refused() {
+        let (mut c, indices) = carrier(b"key");
+        hide(&mut c, &indices, b"hello", TrailerParams::default());
+        assert!(matches!(read_header_limited(&c, &indices, 4), Err(ExtractError::Failed(_))));
+        assert_eq!(read_header_limited(&c, &indices, 5).unwrap(), 5);
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::Read;
+use std::path::Path;
+use image::ImageFormat;
+use crate::*;
+use crate::files::write_vec_to_file;
+use zeroize::Zeroizing;
+
+//Ancillary chunk mode. The message is stored in private ancillary PNG chunks instead of the pixels, so the image
+//is not changed at all. Chunks are easy to find, the data is only masked with the key. First chunk starts
+//with the salt, the masked data starts with the 3 bytes message length.
+
+//chunk type: ancillary, private, safe to copy
+const CHUNK_TYPE: &[u8; 4] = b"stEg";
+
+//maximal size of one chunk
+const CHUNK_LEN: usize = 1 << 20;
+
+//PNG chunk type and data
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+//split PNG file into chunks
+fn parse_chunks(png: &[u8]) -> Result<Vec<Chunk<'_>>, String> {
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err("Chunk mode requires PNG image.".to_string());
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+        if pos + 12 + len > png.len() {
+            return Err("PNG chunk is truncated.".to_string());
+        }
+        chunks.push((png[pos + 4..pos + 8].try_into().unwrap(), &png[pos + 8..pos + 8 + len]));
+        pos += 12 + len;
+    }
+    Ok(chunks)
+}
+
+//append PNG chunk with its length and CRC
+fn push_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+/// Hide message into the chunks of the PNG image. PNG covers are copied byte for byte, other formats are converted to PNG.
+pub fn hide_chunk(in_path: &Path, out_path: &Path, key: &[u8], context: &[u8], message: &mut dyn Read) -> Result<(), String> {
+
+    let mut msg = Zeroizing::new(Vec::new());
+    message.read_to_end(&mut msg).map_err(|s| s.to_string())?;
+    if msg.len() > MAX_MSG_LEN {
+        return Err(too_large(msg.len(), MAX_MSG_LEN));
+    }
+
+    let mut cover = std::fs::read(in_path).map_err(|s| s.to_string())?;
+    if !cover.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let img = image::load_from_memory(&cover).map_err(|s| s.to_string())?;
+        let mut png = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut png, ImageFormat::Png).map_err(|s| s.to_string())?;
+        cover = png.into_inner();
+    }
+
+    let salt = random_salt()?;
+    let mut data = Vec::with_capacity(msg.len() + 3);
+    data.extend_from_slice(&(msg.len() as u32).to_le_bytes()[..3]);
+    data.extend_from_slice(&msg);
+    mask_data(key, &salt, context, &mut data);
+    let mut payload = salt;
+    payload.extend_from_slice(&data);
+
+    //copy all chunks of the cover, except the old hidden ones, and put the new ones in front of IEND
+    let mut out = Vec::from(&b"\x89PNG\r\n\x1a\n"[..]);
+    for (kind, data) in parse_chunks(&cover)? {
+        if &kind == CHUNK_TYPE {
+            continue;
+        }
+        if &kind == b"IEND" {
+            for c in payload.chunks(CHUNK_LEN) {
+                push_chunk(&mut out, CHUNK_TYPE, c);
+            }
+        }
+        push_chunk(&mut out, &kind, data);
+    }
+    write_vec_to_file(out_path, &out).map(|_| ()).map_err(|s| format!("Error accessing the file '{}'. {}", out_path.display(), s))
+}
+
+/// Extract message from the chunks of the PNG image
+pub fn extract_chunk(in_path: &Path, key: &[u8], context: &[u8]) -> Result<Vec<u8>, ExtractError> {
+
+    let png = std::fs::read(in_path).map_err(|s| s.to_string())?;
+    let payload: Vec<u8> = parse_chunks(&png).map_err(ExtractError::Decode)?.iter().filter(|(kind, _)| kind == CHUNK_TYPE).flat_map(|(_, data)| data.iter().copied()).collect();
+    if payload.len() < SALT_LEN + 3 {
+        return Err(ExtractError::NoPayload("No hidden chunks found in the image.".to_string()));
+    }
+
+    let (salt, masked) = payload.split_at(SALT_LEN);
+    let mut data = masked.to_vec();
+    mask_data(key, salt, context, &mut data);
+
+    let msg_len = data[0] as usize | (data[1] as usize) << 8 | (data[2] as usize) << 16;
+    if msg_len + 3 != data.len() {
+        return Err(ExtractError::NoPayload(NO_PAYLOAD.to_string()));
+    }
+    data.drain(..3);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("stegegg-chunk-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn message_round_trip_keeps_the_pixels() {
+        let (cover, stego) = (temp_path("cover.png"), temp_path("stego.png"));
+        let image = RgbImage::from_fn(20, 10, |x, y| image::Rgb([x as u8, y as u8, (x * y) as u8]));
+        image.save(&cover).unwrap();
+
+        //message above the chunk size is stored in several chunks
+        let msg: Vec<u8> = (0..CHUNK_LEN as u32 + 1000).map(|i| (i % 251) as u8).collect();
+        hide_chunk(&cover, &stego, b"key", b"", &mut &msg[..]).unwrap();
+        assert_eq!(extract_chunk(&stego, b"key", b"").unwrap(), msg);
+        assert_eq!(image::open(&stego).unwrap().into_rgb8(), image);
+        assert!(matches!(extract_chunk(&stego, b"wrong key", b""), Err(ExtractError::NoPayload(_))));
+
+        //hiding again replaces the old chunks
+        hide_chunk(&stego, &stego, b"key", b"", &mut &b"short"[..]).unwrap();
+        assert_eq!(extract_chunk(&stego, b"key", b"").unwrap(), b"short");
+
+        std::fs::remove_file(cover).unwrap();
+        std::fs::remove_file(stego).unwrap();
+    }
+
+    #[test]
+    fn png_without_the_chunk_has_no_payload() {
+        let path = temp_path("plain.png");
+        RgbImage::new(8, 8).save(&path).unwrap();
+        assert!(matches!(extract_chunk(&path, b"key", b""), Err(ExtractError::NoPayload(_))));
+        std::fs::write(&path, b"not a png").unwrap();
+        assert!(matches!(extract_chunk(&path, b"key", b""), Err(ExtractError::Decode(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
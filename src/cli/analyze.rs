@@ -0,0 +1,69 @@
+use getopts::Matches;
+use std::path::Path;
+use image::{GrayImage, ImageFormat, Luma, Rgb, RgbImage};
+use super::{diag, os_arg};
+use stegegg::steganalysis;
+use stegegg::files::{open_image, write_atomic};
+
+//LSB plane of the image stretched to the full range, each channel separately. Natural images show noise
+//following the image content, embedded areas look like uniform random noise.
+fn lsb_plane(image: &RgbImage) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let p = image.get_pixel(x, y);
+        Rgb([(p[0] & 1) * 255, (p[1] & 1) * 255, (p[2] & 1) * 255])
+    })
+}
+
+//embedding cost of each pixel as the grayscale PNG, bright in the flat areas where the LSB changes are noticed
+//and dark in the textured ones. The message is spread uniformly, so the map shows where it is exposed.
+pub fn write_cost_map(image: &RgbImage, path: &Path) -> Result<f32, String> {
+    let costs = steganalysis::embedding_costs(image);
+    let map = GrayImage::from_fn(image.width(), image.height(), |x, y| Luma([(costs[(y * image.width() + x) as usize] * 255.0).round() as u8]));
+    write_atomic(path, |tmp| map.save_with_format(tmp, ImageFormat::Png).map_err(|s| s.to_string()))?;
+    Ok(costs.iter().sum::<f32>() / costs.len().max(1) as f32)
+}
+
+//verbal likelihood of the LSB payload by the composite score
+fn likelihood(d: &steganalysis::Detectability) -> &'static str {
+    if d.suspicious() {
+        "likely carries an LSB payload"
+    } else if d.score > 0.1 {
+        "may carry a small LSB payload"
+    } else {
+        "no sign of an LSB payload"
+    }
+}
+
+//'analyze' command. Runs the steganalysis detectors on the image and prints how likely it carries a message
+//hidden by the LSB replacement, optionally writes the LSB plane and the cost map for the visual inspection.
+pub fn analyze(matches: &Matches, path: Option<&String>) {
+
+    let path = match path {
+        Some(p) => os_arg(p),
+        None => { println!("Usage: stegegg analyze [--lsb-plane <output.png>] [--cost-map <output.png>] <image>"); return; },
+    };
+
+    let result = open_image(&path).and_then(|img| {
+        let image = img.to_rgb8();
+        let d = steganalysis::detectability(&image);
+        println!("Image:       {} ({}x{})", path.display(), image.width(), image.height());
+        println!("Chi-square:  {:.3} (probability the pairs of values are equalized)", d.chi_square);
+        println!("RS analysis: {:.3} (estimated embedding rate)", d.rs);
+        println!("Sample pair: {:.3} (estimated embedding rate)", d.spa);
+        println!("Score:       {:.3}, {}", d.score, likelihood(&d));
+
+        if let Some(plane) = matches.opt_str("lsb-plane").map(|p| os_arg(&p)) {
+            write_atomic(&plane, |tmp| lsb_plane(&image).save_with_format(tmp, ImageFormat::Png).map_err(|s| s.to_string()))?;
+            println!("LSB plane written to '{}'.", plane.display());
+        }
+        if let Some(map) = matches.opt_str("cost-map").map(|p| os_arg(&p)) {
+            let mean = write_cost_map(&image, &map)?;
+            println!("Cost map written to '{}', mean embedding cost {:.3}.", map.display(), mean);
+        }
+        Ok(())
+    });
+
+    if let Err(s) = result {
+        diag::error(s);
+    }
+}
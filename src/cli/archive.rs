@@ -0,0 +1,424 @@
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use getopts::Matches;
+use stegegg::*;
+use super::report::collect_files;
+use super::os_arg;
+use stegegg::files::{read_file_to_vec, write_vec_to_file};
+use super::pixel::read_message;
+
+//Archive payload of --archive. Message files (and the files in the message directories) are packed with their
+//names, sizes, permissions and modification times, so the extraction restores them into the output directory.
+//Archive is: magic (4 bytes), version (1), file count (4), and for each file: name length (2), UTF-8 name with
+//'/' separators, unix mode (4), modification time in unix seconds (8, since version 2), size (4), data.
+//Numbers are little endian.
+
+//magic at the start of the archive
+const ARCHIVE_MAGIC: [u8; 4] = *b"sEgA";
+const ARCHIVE_VERSION: u8 = 2;
+
+//permissions of the file, default ones on the systems without unix modes
+fn file_mode(path: &Path) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::metadata(path).map(|m| m.permissions().mode() & 0o777).unwrap_or(0o644)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        0o644
+    }
+}
+
+//restore the permissions of the unpacked file, only the owner write bit is kept on the other systems
+fn set_mode(path: &Path, mode: u32) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777)).map_err(|s| s.to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, mode);
+        Ok(())
+    }
+}
+
+//modification time of the file in unix seconds, 0 when the system doesn't record it
+fn file_mtime(path: &Path) -> u64 {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs())
+}
+
+//restore the modification time of the unpacked file
+fn set_mtime(path: &Path, mtime: u64) -> Result<(), String> {
+    let time = UNIX_EPOCH + Duration::from_secs(mtime);
+    std::fs::File::options().write(true).open(path).and_then(|f| f.set_modified(time)).map_err(|s| s.to_string())
+}
+
+//name of the file in the archive, relative to the message directory or the bare file name
+fn archive_name(path: &Path, base: &Path) -> Result<String, String> {
+    let relative = path.strip_prefix(base).map_err(|_| format!("Can't name the file '{}' in the archive.", path.display()))?;
+    let parts: Option<Vec<&str>> = relative.components().map(|c| match c {
+        Component::Normal(n) => n.to_str(),
+        _ => None,
+    }).collect();
+    match parts {
+        Some(p) if !p.is_empty() => Ok(p.join("/")),
+        _ => Err(format!("File name '{}' can't be stored in the archive, it must be valid UTF-8.", relative.display())),
+    }
+}
+
+//pack the -M files and directories into the archive
+pub fn pack(matches: &Matches) -> Result<Vec<u8>, String> {
+
+    let mut files: Vec<(PathBuf, String)> = Vec::new();
+    for arg in matches.opt_strs("M") {
+        let path = os_arg(&arg);
+        if path.is_dir() {
+            let mut dir_files = Vec::new();
+            collect_files(&path, &mut dir_files)?;
+            let base = path.parent().unwrap_or(Path::new(""));
+            for f in dir_files {
+                let name = archive_name(&f, base)?;
+                files.push((f, name));
+            }
+        } else {
+            let name = archive_name(&path, path.parent().unwrap_or(Path::new("")))?;
+            files.push((path, name));
+        }
+    }
+    if files.len() > u32::MAX as usize {
+        return Err("Too many files for the archive.".to_string());
+    }
+
+    let mut archive = ARCHIVE_MAGIC.to_vec();
+    archive.push(ARCHIVE_VERSION);
+    archive.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    for (path, name) in &files {
+        if files.iter().filter(|(_, n)| n == name).count() > 1 {
+            return Err(format!("File name '{}' is given twice for the archive.", name));
+        }
+        let data = read_file_to_vec(path).map_err(|s| format!("Can't read message file '{}'. {}", path.display(), s))?;
+        if name.len() > u16::MAX as usize || data.len() > MAX_MSG_LEN {
+            return Err(format!("File '{}' is too large for the archive.", path.display()));
+        }
+        archive.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(&file_mode(path).to_le_bytes());
+        archive.extend_from_slice(&file_mtime(path).to_le_bytes());
+        archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&data);
+    }
+    println!("{} files packed into the archive of {}.", files.len(), human_size(archive.len()));
+    Ok(archive)
+}
+
+//reader of the archive fields, fails on the truncated archive
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < n {
+            return Err("Archive is truncated.".to_string());
+        }
+        let (field, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(field)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+//file in the archive. Version 1 archives have no modification times.
+struct Entry<'a> {
+    name: &'a str,
+    mode: u32,
+    mtime: Option<u64>,
+    data: &'a [u8],
+}
+
+//files of the archive, the whole archive is checked before anything is unpacked
+fn entries(data: &[u8]) -> Result<Vec<Entry<'_>>, String> {
+
+    let mut fields = Fields(data);
+    if fields.take(4).ok() != Some(&ARCHIVE_MAGIC[..]) {
+        return Err("Message isn't an archive, it was hidden without --archive.".to_string());
+    }
+    let version = fields.take(1)?[0];
+    if version > ARCHIVE_VERSION {
+        return Err(format!("Archive has version {}, this stegegg supports up to {}. Please update stegegg.", version, ARCHIVE_VERSION));
+    }
+
+    //every entry takes at least its fixed fields, so a damaged count can't allocate much
+    let count = fields.u32()? as usize;
+    let mut entries = Vec::with_capacity(count.min(data.len() / 10));
+    for _ in 0..count {
+        let name_len = u16::from_le_bytes(fields.take(2)?.try_into().unwrap()) as usize;
+        let name = std::str::from_utf8(fields.take(name_len)?).map_err(|_| "Archive has a file name which isn't UTF-8.".to_string())?;
+        let mode = fields.u32()?;
+        let mtime = if version >= 2 { Some(fields.u64()?) } else { None };
+        let size = fields.u32()? as usize;
+        entries.push(Entry { name, mode, mtime, data: fields.take(size)? });
+    }
+    Ok(entries)
+}
+
+//UTC date and time of the unix seconds as "YYYY-MM-DD HH:MM"
+fn format_time(secs: u64) -> String {
+    //days to the civil date, the proleptic Gregorian calendar counted in 400 year eras from 0000-03-01
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, secs % 86400 / 3600, secs % 3600 / 60)
+}
+
+//path of the unpacked file in the output directory. Names leaving the directory are refused.
+fn unpack_path(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    let mut path = dir.to_path_buf();
+    for part in name.split('/') {
+        let mut components = Path::new(part).components();
+        if part.contains('\\') || !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+            return Err(format!("Archive has invalid file name '{}'.", name));
+        }
+        path.push(part);
+    }
+    Ok(path)
+}
+
+//how the archive is unpacked into the output directory
+#[derive(Default)]
+pub struct UnpackOptions {
+    //overwrite the existing files (never through the symbolic links)
+    pub force: bool,
+    //unpack all files right into the output directory, without their directories
+    pub flatten: bool,
+    //unpack only these files and directories, all when empty
+    pub entries: Vec<String>,
+}
+
+//refuse the path which is a symbolic link or lies under one inside the output directory, the link could point
+//anywhere outside it
+fn check_symlinks(dir: &Path, path: &Path) -> Result<(), String> {
+    let mut current = dir.to_path_buf();
+    for part in path.strip_prefix(dir).map_err(|_| format!("File '{}' is outside of the output directory.", path.display()))?.components() {
+        current.push(part);
+        if std::fs::symlink_metadata(&current).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(format!("Refusing to write through the symbolic link '{}'.", current.display()));
+        }
+    }
+    Ok(())
+}
+
+//entry is the selected file or lies in the selected directory
+fn selected(entry: &Entry, name: &str) -> bool {
+    let name = name.trim_end_matches('/');
+    entry.name == name || entry.name.strip_prefix(name).is_some_and(|rest| rest.starts_with('/'))
+}
+
+//unpack the archive into the output directory, only the selected files and directories when some are given.
+//All paths are checked before the first file is written. Returns number of unpacked files.
+pub fn unpack(data: &[u8], dir: &Path, options: &UnpackOptions) -> Result<usize, String> {
+
+    let mut entries = entries(data)?;
+    let names = &options.entries;
+    if let Some(name) = names.iter().find(|n| !entries.iter().any(|e| selected(e, n))) {
+        return Err(format!("Archive has no file or directory '{}'.", name));
+    }
+    if !names.is_empty() {
+        entries.retain(|e| names.iter().any(|n| selected(e, n)));
+    }
+
+    let mut paths: Vec<PathBuf> = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let mut path = unpack_path(dir, entry.name)?;
+        if options.flatten {
+            path = dir.join(path.file_name().unwrap());
+        }
+        if let Some(i) = paths.iter().position(|p| *p == path) {
+            return Err(format!("Files '{}' and '{}' are both unpacked into '{}'.", entries[i].name, entry.name, path.display()));
+        }
+        check_symlinks(dir, &path)?;
+        if path.exists() && !options.force {
+            return Err(format!("File '{}' already exists. Use --force to overwrite.", path.display()));
+        }
+        paths.push(path);
+    }
+
+    for (entry, path) in entries.iter().zip(paths) {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|s| format!("Can't create directory '{}'. {}", parent.display(), s))?;
+        }
+        //overwritten file is replaced, so its read-only permissions don't block it
+        if path.is_file() {
+            std::fs::remove_file(&path).map_err(|s| format!("Can't replace the file '{}'. {}", path.display(), s))?;
+        }
+        write_vec_to_file(&path, entry.data).map_err(|s| format!("Error accessing the file '{}'. {}", path.display(), s))?;
+        set_mode(&path, entry.mode)?;
+        if let Some(mtime) = entry.mtime {
+            set_mtime(&path, mtime).map_err(|s| format!("Can't set the modification time of '{}'. {}", path.display(), s))?;
+        }
+        println!("{} ({})", path.display(), human_size(entry.data.len()));
+    }
+    Ok(entries.len())
+}
+
+//'list' command. Reads the archive hidden in the carrier into the memory and prints its files without writing
+//anything.
+pub fn list(matches: &Matches, in_filename: Option<&String>, key: &[u8]) -> Result<(), ExtractError> {
+
+    let in_filename = match in_filename {
+        Some(f) => os_arg(f),
+        None => { println!("Usage: stegegg list -k <key> [extraction options] <carrier>"); return Ok(()); },
+    };
+    let msg = read_message(matches, &in_filename, key)?;
+    let entries = entries(&msg)?;
+    for entry in &entries {
+        let mtime = entry.mtime.map_or_else(|| "-".repeat(16), format_time);
+        println!("{:04o}  {:>10}  {}  {}", entry.mode & 0o7777, human_size(entry.data.len()), mtime, entry.name);
+    }
+    let total: usize = entries.iter().map(|e| e.data.len()).sum();
+    println!("{} files, {} in total.", entries.len(), human_size(total));
+    Ok(())
+}
+
+//extract the archive from the carrier into the memory and unpack it into the output directory
+pub fn extract(matches: &Matches, in_filename: &Path, dir: &Path, key: &[u8]) -> Result<(), ExtractError> {
+    let msg = read_message(matches, in_filename, key)?;
+    let options = UnpackOptions { force: matches.opt_present("force"), flatten: matches.opt_present("flatten"), entries: matches.opt_strs("entry") };
+    let n = unpack(&msg, dir, &options)?;
+    println!("{} files unpacked into '{}'.", n, dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //archive of the current version with the named files
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut archive = ARCHIVE_MAGIC.to_vec();
+        archive.push(ARCHIVE_VERSION);
+        archive.extend_from_slice(&(files.len() as u32).to_le_bytes());
+        for (name, data) in files {
+            archive.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            archive.extend_from_slice(name.as_bytes());
+            archive.extend_from_slice(&0o644u32.to_le_bytes());
+            archive.extend_from_slice(&1_700_000_000u64.to_le_bytes());
+            archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            archive.extend_from_slice(data);
+        }
+        archive
+    }
+
+    //empty directory for the unpacked files
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("stegegg-archive-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn names_stay_in_the_directory() {
+        let dir = Path::new("out");
+        assert_eq!(unpack_path(dir, "a.txt").unwrap(), dir.join("a.txt"));
+        assert_eq!(unpack_path(dir, "docs/a.txt").unwrap(), dir.join("docs").join("a.txt"));
+        for name in ["", "../a.txt", "docs/../../a.txt", "/etc/passwd", "docs//a.txt", "./a.txt", "docs/.", "..", "docs\\a.txt", "..\\a.txt"] {
+            assert!(unpack_path(dir, name).is_err(), "'{}' accepted", name);
+        }
+    }
+
+    #[test]
+    fn archive_names_are_relative() {
+        let base = Path::new("/home/user");
+        assert_eq!(archive_name(Path::new("/home/user/docs/a.txt"), base).unwrap(), "docs/a.txt");
+        assert!(archive_name(Path::new("/tmp/a.txt"), base).is_err());
+        assert!(archive_name(base, base).is_err());
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let dir = temp_dir("round-trip");
+        let data = archive(&[("a.txt", b"first"), ("docs/b.txt", b"second")]);
+        let names: Vec<&str> = entries(&data).unwrap().iter().map(|e| e.name).collect();
+        assert_eq!(names, ["a.txt", "docs/b.txt"]);
+
+        assert_eq!(unpack(&data, &dir, &UnpackOptions::default()).unwrap(), 2);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"first");
+        assert_eq!(std::fs::read(dir.join("docs/b.txt")).unwrap(), b"second");
+
+        //existing files are kept without force
+        assert!(unpack(&data, &dir, &UnpackOptions::default()).is_err());
+        assert_eq!(unpack(&data, &dir, &UnpackOptions { force: true, ..Default::default() }).unwrap(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn escaping_name_writes_nothing() {
+        let dir = temp_dir("escape");
+        let data = archive(&[("a.txt", b"first"), ("../escaped.txt", b"second")]);
+        assert!(unpack(&data, &dir, &UnpackOptions::default()).is_err());
+        assert!(!dir.join("a.txt").exists());
+        assert!(!dir.parent().unwrap().join("escaped.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symbolic_links_are_not_followed() {
+        let (dir, outside) = (temp_dir("symlink"), temp_dir("symlink-target"));
+        std::os::unix::fs::symlink(&outside, dir.join("docs")).unwrap();
+        let data = archive(&[("docs/a.txt", b"first")]);
+        assert!(unpack(&data, &dir, &UnpackOptions { force: true, ..Default::default() }).is_err());
+        assert!(!outside.join("a.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[test]
+    fn flattened_names_must_differ() {
+        let dir = temp_dir("flatten");
+        let data = archive(&[("one/a.txt", b"first"), ("two/a.txt", b"second")]);
+        assert!(unpack(&data, &dir, &UnpackOptions { flatten: true, ..Default::default() }).is_err());
+        let options = UnpackOptions { flatten: true, entries: vec!["two".to_string()], ..Default::default() };
+        assert_eq!(unpack(&data, &dir, &options).unwrap(), 1);
+        assert_eq!(std::fs::read(dir.join("a.txt")).unwrap(), b"second");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_archive_is_refused() {
+        let data = archive(&[("a.txt", b"first")]);
+        assert!(entries(&data[..data.len() - 1]).is_err());
+        assert!(entries(b"not an archive").is_err());
+
+        let mut newer = data.clone();
+        newer[4] = ARCHIVE_VERSION + 1;
+        assert!(entries(&newer).is_err());
+
+        let mut count = data.clone();
+        count[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(entries(&count).is_err());
+    }
+
+    #[test]
+    fn time_is_formatted_in_utc() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(1_700_000_000), "2023-11-14 22:13");
+        assert_eq!(format_time(951_782_400), "2000-02-29 00:00");
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::Cursor;
+use getopts::{Matches, Options};
+use image::{imageops, ImageFormat, RgbImage};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use stegegg::*;
+use super::{diag, extract_any_kdf, key_context, os_arg, probe};
+use stegegg::files::open_image;
+use super::pixel::{prepare_spots, read_message};
+
+//apply one transformation given as "name:parameter" to the image
+fn apply_op(image: &RgbImage, op: &str) -> Result<RgbImage, String> {
+
+    let (name, param) = op.split_once(':').unwrap_or((op, ""));
+    let value = |default: f64| -> Result<f64, String> {
+        if param.is_empty() { Ok(default) } else { param.parse::<f64>().map_err(|_| format!("Invalid parameter of '{}'.", op)) }
+    };
+    let (width, height) = image.dimensions();
+
+    match name {
+        //scale the image and resample it back to the original size
+        "resize" => {
+            let f = value(0.9)?;
+            if f <= 0.0 {
+                return Err(format!("Invalid parameter of '{}'.", op));
+            }
+            let scaled = imageops::resize(image, ((width as f64 * f) as u32).max(1), ((height as f64 * f) as u32).max(1), FilterType::Triangle);
+            Ok(imageops::resize(&scaled, width, height, FilterType::Triangle))
+        },
+
+        //lossy JPEG recompression with the given quality
+        "jpeg" => {
+            let mut buf = Vec::new();
+            JpegEncoder::new_with_quality(&mut buf, value(85.0)?.clamp(1.0, 100.0) as u8).encode_image(image).map_err(|s| s.to_string())?;
+            image::load(Cursor::new(buf), ImageFormat::Jpeg).map(|i| i.to_rgb8()).map_err(|s| s.to_string())
+        },
+
+        //cut the given number of pixels from each edge
+        "crop" => {
+            let px = value(10.0)? as u32;
+            if px * 2 >= width || px * 2 >= height {
+                return Err(format!("Crop '{}' removes the whole image.", op));
+            }
+            Ok(imageops::crop_imm(image, px, px, width - px * 2, height - px * 2).to_image())
+        },
+
+        //add uniform noise of the given amplitude to every sample
+        "noise" => {
+            let amplitude = value(1.0)? as i32;
+            let mut seed = [0; 32];
+            getrandom::fill(&mut seed).map_err(|s| s.to_string())?;
+            let mut prng_state = init_prng(&seed, &[], &[]);
+            let mut noisy = image.clone();
+            for p in noisy.pixels_mut() {
+                for c in 0..3 {
+                    let n = (xoshiro256pp(&mut prng_state) % (amplitude as u64 * 2 + 1)) as i32 - amplitude;
+                    p[c] = (p[c] as i32 + n).clamp(0, 255) as u8;
+                }
+            }
+            Ok(noisy)
+        },
+
+        //rotation by the multiple of 90 degrees clockwise
+        "rotate" => match value(90.0)? as i32 {
+            90 => Ok(imageops::rotate90(image)),
+            180 => Ok(imageops::rotate180(image)),
+            270 => Ok(imageops::rotate270(image)),
+            _ => Err(format!("Rotation '{}' must be 90, 180 or 270 degrees.", op)),
+        },
+
+        //nearest neighbor upscaling by the integer factor, as the screenshots of the zoomed image
+        "zoom" => {
+            let f = value(2.0)? as u32;
+            if f == 0 {
+                return Err(format!("Invalid parameter of '{}'.", op));
+            }
+            Ok(imageops::resize(image, width * f, height * f, FilterType::Nearest))
+        },
+
+        //gaussian blur with the given sigma
+        "blur" => Ok(imageops::blur(image, value(0.5)? as f32)),
+
+        //lossless PNG re-encoding
+        "png" => {
+            let mut buf = Vec::new();
+            image.write_to(&mut Cursor::new(&mut buf), ImageFormat::Png).map_err(|s| s.to_string())?;
+            image::load(Cursor::new(buf), ImageFormat::Png).map(|i| i.to_rgb8()).map_err(|s| s.to_string())
+        },
+
+        _ => Err(format!("Unknown operation '{}'. Available operations: resize, jpeg, crop, noise, rotate, zoom, blur, png.", name)),
+    }
+}
+
+//ratio of different bits between the two byte slices
+fn bit_error_rate(a: &[u8], b: &[u8]) -> f64 {
+    if a.is_empty() {
+        return 0.0;
+    }
+    let errors: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+    errors as f64 / (a.len() * 8) as f64
+}
+
+//raw bytes of the message stored in the spots of the image, by the positions the key and the image dimensions give.
+//Tiled images have their own spots, so there is nothing to compare the bits of.
+fn stored_bytes(matches: &Matches, image: &RgbImage, key: &[u8], context: &[u8], len: usize) -> Option<Vec<u8>> {
+    if matches.opt_present("tiled") {
+        return None;
+    }
+    let (layout, _, indices) = prepare_spots(matches, image, key, context, true).ok()?;
+    (indices.len() >= (len + 3) * 8).then(|| extract_bytes(image, layout, &indices[3 * 8..], 0, len))
+}
+
+//apply transformations to the stego image and report whether the payload survives each of them (and all combined)
+pub fn attack(opts: &Options, matches: &Matches, path: Option<&String>, key: &[u8], ops: Option<String>) {
+
+    let path = match path {
+        Some(p) => os_arg(p),
+        None => { println!("Input file not specified."); return; },
+    };
+    let ops: Vec<String> = ops.unwrap_or("resize:0.9,jpeg:85,crop:10,noise:1,png".to_string()).split(',').map(|s| s.trim().to_string()).collect();
+
+    //the attacked images are extracted the same way as by -x, so the layout is probed and the older key derivation
+    //tried on the original image, and its options are used for all of them
+    let result = if matches.opt_present("auto") {
+        probe::probe_options(opts, matches, &path, key, false)
+    } else {
+        Ok(matches.clone())
+    };
+    let result = result.and_then(|m| extract_any_kdf(opts, &m, key, |m, key| Ok((m.clone(), read_message(m, &path, key)?))));
+    let (matches, reference) = match result {
+        Ok(r) => r,
+        Err(e) => { diag::error(format!("Can't extract payload from the original image. {}", e)); return; },
+    };
+    let result = key_context(&matches, &path).and_then(|context| {
+        let image = open_image(&path)?.to_rgb8();
+        let stored = prepare_spots(&matches, &image, key, &context, true).ok()
+            .and_then(|(layout, _, indices)| read_header(&image, layout, &indices).ok())
+            .and_then(|len| stored_bytes(&matches, &image, key, &context, len));
+        Ok((context, image, stored))
+    });
+    let (context, image, stored) = match result {
+        Ok(r) => r,
+        Err(s) => { diag::error(s); return; },
+    };
+
+    //the attacked image keeps the file name of the original, which --salt-from-name mixes into the key
+    let dir = std::env::temp_dir().join(format!("stegegg-attack-{}", std::process::id()));
+    if let Err(s) = std::fs::create_dir_all(&dir) {
+        diag::error(format!("Can't create the directory '{}'. {}", dir.display(), s));
+        return;
+    }
+    let attacked_path = dir.join(path.file_name().unwrap_or("attacked.png".as_ref()));
+
+    println!("Payload of {} bytes extracted from the original image.\n", reference.len());
+    println!("{:<24}  {:>9}  {:>9}  {:>10}  note", "operation", "extracted", "identical", "bit errors");
+
+    //each operation alone and then all of them combined
+    let mut runs: Vec<Vec<String>> = ops.iter().map(|o| vec![o.clone()]).collect();
+    if ops.len() > 1 {
+        runs.push(ops.clone());
+    }
+
+    let mut survived = 0;
+    for run in &runs {
+        let name = if run.len() > 1 { "combined".to_string() } else { run[0].clone() };
+        let attacked = run.iter().try_fold(image.clone(), |img, op| apply_op(&img, op))
+            .and_then(|a| a.save_with_format(&attacked_path, ImageFormat::Png).map(|_| a).map_err(|s| s.to_string()));
+        let attacked = match attacked {
+            Ok(a) => a,
+            Err(s) => { println!("{:<24}  {}", name, s); continue; },
+        };
+
+        let extracted = read_message(&matches, &attacked_path, key);
+
+        //bits can be compared only where the spots stay at the same positions, cropped or resized images have others
+        let same_spots = attacked.dimensions() == image.dimensions();
+        let bytes = stored.as_ref().filter(|_| same_spots).and_then(|s| stored_bytes(&matches, &attacked, key, &context, s.len()).map(|b| (s, b)));
+        let ber = match &bytes {
+            Some((s, b)) => format!("{:>9.2}%", bit_error_rate(s, b) * 100.0),
+            None => format!("{:>10}", "-"),
+        };
+
+        let note = match &extracted {
+            Err(e) => e.to_string().lines().next().unwrap_or_default().to_string(),
+            Ok(_) if same_spots && !matches.opt_present("tiled") => {
+                let low = prepare_spots(&matches, &attacked, key, &context, true).ok().and_then(|(layout, _, idx)| {
+                    layout.redundant.then(|| byte_confidence(&attacked, layout, &idx, bytes.as_ref().map_or(0, |(s, _)| s.len())).iter().filter(|&&c| c < 1.0).count())
+                });
+                low.map(|l| format!("{} low-confidence bytes", l)).unwrap_or_default()
+            },
+            Ok(_) => String::new(),
+        };
+
+        let identical = extracted.as_ref().is_ok_and(|m| **m == *reference);
+        survived += identical as usize;
+        println!("{:<24}  {:>9}  {:>9}  {}  {}", name, if extracted.is_ok() { "yes" } else { "no" }, if identical { "yes" } else { "no" }, ber, note);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+    println!("\nPayload survived {} of {} attacks.", survived, runs.len());
+}
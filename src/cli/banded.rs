@@ -0,0 +1,32 @@
+use std::path::Path;
+use getopts::Matches;
+use stegegg::*;
+use stegegg::banded::{extract_banded, hide_banded, BandedLayout};
+use super::i18n::tr;
+use super::manifest::{self, PayloadDigest};
+use super::options::max_extract_size;
+use super::{decrypt_output, key_context, message_source};
+
+//hide or extract the message of the image processed in row bands (--banded, --low-memory)
+pub fn process_banded(matches: &Matches, in_filename: &Path, out_filename: &Path, key: &[u8]) -> Result<(), ExtractError> {
+    let banded = BandedLayout {
+        layout: SpotLayout { redundant: matches.opt_present("redundant"), depth: DEFAULT_DEPTH },
+        legacy: matches.opt_present("legacy"),
+        memoryless: matches.opt_present("low-memory"),
+    };
+    let carrier = if matches.opt_present("x") { in_filename } else { out_filename };
+    let context = key_context(matches, carrier)?;
+
+    if matches.opt_present("x") {
+        let n = extract_banded(in_filename, out_filename, banded, key, &context, max_extract_size(matches)?)?;
+        println!("{}", tr!("bytes-written", count = n, file = out_filename.display().to_string()));
+        decrypt_output(matches, out_filename)
+    } else {
+        let digest = PayloadDigest::default();
+        let (mut reader, _) = message_source(matches, &digest)?;
+        hide_banded(in_filename, out_filename, banded, key, &context, &mut reader)?;
+        println!("{}", tr!("message-hidden", file = out_filename.display().to_string()));
+        manifest::write_manifest(matches, in_filename, out_filename, &digest);
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+use getopts::Matches;
+use super::{diag, message_source};
+use super::pixel::{hide_file, output_format};
+use super::manifest::PayloadDigest;
+use super::report::collect_files;
+
+//name of the state file with already processed covers in the output directory
+const STATE_FILE: &str = ".stegegg-batch";
+
+//hide the same message into all images in the input directory. Completed covers are recorded in the state file,
+//so the interrupted run continues where it left off instead of redoing (and re-randomizing) earlier outputs.
+pub fn hide_batch(matches: &Matches, in_dir: &Path, out_dir: &Path, key: &[u8]) {
+
+    //message is read once and hidden into each cover
+    let mut msg = Vec::new();
+    match message_source(matches, &PayloadDigest::default()) {
+        Ok((mut reader, _)) => if let Err(s) = reader.read_to_end(&mut msg) {
+            diag::error(s);
+            return;
+        },
+        Err(s) => { diag::error(s); return; },
+    }
+
+    let mut files = Vec::new();
+    if let Err(s) = collect_files(in_dir, &mut files) {
+        diag::error(s);
+        return;
+    }
+
+    if let Err(s) = fs::create_dir_all(out_dir) {
+        diag::error(format!("Can't create output directory '{}'. {}", out_dir.display(), s));
+        return;
+    }
+
+    let state_path = out_dir.join(STATE_FILE);
+    let done: HashSet<String> = match fs::read_to_string(&state_path) {
+        Ok(s) => s.lines().map(|l| l.to_string()).collect(),
+        Err(_) => HashSet::new(),
+    };
+    if !done.is_empty() {
+        println!("Resuming batch. {} covers already processed.", done.len());
+    }
+
+    let mut state = match OpenOptions::new().create(true).append(true).open(&state_path) {
+        Ok(f) => f,
+        Err(s) => { diag::error(format!("Can't open state file '{}'. {}", state_path.display(), s)); return; },
+    };
+
+    let (mut processed, mut skipped, mut failed) = (0, 0, 0);
+    for path in files {
+
+        //skip files which are not images
+        if image::image_dimensions(&path).is_err() {
+            continue;
+        }
+
+        let relative_path = path.strip_prefix(in_dir).unwrap_or(&path);
+        let relative = relative_path.to_string_lossy().to_string();
+        if done.contains(&relative) {
+            diag::log(format!("Batch: '{}' skipped, already done.", relative));
+            skipped += 1;
+            continue;
+        }
+
+        //output keeps the lossless format (and the extension) of the cover unless other format is requested
+        let extension = match output_format(matches, &path, None) {
+            Ok(f) => match path.extension().map(|e| e.to_string_lossy().to_string()) {
+                Some(e) if f.extensions_str().contains(&e.to_lowercase().as_str()) => e,
+                _ => f.extensions_str()[0].to_string(),
+            },
+            Err(s) => { diag::error(s); return; },
+        };
+        let out_path = out_dir.join(relative_path).with_extension(extension);
+        if let Some(parent) = out_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        let started = Instant::now();
+        match hide_file(matches, &path, &out_path, key, &mut Cursor::new(&msg), Some(msg.len())) {
+            Ok(_) => {
+                diag::log(format!("Batch: '{}' -> '{}' done in {:.3}s.", relative, out_path.display(), started.elapsed().as_secs_f64()));
+                processed += 1;
+                if let Err(s) = writeln!(state, "{}", relative).and_then(|_| state.sync_data()) {
+                    diag::error(format!("Can't write state file '{}'. {}", state_path.display(), s));
+                    return;
+                }
+            },
+            Err(s) => {
+                diag::log(format!("Batch: '{}' failed in {:.3}s.", relative, started.elapsed().as_secs_f64()));
+                failed += 1;
+                diag::error(format!("Error processing '{}'. {}", path.display(), s));
+            },
+        }
+    }
+
+    println!("{} covers processed, {} skipped as already done, {} failed.", processed, skipped, failed);
+    diag::log(format!("Batch: {} covers processed, {} skipped, {} failed.", processed, skipped, failed));
+
+    //all covers are done, state is not needed anymore
+    if failed == 0 {
+        let _ = fs::remove_file(&state_path);
+    }
+}
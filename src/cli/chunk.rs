@@ -0,0 +1,29 @@
+use std::path::Path;
+use getopts::Matches;
+use stegegg::*;
+use stegegg::chunk::{extract_chunk, hide_chunk};
+use stegegg::files::write_vec_to_file;
+use zeroize::Zeroizing;
+use super::i18n::tr;
+use super::manifest::{self, PayloadDigest};
+use super::{decrypt_output, key_context, message_source};
+
+//hide or extract the message of the ancillary PNG chunks (--mode chunk)
+pub fn process_chunk(matches: &Matches, in_filename: &Path, out_filename: &Path, key: &[u8]) -> Result<(), ExtractError> {
+    let carrier = if matches.opt_present("x") { in_filename } else { out_filename };
+    let context = key_context(matches, carrier)?;
+
+    if matches.opt_present("x") {
+        let v = Zeroizing::new(extract_chunk(in_filename, key, &context)?);
+        let n = write_vec_to_file(out_filename, &v)?;
+        println!("{}", tr!("bytes-written", count = n, file = out_filename.display().to_string()));
+        decrypt_output(matches, out_filename)
+    } else {
+        let digest = PayloadDigest::default();
+        let (mut reader, _) = message_source(matches, &digest)?;
+        hide_chunk(in_filename, out_filename, key, &context, &mut reader)?;
+        println!("{}", tr!("message-hidden", file = out_filename.display().to_string()));
+        manifest::write_manifest(matches, in_filename, out_filename, &digest);
+        Ok(())
+    }
+}
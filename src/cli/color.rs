@@ -0,0 +1,105 @@
+use getopts::Matches;
+use image::{ColorType, DynamicImage, GrayImage, RgbImage, RgbaImage};
+use stegegg::SpotLayout;
+use super::diag;
+
+//Color normalization of the covers. Message is hidden in 8-bit RGB samples, so the other decoded color types
+//(grayscale, alpha, 16-bit and float samples) are converted into RGB8 for the embedding. Alpha channel doesn't
+//carry the message, so it is kept aside and put back into the stego image. CMYK JPEGs are converted to RGB
+//by the decoder already. Conversions losing information are refused with --strict-color.
+
+//rgb8 image and the alpha channel of the cover
+pub type Cover = (DynamicImage, Option<GrayImage>);
+
+//conversions of the color type into RGB8 which lose information of the cover
+fn lossy_changes(color: ColorType, drop_alpha: bool) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+    if color.bytes_per_pixel() / color.channel_count() > 1 {
+        changes.push("samples are reduced to 8 bits");
+    }
+    if color.has_alpha() && drop_alpha {
+        changes.push("alpha channel is dropped");
+    }
+    changes
+}
+
+//convert the cover into RGB8 and return it with its alpha channel. On extraction (no output is made) the image
+//is converted silently and alpha is ignored. On hiding the lossy conversions are reported or refused.
+pub fn normalize(img: DynamicImage, matches: &Matches) -> Result<Cover, String> {
+    let color = img.color();
+    if color == ColorType::Rgb8 {
+        return Ok((img, None));
+    }
+    if matches.opt_present("x") && !matches.opt_present("use-alpha") {
+        return Ok((DynamicImage::ImageRgb8(img.into_rgb8()), None));
+    }
+
+    let drop_alpha = matches.opt_present("drop-alpha");
+    let lossy = lossy_changes(color, drop_alpha);
+    if !lossy.is_empty() && matches.opt_present("strict-color") {
+        return Err(format!("Image is {:?} and its conversion to RGB8 loses information ({}). Remove --strict-color to allow it.", color, lossy.join(", ")));
+    }
+
+    let mut changes = lossy;
+    if !color.has_color() {
+        changes.push("grayscale is stored as RGB");
+    }
+    if !changes.is_empty() && !matches.opt_present("x") {
+        diag::warning(format!("image is converted from {:?} to RGB8 ({}).", color, changes.join(", ")));
+    }
+
+    //alpha samples are taken from the 8-bit conversion, so they match the 8-bit color samples
+    let alpha = if color.has_alpha() && !drop_alpha {
+        let rgba = img.to_rgba8();
+        Some(GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| image::Luma([rgba.get_pixel(x, y)[3]])))
+    } else {
+        None
+    };
+    Ok((DynamicImage::ImageRgb8(img.into_rgb8()), alpha))
+}
+
+//number of the rows added below the image for its alpha samples, 3 samples in each pixel
+fn alpha_rows(width: u32, height: u32) -> u32 {
+    (width as u64 * height as u64).div_ceil(3 * width as u64) as u32
+}
+
+//image with the alpha samples packed into the rows below it, so they are hidding spots of any layout (--use-alpha)
+pub fn pack_alpha(rgb: &RgbImage, alpha: &GrayImage) -> RgbImage {
+    let (width, height) = rgb.dimensions();
+    let mut samples = rgb.as_raw().clone();
+    samples.extend_from_slice(alpha.as_raw());
+    samples.resize(width as usize * (height + alpha_rows(width, height)) as usize * 3, 0);
+    RgbImage::from_raw(width, height + alpha_rows(width, height), samples).unwrap()
+}
+
+//spot of the packed image lies in the image or in the alpha samples, not in the padding behind them
+pub fn packed_spot(layout: SpotLayout, spot_idx: u32, width: u32, height: u32) -> bool {
+    let (x, y, colors, _) = layout.position(spot_idx, width);
+    if y < height {
+        return true;
+    }
+    let first = ((y - height) as u64 * width as u64 + x as u64) * 3;
+    colors.end as u64 + first <= width as u64 * height as u64
+}
+
+//put the alpha channel of the cover back into the stego image. Alpha packed by --use-alpha is taken from the image.
+pub fn restore(img: DynamicImage, alpha: Option<&GrayImage>) -> DynamicImage {
+    let (Some(alpha), Some(rgb)) = (alpha, img.as_rgb8()) else {
+        return img;
+    };
+    let (width, height) = alpha.dimensions();
+    let unpacked;
+    let (rgb, alpha) = if rgb.height() > height {
+        let color_len = (width * height * 3) as usize;
+        let rgb_part = RgbImage::from_raw(width, height, rgb.as_raw()[..color_len].to_vec()).unwrap();
+        let alpha_part = GrayImage::from_raw(width, height, rgb.as_raw()[color_len..color_len + (width * height) as usize].to_vec()).unwrap();
+        unpacked = (rgb_part, alpha_part);
+        (&unpacked.0, &unpacked.1)
+    } else {
+        (rgb, alpha)
+    };
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+        let [r, g, b] = rgb.get_pixel(x, y).0;
+        image::Rgba([r, g, b, alpha.get_pixel(x, y)[0]])
+    }))
+}
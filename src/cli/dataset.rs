@@ -0,0 +1,119 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use stegegg::*;
+use super::diag;
+use stegegg::files::open_image;
+use stegegg::pngenc::save_png;
+use super::report::collect_files;
+
+//embedding algorithms available for the dataset
+const ALGORITHMS: [&str; 2] = ["lsb", "redundant"];
+
+//hex encoded bytes
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//parse comma separated list of payload rates in bits per color sample
+fn parse_rates(rates: &str) -> Result<Vec<f64>, String> {
+    rates.split(',').map(|r| match r.trim().parse::<f64>() {
+        Ok(v) if v > 0.0 && v <= 1.0 => Ok(v),
+        _ => Err(format!("Invalid payload rate '{}'. Rates are bits per color sample in (0, 1].", r)),
+    }).collect()
+}
+
+//hide random payload with random key into the cover. Returns the key, usable with the -k option.
+fn make_stego(cover: &image::RgbImage, layout: SpotLayout, payload_len: usize, out_path: &str, threads: usize) -> Result<String, String> {
+    let mut image = cover.clone();
+    let key = to_hex(&random_salt()?);
+    let salt = random_salt()?;
+    let mut payload = vec![0; payload_len];
+    getrandom::fill(&mut payload).map_err(|s| s.to_string())?;
+
+    let mut prng_state = init_prng(key.as_bytes(), &salt, &[]);
+    let indices = hidding_spots(&image, layout, RESERVED_SPOTS, &mut prng_state)?;
+    write_salt(&mut image, layout, &salt);
+    hide_from_reader(&mut Cursor::new(payload), Some(payload_len), &mut image, layout, &indices)?;
+    save_png(&image, out_path, threads)?;
+    Ok(key)
+}
+
+//create paired cover / stego images for all covers in the directory across the grid of payload rates and algorithms,
+//with labels.csv describing each produced image
+pub fn make_dataset(in_dir: Option<&String>, out_dir: Option<&String>, rates: Option<String>, algorithms: Option<String>, threads: usize) {
+
+    let (in_dir, out_dir) = match (in_dir, out_dir) {
+        (Some(i), Some(o)) => (i, o),
+        _ => { diag::error("Input and output directories must be specified."); return; },
+    };
+
+    let rates = match parse_rates(&rates.unwrap_or("0.05,0.1,0.2,0.4".to_string())) {
+        Ok(r) => r,
+        Err(s) => { diag::error(s); return; },
+    };
+
+    let algorithms: Vec<String> = algorithms.unwrap_or("lsb".to_string()).split(',').map(|a| a.trim().to_string()).collect();
+    if let Some(a) = algorithms.iter().find(|a| !ALGORITHMS.contains(&a.as_str())) {
+        diag::error(format!("Unknown algorithm '{}'. Available algorithms: {}", a, ALGORITHMS.join(", ")));
+        return;
+    }
+
+    let mut files = Vec::new();
+    if let Err(s) = collect_files(Path::new(in_dir), &mut files) {
+        diag::error(s);
+        return;
+    }
+    if let Err(s) = fs::create_dir_all(out_dir) {
+        diag::error(format!("Can't create output directory '{}'. {}", out_dir, s));
+        return;
+    }
+
+    let mut labels = String::from("file,cover,label,algorithm,rate,payload_bytes,key\n");
+    let mut count = 0;
+
+    for (i, path) in files.iter().enumerate() {
+        let cover = match open_image(path) {
+            Ok(img) => img.to_rgb8(),
+            Err(_) => continue,
+        };
+
+        //covers are re-encoded by the same PNG encoder as the stego images, so they differ only in the payload
+        let stem = format!("{:05}_{}", i, path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default());
+        let cover_name = format!("{}_cover.png", stem);
+        if let Err(s) = save_png(&cover, Path::new(out_dir).join(&cover_name), threads) {
+            diag::error(format!("Error writing '{}'. {}", cover_name, s));
+            continue;
+        }
+        labels.push_str(&format!("{},{},0,,0,0,\n", cover_name, cover_name));
+        count += 1;
+
+        for algorithm in &algorithms {
+            let layout = SpotLayout { redundant: algorithm == "redundant", depth: DEFAULT_DEPTH };
+            let capacity = layout.spot_count(&cover).map_or(0, |spots| capacity_for(layout, spots));
+
+            for &rate in &rates {
+                let payload_len = (rate * cover.width() as f64 * cover.height() as f64 * 3.0 / 8.0) as usize;
+                if payload_len > capacity {
+                    println!("Skipping {} at rate {} for '{}'. Payload doesn't fit.", algorithm, rate, path.display());
+                    continue;
+                }
+
+                let name = format!("{}_{}_{}.png", stem, algorithm, rate);
+                match make_stego(&cover, layout, payload_len, &Path::new(out_dir).join(&name).to_string_lossy(), threads) {
+                    Ok(key) => {
+                        labels.push_str(&format!("{},{},1,{},{},{},{}\n", name, cover_name, algorithm, rate, payload_len, key));
+                        count += 1;
+                    },
+                    Err(s) => diag::error(format!("Error creating '{}'. {}", name, s)),
+                }
+            }
+        }
+    }
+
+    let labels_path = Path::new(out_dir).join("labels.csv");
+    match fs::write(&labels_path, labels) {
+        Ok(_) => println!("{} images written to '{}' with labels in '{}'.", count, out_dir, labels_path.display()),
+        Err(s) => diag::error(format!("Error writing '{}'. {}", labels_path.display(), s)),
+    }
+}
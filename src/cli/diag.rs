@@ -0,0 +1,70 @@
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use super::i18n::tr;
+
+//Diagnostics printed as "Error: ...", "Warning: ..." and "Hint: ...". Level is colored on the terminal,
+//color is off when the output is redirected, with --no-color or when NO_COLOR is set (https://no-color.org).
+//With --log-file the diagnostics and more verbose records are also appended to the log.
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+//an error was printed, the process exits with code 1 at the end
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+//enable color when printing to the terminal and it isn't disabled
+pub fn init(no_color: bool) {
+    let enabled = !no_color && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stdout().is_terminal();
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+//print the message with the level label in the ANSI color and log it
+fn print(label: &str, color: &str, msg: impl Display) {
+    log(format!("{}: {}", label, msg));
+    if COLOR.load(Ordering::Relaxed) {
+        println!("\x1b[1;{}m{}:\x1b[0m {}", color, label, msg);
+    } else {
+        println!("{}: {}", label, msg);
+    }
+}
+
+pub fn error(msg: impl Display) {
+    FAILED.store(true, Ordering::Relaxed);
+    print(&tr!("diag-error"), "31", msg);
+}
+
+//some error was printed
+pub fn failed() -> bool {
+    FAILED.load(Ordering::Relaxed)
+}
+
+pub fn warning(msg: impl Display) {
+    print(&tr!("diag-warning"), "33", msg);
+}
+
+pub fn hint(msg: impl Display) {
+    print(&tr!("diag-hint"), "36", msg);
+}
+
+//log file with the time of the start of the run
+static LOG: Mutex<Option<(File, Instant)>> = Mutex::new(None);
+
+//open the log file. Diagnostics and the verbose records (parameters, timings, batch results) are appended to it.
+pub fn open_log(path: &Path) -> Result<(), String> {
+    let mut f = OpenOptions::new().create(true).append(true).open(path).map_err(|s| format!("Can't open log file '{}'. {}", path.display(), s))?;
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    writeln!(f, "--- stegegg v{} started at {} (unix time)", env!("CARGO_PKG_VERSION"), since_epoch).map_err(|s| s.to_string())?;
+    *LOG.lock().unwrap() = Some((f, Instant::now()));
+    Ok(())
+}
+
+//write the record with the time since the start into the log file, if there is one
+pub fn log(msg: impl Display) {
+    if let Some((f, start)) = LOG.lock().unwrap().as_mut() {
+        let _ = writeln!(f, "[{:>9.3}s] {}", start.elapsed().as_secs_f64(), msg);
+    }
+}
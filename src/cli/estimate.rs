@@ -0,0 +1,72 @@
+use std::io::Read;
+use getopts::Matches;
+use stegegg::*;
+use super::{diag, message_source};
+use super::options::{codec, encrypt, max_fill};
+use super::manifest::{options_capacity, PayloadDigest};
+
+//common aspect ratios of the recommended cover sizes
+const ASPECTS: [(u32, u32); 3] = [(4, 3), (16, 9), (1, 1)];
+
+//largest image side tried, larger covers are unusual
+const MAX_SIDE: u32 = 1 << 16;
+
+//smallest image of the given aspect ratio holding the payload at the fill rate
+fn smallest_cover(matches: &Matches, aspect: (u32, u32), payload: usize, max_fill: f64) -> Result<Option<(u32, u32, usize)>, String> {
+    for width in 1..=MAX_SIDE {
+        let height = (width as u64 * aspect.1 as u64).div_ceil(aspect.0 as u64) as u32;
+        let capacity = options_capacity(matches, width, height)?;
+        if capacity as f64 * max_fill >= payload as f64 {
+            return Ok(Some((width, height, capacity)));
+        }
+    }
+    Ok(None)
+}
+
+//'estimate' command. Prints the smallest covers which can carry the payload with the given pipeline options.
+pub fn estimate(matches: &Matches) {
+
+    let max_fill = match max_fill(matches) {
+        Ok(f) => f,
+        Err(s) => { diag::error(s); return; },
+    };
+
+    //payload size after the optional compression and the encryption for the recipients
+    let payload = match message_source(matches, &PayloadDigest::default()) {
+        Ok((mut reader, _)) if matches.opt_present("compress") => {
+            let mut msg = zeroize::Zeroizing::new(Vec::new());
+            match reader.read_to_end(&mut msg) {
+                Ok(_) => match codec(matches) {
+                    Ok(codec) => compress::compress(&msg, codec).map_or(msg.len(), |(_, c)| c.len()),
+                    Err(s) => { diag::error(s); return; },
+                },
+                Err(s) => { diag::error(s); return; },
+            }
+        },
+        Ok((_, Some(len))) => len,
+        Ok((mut reader, None)) => match std::io::copy(&mut reader, &mut std::io::sink()) {
+            Ok(n) => n as usize,
+            Err(s) => { diag::error(s); return; },
+        },
+        Err(s) => { diag::error(s); return; },
+    };
+    let payload = payload + if encrypt(matches) { aead::OVERHEAD } else { 0 };
+    if payload > MAX_MSG_LEN {
+        diag::error(format!("Payload of {} is above the maximum message size of {}.", human_size(payload), human_size(MAX_MSG_LEN)));
+        return;
+    }
+    if matches.opt_str("mode").as_deref() == Some("chunk") {
+        println!("Chunk mode stores the payload of {} outside the pixels, any PNG cover can carry it.", human_size(payload));
+        return;
+    }
+
+    let (format, algorithm, ecc) = super::manifest::parameters(matches);
+    println!("Payload {}, format {}, algorithm {}, ecc {}, at most {:.0}% of the capacity used.", human_size(payload), format, algorithm, ecc, max_fill * 100.0);
+    for aspect in ASPECTS {
+        match smallest_cover(matches, aspect, payload, max_fill) {
+            Ok(Some((width, height, capacity))) => println!("{:>2}:{:<2} {}x{} pixels, capacity {}", aspect.0, aspect.1, width, height, human_size(capacity)),
+            Ok(None) => println!("{:>2}:{:<2} no cover up to {} pixels wide is large enough", aspect.0, aspect.1, MAX_SIDE),
+            Err(s) => { diag::error(s); return; },
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use getopts::Matches;
+use image::RgbImage;
+use super::{diag, key_context};
+use stegegg::files::{open_image, write_vec_to_file};
+use super::pixel::prepare_spots;
+
+//pack bits MSB first, as StegSolve and zsteg (b1,..,msb,xy) do
+fn pack_msb_first(bits: impl Iterator<Item = u8>) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (i, bit) in bits.enumerate() {
+        if i % 8 == 0 {
+            data.push(0);
+        }
+        *data.last_mut().unwrap() |= bit << (7 - i % 8);
+    }
+    data
+}
+
+//write the bit stream into "<prefix>.<name>.bin"
+fn write_stream(prefix: &str, name: &str, data: &[u8]) -> Result<(), String> {
+    let path = format!("{}.{}.bin", prefix, name);
+    write_vec_to_file(&path, data).map_err(|s| format!("Error accessing the file '{}'. {}", path, s))?;
+    println!("{} bytes written to '{}'", data.len(), path);
+    Ok(())
+}
+
+//dump LSB planes of the image for external analysis tools. Scan order streams are always written,
+//the keyed stream (in the order stegegg reads the message, LSB first) only when the key is given.
+pub fn export_lsb(matches: &Matches, path: Option<&String>, prefix: Option<&String>, key: Option<&[u8]>) {
+
+    let (path, prefix) = match (path, prefix) {
+        (Some(p), Some(o)) => (p, o),
+        _ => { println!("Usage: stegegg export-lsb [-k <key>] <image> <output prefix>"); return; },
+    };
+
+    let result = open_image(path).and_then(|img| {
+        let image: RgbImage = img.to_rgb8();
+
+        //per channel and interleaved RGB planes in raster order
+        for (c, name) in ["r", "g", "b"].iter().enumerate() {
+            write_stream(prefix, name, &pack_msb_first(image.pixels().map(|p| p[c] & 1)))?;
+        }
+        write_stream(prefix, "rgb", &pack_msb_first(image.as_raw().iter().map(|v| v & 1)))?;
+
+        //bits in the key dependent order, packed into bytes the way stegegg packs the message
+        if let Some(key) = key {
+            let context = key_context(matches, path)?;
+            let (layout, _, indices) = prepare_spots(matches, &image, key, &context, true)?;
+            let data: Vec<u8> = indices.chunks_exact(8).map(|spots| {
+                spots.iter().enumerate().fold(0, |b, (n, &spot)| b | layout.read_bit(&image, spot) << n)
+            }).collect();
+            write_stream(prefix, "keyed", &data)?;
+        }
+        Ok(())
+    });
+
+    if let Err(s) = result {
+        diag::error(s);
+    }
+}
@@ -0,0 +1,183 @@
+use std::path::PathBuf;
+use image::RgbImage;
+use super::{diag, os_arg};
+use stegegg::files::{open_image, write_vec_to_file};
+use super::report::collect_files;
+
+//Rich model features for ML steganalysis. SPAM686 (second order Markov transitions of the pixel differences
+//truncated to [-3, 3], Pevny, Bas, Fridrich) and the co-occurrence matrix of neighbouring differences truncated
+//to [-4, 4]. Features are computed on each channel and averaged.
+
+//truncation of the SPAM differences
+const SPAM_T: i32 = 3;
+
+//truncation of the co-occurrence differences
+const COOC_T: i32 = 4;
+
+//straight and diagonal directions, averaged into the two halves of SPAM
+const STRAIGHT: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const DIAGONAL: [(i32, i32); 4] = [(1, 1), (-1, -1), (1, -1), (-1, 1)];
+
+//number of features of one image
+pub const FEATURE_COUNT: usize = 2 * 7 * 7 * 7 + 9 * 9;
+
+//truncated difference of the samples at the positions
+fn difference(channel: &[i32], width: usize, (x0, y0): (i32, i32), (x1, y1): (i32, i32), t: i32) -> usize {
+    let d = channel[y0 as usize * width + x0 as usize] - channel[y1 as usize * width + x1 as usize];
+    (d.clamp(-t, t) + t) as usize
+}
+
+//second order Markov transition probabilities of the differences in one direction
+fn spam_direction(channel: &[i32], width: usize, height: usize, (dx, dy): (i32, i32)) -> Vec<f64> {
+    let n = (2 * SPAM_T + 1) as usize;
+    let mut triples = vec![0u64; n * n * n];
+    let mut pairs = vec![0u64; n * n];
+
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let last = (x + 3 * dx, y + 3 * dy);
+            if last.0 < 0 || last.1 < 0 || last.0 >= width as i32 || last.1 >= height as i32 {
+                continue;
+            }
+            let p = |k: i32| (x + k * dx, y + k * dy);
+            let d1 = difference(channel, width, p(0), p(1), SPAM_T);
+            let d2 = difference(channel, width, p(1), p(2), SPAM_T);
+            let d3 = difference(channel, width, p(2), p(3), SPAM_T);
+            triples[(d1 * n + d2) * n + d3] += 1;
+            pairs[d1 * n + d2] += 1;
+        }
+    }
+
+    triples.iter().enumerate().map(|(i, &c)| {
+        let pair = pairs[i / n];
+        if pair == 0 { 0.0 } else { c as f64 / pair as f64 }
+    }).collect()
+}
+
+//co-occurrence of the horizontal and vertical neighbouring differences, normalized to sum 1
+fn cooccurrence(channel: &[i32], width: usize, height: usize) -> Vec<f64> {
+    let n = (2 * COOC_T + 1) as usize;
+    let mut counts = vec![0u64; n * n];
+    for (dx, dy) in [(1, 0), (0, 1)] {
+        for y in 0..height as i32 - 2 * dy {
+            for x in 0..width as i32 - 2 * dx {
+                let d1 = difference(channel, width, (x, y), (x + dx, y + dy), COOC_T);
+                let d2 = difference(channel, width, (x + dx, y + dy), (x + 2 * dx, y + 2 * dy), COOC_T);
+                counts[d1 * n + d2] += 1;
+            }
+        }
+    }
+    let total = counts.iter().sum::<u64>().max(1) as f64;
+    counts.iter().map(|&c| c as f64 / total).collect()
+}
+
+//SPAM686 followed by the co-occurrence matrix, averaged over the channels
+pub fn image_features(image: &RgbImage) -> Vec<f64> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut features = vec![0.0; FEATURE_COUNT];
+
+    for c in 0..3 {
+        let channel: Vec<i32> = image.pixels().map(|p| p[c] as i32).collect();
+        let mut channel_features = Vec::with_capacity(FEATURE_COUNT);
+        for directions in [STRAIGHT, DIAGONAL] {
+            let mut half = vec![0.0; 7 * 7 * 7];
+            for direction in directions {
+                for (h, m) in half.iter_mut().zip(spam_direction(&channel, width, height, direction)) {
+                    *h += m / 4.0;
+                }
+            }
+            channel_features.extend(half);
+        }
+        channel_features.extend(cooccurrence(&channel, width, height));
+
+        for (f, v) in features.iter_mut().zip(channel_features) {
+            *f += v / 3.0;
+        }
+    }
+    features
+}
+
+//names of the feature columns
+fn feature_names() -> Vec<String> {
+    let spam = |half: &'static str| (0..7 * 7 * 7).map(move |i| format!("spam_{}_{}_{}_{}", half, i / 49 - 3, i / 7 % 7 - 3, i % 7 - 3));
+    let cooc = (0..9 * 9).map(|i| format!("cooc_{}_{}", i / 9 - 4, i % 9 - 4));
+    spam("straight").chain(spam("diagonal")).chain(cooc).collect()
+}
+
+//numpy .npy file (format 1.0) with the float32 matrix of features, one row per image
+fn npy(rows: &[Vec<f64>]) -> Vec<u8> {
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}", rows.len(), FEATURE_COUNT);
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut data = b"\x93NUMPY\x01\x00".to_vec();
+    data.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    data.extend_from_slice(header.as_bytes());
+    for v in rows.iter().flatten() {
+        data.extend_from_slice(&(*v as f32).to_le_bytes());
+    }
+    data
+}
+
+//compute the features of the images (files or directories) and write them as CSV or NPY by the output extension.
+//NPY has no image names, they are written in the order of the rows into "<output>.files.txt".
+pub fn export_features(args: &[String]) {
+
+    let (output, inputs) = match args.split_first() {
+        Some((o, i)) if !i.is_empty() => (os_arg(o), i),
+        _ => { println!("Usage: stegegg features <output.csv|output.npy> <images or directories...>"); return; },
+    };
+    let numpy = match output.extension().map(|e| e.to_string_lossy().to_lowercase()).as_deref() {
+        Some("csv") => false,
+        Some("npy") => true,
+        _ => { diag::error("Output of 'features' must be a .csv or .npy file."); return; },
+    };
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    for input in inputs.iter().map(|i| os_arg(i)) {
+        if input.is_dir() {
+            if let Err(s) = collect_files(&input, &mut files) {
+                diag::error(s);
+                return;
+            }
+        } else {
+            files.push(input);
+        }
+    }
+
+    let mut names = Vec::new();
+    let mut rows = Vec::new();
+    for path in &files {
+
+        //skip files which are not images
+        if image::image_dimensions(path).is_err() {
+            continue;
+        }
+        match open_image(path) {
+            Ok(img) => {
+                rows.push(image_features(&img.to_rgb8()));
+                names.push(path.display().to_string());
+            },
+            Err(s) => diag::warning(format!("Skipping '{}'. {}", path.display(), s)),
+        }
+    }
+
+    let result = if numpy {
+        let list_path = PathBuf::from(format!("{}.files.txt", output.display()));
+        write_vec_to_file(&list_path, names.iter().map(|n| format!("{}\n", n)).collect::<String>().as_bytes())
+            .and_then(|_| write_vec_to_file(&output, &npy(&rows)))
+    } else {
+        let mut csv = format!("image,{}\n", feature_names().join(","));
+        for (name, row) in names.iter().zip(&rows) {
+            csv.push_str(&format!("\"{}\",{}\n", name.replace('"', "\"\""), row.iter().map(|v| format!("{:.6}", v)).collect::<Vec<_>>().join(",")));
+        }
+        write_vec_to_file(&output, csv.as_bytes())
+    };
+
+    match result {
+        Ok(_) => println!("{} features of {} images written to '{}'.", FEATURE_COUNT, rows.len(), output.display()),
+        Err(s) => diag::error(format!("Error writing '{}'. {}", output.display(), s)),
+    }
+}
@@ -0,0 +1,105 @@
+use stegegg::{async_api, ExtractError};
+use tonic::{Request, Response, Status};
+
+//gRPC server for the microservice deployments. Messages match proto/stegegg.proto, the service code
+//is generated by build.rs. The calls use the same bytes in / bytes out API as the HTTP server.
+
+mod service {
+    include!(concat!(env!("OUT_DIR"), "/stegegg.Stegegg.rs"));
+}
+use service::stegegg_server::{Stegegg, StegeggServer};
+use super::diag;
+
+//maximal size of the decoded request
+const MAX_REQUEST_LEN: usize = 64 << 20;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EmbedRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub image: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub message: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EmbedResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub image: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExtractRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub image: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ExtractResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub message: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CapacityRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub image: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CapacityResponse {
+    #[prost(uint32, tag = "1")]
+    pub width: u32,
+    #[prost(uint32, tag = "2")]
+    pub height: u32,
+    #[prost(uint64, tag = "3")]
+    pub capacity_bytes: u64,
+}
+
+struct StegeggService;
+
+#[tonic::async_trait]
+impl Stegegg for StegeggService {
+    async fn embed(&self, request: Request<EmbedRequest>) -> Result<Response<EmbedResponse>, Status> {
+        let r = request.into_inner();
+        let image = async_api::embed_image(r.image, r.key, r.message).await.map_err(Status::invalid_argument)?;
+        Ok(Response::new(EmbedResponse { image }))
+    }
+
+    async fn extract(&self, request: Request<ExtractRequest>) -> Result<Response<ExtractResponse>, Status> {
+        let r = request.into_inner();
+        let message = async_api::extract_image(r.image, r.key).await.map_err(|e| match e {
+            ExtractError::NoPayload(s) => Status::not_found(s),
+            e => Status::invalid_argument(e.to_string()),
+        })?;
+        Ok(Response::new(ExtractResponse { message }))
+    }
+
+    async fn capacity(&self, request: Request<CapacityRequest>) -> Result<Response<CapacityResponse>, Status> {
+        let (width, height, capacity) = async_api::image_capacity(request.into_inner().image).await.map_err(Status::invalid_argument)?;
+        Ok(Response::new(CapacityResponse { width, height, capacity_bytes: capacity as u64 }))
+    }
+}
+
+//'grpc' command. Serves the Stegegg service on the address until it is killed.
+pub fn grpc(listen: Option<String>, threads: usize) {
+    let listen = listen.unwrap_or_else(|| "127.0.0.1:50051".to_string());
+    let addr = match listen.parse() {
+        Ok(a) => a,
+        Err(s) => { diag::error(format!("Invalid address '{}'. {}", listen, s)); return; },
+    };
+
+    let runtime = match tokio::runtime::Builder::new_multi_thread().worker_threads(threads).enable_all().build() {
+        Ok(r) => r,
+        Err(s) => { diag::error(format!("Can't start the runtime. {}", s)); return; },
+    };
+
+    println!("gRPC service listening on {}", listen);
+    let service = StegeggServer::new(StegeggService).max_decoding_message_size(MAX_REQUEST_LEN);
+    if let Err(s) = runtime.block_on(tonic::transport::Server::builder().add_service(service).serve(addr)) {
+        diag::error(format!("Can't listen on '{}'. {}", listen, s));
+    }
+}
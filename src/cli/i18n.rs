@@ -0,0 +1,53 @@
+use std::sync::OnceLock;
+use fluent::concurrent::FluentBundle;
+use fluent::{FluentArgs, FluentResource};
+
+//Translations of the user-facing messages. Locale is selected by LC_ALL, LC_MESSAGES or LANG (e.g. "es_ES.UTF-8"),
+//messages missing in the locale fall back to English. Translations are Fluent files compiled into the binary.
+
+//translated locales as (language, fluent file)
+const LOCALES: &[(&str, &str)] = &[
+    ("en", include_str!("../../locales/en/stegegg.ftl")),
+    ("es", include_str!("../../locales/es/stegegg.ftl")),
+];
+
+//language of the user from the environment, as POSIX programs look it up
+fn user_language() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+        .filter_map(|v| std::env::var(v).ok())
+        .find(|v| !v.is_empty())
+        .map(|v| v.split(['_', '.', '@', '-']).next().unwrap_or_default().to_lowercase())
+        .unwrap_or_default()
+}
+
+//bundle with the messages of the language
+fn bundle(language: &str) -> FluentBundle<FluentResource> {
+    let ftl = LOCALES.iter().find(|l| l.0 == language).unwrap_or(&LOCALES[0]).1;
+    let mut bundle = FluentBundle::new_concurrent(vec![language.parse().unwrap_or_default()]);
+
+    //no unicode isolation marks around the arguments, they show up as garbage in some terminals
+    bundle.set_use_isolating(false);
+    bundle.add_resource(FluentResource::try_new(ftl.to_string()).expect("invalid translation")).expect("invalid translation");
+    bundle
+}
+
+//translated message with the arguments
+pub fn translate(id: &str, args: Option<&FluentArgs>) -> String {
+    static BUNDLES: OnceLock<(FluentBundle<FluentResource>, FluentBundle<FluentResource>)> = OnceLock::new();
+    let (user, english) = BUNDLES.get_or_init(|| (bundle(&user_language()), bundle("en")));
+
+    let bundle = if user.has_message(id) { user } else { english };
+    match bundle.get_message(id).and_then(|m| m.value()) {
+        Some(pattern) => bundle.format_pattern(pattern, args, &mut Vec::new()).into_owned(),
+        None => id.to_string(),
+    }
+}
+
+//translated message, arguments are given as name = value
+macro_rules! tr {
+    ($id:expr) => { $crate::cli::i18n::translate($id, None) };
+    ($id:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::cli::i18n::translate($id, Some(&fluent::fluent_args![$(stringify!($name) => $value),+]))
+    };
+}
+pub(crate) use tr;
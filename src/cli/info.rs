@@ -0,0 +1,113 @@
+use std::path::Path;
+use getopts::Matches;
+use stegegg::*;
+use stegegg::audio::AudioCarrier;
+use super::{color, diag, key_context, os_arg, wav};
+use stegegg::files::open_image;
+use super::options::{encrypt, kdf, max_extract_size};
+use super::pixel::prepare_spots;
+use super::manifest::{options_capacity, parameters};
+
+//state of the stegegg header read with the key
+fn header_state(matches: &Matches, in_filename: &Path, img: image::DynamicImage, key: &[u8]) -> Result<String, String> {
+    let context = key_context(matches, in_filename)?;
+    let (img, _) = color::normalize(img, matches)?;
+    let rgb_img = img.as_rgb8().unwrap();
+    let (layout, _, indices) = match prepare_spots(matches, rgb_img, key, &context, true) {
+        Ok(s) => s,
+        Err(_) => return Ok("not found, the image is too small".to_string()),
+    };
+    let msg_len = match read_header_limited(rgb_img, layout, &indices, max_extract_size(matches)?) {
+        Ok(len) => len,
+        Err(_) => return Ok("not found with this key and layout".to_string()),
+    };
+    //only the legacy images may be older than the trailer, for the others the missing trailer means wrong key
+    if matches.opt_present("legacy") {
+        return Ok(match verify_legacy_payload(rgb_img, layout, &indices, msg_len) {
+            Ok(Some(_)) => format!("valid, message of {}, checksum verified", human_size(msg_len)),
+            Ok(None) => format!("message of {} without checksum (legacy format, the key can't be verified)", human_size(msg_len)),
+            Err(e) => format!("present, message of {}. {}", human_size(msg_len), e),
+        });
+    }
+    Ok(match verify_payload(rgb_img, layout, &indices, msg_len) {
+        Ok(_) => format!("valid, message of {}, checksum verified", human_size(msg_len)),
+        Err(ExtractError::NoPayload(_)) => "not found with this key and layout".to_string(),
+        Err(e) => format!("present, message of {}. {}", human_size(msg_len), e),
+    })
+}
+
+//state of the stegegg header in the sample LSBs of the WAV audio
+fn audio_header_state(matches: &Matches, in_filename: &Path, audio: &AudioCarrier, key: &[u8]) -> Result<String, String> {
+    let context = key_context(matches, in_filename)?;
+    let salt = carrier::read_salt(audio);
+    let indices = audio.spots(&kdf(matches)?.derive(key, &salt)?, &salt, &context);
+    Ok(match carrier::read_header_limited(audio, &indices, max_extract_size(matches)?) {
+        Ok(msg_len) => match carrier::verify_payload(audio, &indices, msg_len) {
+            Ok(_) => format!("valid, message of {}, checksum verified", human_size(msg_len)),
+            Err(ExtractError::NoPayload(_)) => "not found with this key".to_string(),
+            Err(e) => format!("present, message of {}. {}", human_size(msg_len), e),
+        },
+        Err(_) => "not found with this key".to_string(),
+    })
+}
+
+//capacity of the WAV carrier in the sample LSBs
+fn audio_info(matches: &Matches, in_filename: &Path, key: Option<&[u8]>) -> Result<(), String> {
+    let audio = AudioCarrier::open(in_filename)?;
+    let capacity = if encrypt(matches) { audio.capacity().saturating_sub(aead::OVERHEAD) } else { audio.capacity() };
+    println!("Audio:      '{}'", in_filename.display());
+    println!("Format:     {} channels, {} Hz, {} samples of 16 bits", audio.spec.channels, audio.spec.sample_rate, audio.samples.len());
+    println!("Capacity:   {}", human_size(capacity));
+    if let Some(key) = key {
+        println!("Header:     {}", audio_header_state(matches, in_filename, &audio, key)?);
+    }
+    Ok(())
+}
+
+//'capacity' command. Prints the dimensions, color type and capacity of the carrier with the layout options,
+//and whether a stegegg header is present when the key is given.
+pub fn capacity_info(matches: &Matches, path: Option<&String>, key: Option<&[u8]>) {
+
+    let in_filename = match path {
+        Some(p) => os_arg(p),
+        None => { println!("Usage: stegegg capacity [-k <key>] [layout options] <image>"); return; },
+    };
+    if matches.opt_present("use-alpha") {
+        diag::error("Option --use-alpha can't be used with 'capacity'.");
+        return;
+    }
+
+    if wav::is_wav(&in_filename) {
+        if let Err(s) = audio_info(matches, &in_filename, key) {
+            diag::error(s);
+        }
+        return;
+    }
+
+    let result = open_image(&in_filename).and_then(|img| {
+        let (width, height) = (img.width(), img.height());
+        let (format, algorithm, ecc) = parameters(matches);
+        let capacity = options_capacity(matches, width, height)?;
+        let capacity = if encrypt(matches) { capacity.saturating_sub(aead::OVERHEAD) } else { capacity };
+
+        println!("Image:      '{}'", in_filename.display());
+        println!("Size:       {}x{}, {:?}", width, height, img.color());
+        println!("Layout:     format {}, algorithm {}, ecc {}", format, algorithm, ecc);
+        println!("Capacity:   {}", human_size(capacity));
+
+        //header is read by the whole image pixel modes only
+        if let Some(key) = key {
+            let state = if format == "chunk" || ["tiled", "banded", "low-memory"].iter().any(|o| matches.opt_present(o)) {
+                "not checked in this mode".to_string()
+            } else {
+                header_state(matches, &in_filename, img, key)?
+            };
+            println!("Header:     {}", state);
+        }
+        Ok(())
+    });
+
+    if let Err(s) = result {
+        diag::error(s);
+    }
+}
@@ -0,0 +1,85 @@
+use getopts::Matches;
+use stegegg::*;
+use super::{color, key_context, looks_binary, manifest, os_arg};
+use stegegg::files::open_image;
+use super::pixel::prepare_spots;
+
+//bytes of the payload start used to recognize its type
+const SNIFF_LEN: usize = 64;
+
+//type of the payload recognized by the leading bytes
+fn payload_type(data: &[u8]) -> &'static str {
+    let signatures: [(&[u8], &str); 9] = [
+        (b"age-encryption.org/", "age encrypted"),
+        (b"-----BEGIN AGE ENCRYPTED FILE-----", "age encrypted (armored)"),
+        (b"-----BEGIN PGP MESSAGE-----", "OpenPGP message (armored)"),
+        (b"\x1f\x8b", "gzip"),
+        (b"PK\x03\x04", "zip"),
+        (b"\x89PNG\r\n\x1a\n", "PNG image"),
+        (b"\xff\xd8\xff", "JPEG image"),
+        (b"%PDF-", "PDF document"),
+        (b"7z\xbc\xaf\x27\x1c", "7z archive"),
+    ];
+    match signatures.iter().find(|(magic, _)| data.starts_with(magic)) {
+        Some((_, name)) => name,
+        None if data.is_empty() => "empty",
+        None if looks_binary(data) => "binary data (encrypted or unknown format)",
+        None => "text",
+    }
+}
+
+//'inspect-header' command. Decodes the header of the hidden message with the key and prints its fields
+//without extracting the message body.
+pub fn inspect_header(matches: &Matches, in_filename: Option<&String>, key: &[u8]) -> Result<(), ExtractError> {
+
+    let in_filename = match in_filename {
+        Some(f) => os_arg(f),
+        None => { println!("Usage: stegegg inspect-header -k <key> [extraction options] <image>"); return Ok(()); },
+    };
+    let (format, algorithm, ecc) = manifest::parameters(matches);
+    if matches.opt_present("tiled") || matches.opt_present("banded") || matches.opt_present("low-memory") || format == "chunk" {
+        return Err(ExtractError::Failed("inspect-header supports only the whole image pixel modes.".to_string()));
+    }
+    if matches.opt_present("use-alpha") {
+        return Err(ExtractError::Failed("inspect-header can't read the messages hidden with --use-alpha.".to_string()));
+    }
+
+    let context = key_context(matches, &in_filename)?;
+    let (img, _) = color::normalize(open_image(&in_filename).map_err(ExtractError::Decode)?, matches)?;
+    let rgb_img = img.as_rgb8().unwrap();
+    let (layout, salt, indices) = prepare_spots(matches, rgb_img, key, &context, true)?;
+    let msg_len = read_header(rgb_img, layout, &indices)?;
+    let trailer = if matches.opt_present("legacy") {
+        verify_legacy_payload(rgb_img, layout, &indices, msg_len)?
+    } else {
+        Some(verify_payload(rgb_img, layout, &indices, msg_len)?)
+    };
+    let version = match &trailer {
+        Some(t) => {
+            let mut notes = vec![format!("checksum {:08x} verified", t.crc32)];
+            notes.extend(t.codec().map(|c| format!("{} compressed", c.name())));
+            notes.extend(t.encrypted().then(|| "encrypted".to_string()));
+            notes.extend(t.label.as_ref().map(|l| format!("label '{}'", l)));
+            notes.push(t.kdf.map_or("key derivation not recorded".to_string(), |k| format!("{} key derivation", k.name())));
+            format!("{} ({})", t.version, notes.join(", "))
+        },
+        None => "0 (no checksum)".to_string(),
+    };
+    let capacity = message_capacity(indices.len());
+
+    let depth = layout.depth.iter().zip("rgb".chars()).map(|(d, c)| format!("{}:{}", c, d)).collect::<Vec<_>>().join(",");
+    let depth_source = if layout.redundant || layout.depth == DEFAULT_DEPTH { "default" } else { "from the depth descriptor" };
+
+    println!("Image:      {}x{} '{}'", rgb_img.width(), rgb_img.height(), in_filename.display());
+    println!("Format:     {}, version {}", format, version);
+    println!("Algorithm:  {}", if layout.depth != DEFAULT_DEPTH { "lsb-depth" } else { algorithm });
+    println!("ECC:        {}", ecc);
+    if !salt.is_empty() {
+        println!("Salt:       {}", salt.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    }
+    println!("Depth:      {} ({})", depth, depth_source);
+    println!("Length:     {}", human_size(msg_len));
+    println!("Capacity:   {} ({:.1}% used)", human_size(capacity), msg_len as f64 * 100.0 / capacity.max(1) as f64);
+    println!("Payload:    {}", payload_type(&extract_bytes(rgb_img, layout, &indices[3 * 8..], 0, msg_len.min(SNIFF_LEN))));
+    Ok(())
+}
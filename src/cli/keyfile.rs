@@ -0,0 +1,20 @@
+use std::path::Path;
+use stegegg::files::read_file_to_vec;
+use stegegg::keyfile::{is_protected, unprotect};
+use zeroize::Zeroizing;
+use super::i18n::tr;
+
+//read the passphrase from the terminal without echo
+pub fn ask_passphrase(prompt: String) -> Result<Zeroizing<String>, String> {
+    rpassword::prompt_password(prompt).map(Zeroizing::new).map_err(|s| format!("Can't read the passphrase. {}", s))
+}
+
+//read the key from the keyfile for -K, the protected one is decrypted by the prompted passphrase
+pub fn read_key_file(path: &Path) -> Result<Vec<u8>, String> {
+    let data = Zeroizing::new(read_file_to_vec(path)?);
+    if !is_protected(&data) {
+        return Ok(data.to_vec());
+    }
+    let passphrase = ask_passphrase(tr!("passphrase-prompt", file = path.display().to_string()))?;
+    unprotect(&data, passphrase.as_bytes())
+}
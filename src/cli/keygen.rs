@@ -0,0 +1,76 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use getopts::Matches;
+use zeroize::Zeroizing;
+use super::{diag, keyfile, os_arg};
+use stegegg::files::write_atomic;
+use super::options::parse_size;
+use super::i18n::tr;
+
+//default and minimal size of the generated key in bytes
+const DEFAULT_KEY_SIZE: usize = 32;
+const MIN_KEY_SIZE: usize = 16;
+
+//write the secret into the new file readable only by the owner
+pub fn write_secret(path: &Path, data: &[u8]) -> Result<(), String> {
+    write_atomic(path, |tmp| {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(tmp).and_then(|mut f| f.write_all(data)).map_err(|s| s.to_string())
+    })
+}
+
+//encrypt the key with the passphrase entered twice
+fn protect(key: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let passphrase = keyfile::ask_passphrase(tr!("passphrase-new"))?;
+    if passphrase.is_empty() {
+        return Err("Passphrase must not be empty.".to_string());
+    }
+    if *keyfile::ask_passphrase(tr!("passphrase-repeat"))? != *passphrase {
+        return Err("Passphrases don't match.".to_string());
+    }
+    stegegg::keyfile::protect(key, passphrase.as_bytes()).map(Zeroizing::new)
+}
+
+//'keygen' command. Random keyfile from the system CSPRNG for -K, much stronger than typed passwords.
+pub fn keygen(matches: &Matches) {
+
+    let path = match matches.opt_str("o") {
+        Some(p) => os_arg(&p),
+        None => { println!("Usage: stegegg keygen [--protect] -o <key file> [--size 32]"); return; },
+    };
+    let size = match matches.opt_str("size").map_or(Ok(DEFAULT_KEY_SIZE), |s| parse_size(&s)) {
+        Ok(s) if (MIN_KEY_SIZE..=1 << 20).contains(&s) => s,
+        Ok(_) => { diag::error(format!("Key size must be from {} bytes to 1MiB.", MIN_KEY_SIZE)); return; },
+        Err(s) => { diag::error(s); return; },
+    };
+
+    //overwriting a key makes everything hidden with it unreadable
+    if path.exists() && !matches.opt_present("force") {
+        diag::error(format!("Key file '{}' already exists. Use --force to overwrite it.", path.display()));
+        return;
+    }
+
+    let mut key = Zeroizing::new(vec![0; size]);
+    if let Err(s) = getrandom::fill(&mut key) {
+        diag::error(format!("Can't generate random key. {}", s));
+        return;
+    }
+
+    //key at rest encrypted by the passphrase, -K asks for it
+    let data = if matches.opt_present("protect") {
+        match protect(&key) {
+            Ok(d) => d,
+            Err(s) => { diag::error(s); return; },
+        }
+    } else {
+        key.clone()
+    };
+    match write_secret(&path, &data) {
+        Ok(_) => println!("Random key of {} bytes written to '{}'. Use it with -K and keep a backup.", size, path.display()),
+        Err(s) => diag::error(format!("Error writing '{}'. {}", path.display(), s)),
+    }
+}
@@ -0,0 +1,123 @@
+use std::cell::RefCell;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+use getopts::Matches;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use stegegg::*;
+use super::{diag, os_arg};
+use stegegg::files::write_vec_to_file;
+use super::options::{spot_layout, tile_size};
+
+//SHA256 and length of the payload, updated while the payload is read
+#[derive(Clone, Default)]
+pub struct PayloadDigest(Rc<RefCell<(Sha256, usize)>>);
+
+//reader computing the digest of the data read through it
+pub struct DigestReader<R: Read> {
+    inner: R,
+    digest: PayloadDigest,
+}
+
+impl<R: Read> DigestReader<R> {
+    pub fn new(inner: R, digest: &PayloadDigest) -> DigestReader<R> {
+        DigestReader { inner, digest: digest.clone() }
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let mut state = self.digest.0.borrow_mut();
+        state.0.update(&buf[..n]);
+        state.1 += n;
+        Ok(n)
+    }
+}
+
+//embedding parameters as (format, algorithm, ecc) given by the options
+pub fn parameters(matches: &Matches) -> (&'static str, &'static str, &'static str) {
+    let format = if matches.opt_present("sequential") {
+        "sequential"
+    } else if matches.opt_present("legacy") {
+        "legacy"
+    } else {
+        "salted"
+    };
+
+    if matches.opt_str("mode").as_deref() == Some("chunk") {
+        ("chunk", "png-chunk", "none")
+    } else if matches.opt_present("tiled") {
+        ("tiled", "lsb-tiled", "tile-replicas")
+    } else if matches.opt_present("redundant") {
+        (format, "lsb-redundant", "majority-3")
+    } else if matches.opt_present("depth") {
+        (format, "lsb-depth", "none")
+    } else if matches.opt_present("lsb-matching") {
+        (format, "lsb-matching", "none")
+    } else {
+        (format, "lsb", "none")
+    }
+}
+
+//capacity of the carrier in bytes for the given options
+fn carrier_capacity(matches: &Matches, out_filename: &Path) -> Result<usize, String> {
+    let (width, height) = image::image_dimensions(out_filename).map_err(|s| s.to_string())?;
+    options_capacity(matches, width, height)
+}
+
+//capacity in bytes of the image of given size for the given options
+pub fn options_capacity(matches: &Matches, width: u32, height: u32) -> Result<usize, String> {
+    if matches.opt_str("mode").as_deref() == Some("chunk") {
+        Ok(MAX_MSG_LEN)
+    } else if matches.opt_present("tiled") {
+        Ok(tiled::tiled_capacity(width, height, tile_size(matches)?))
+    } else {
+        let layout = spot_layout(matches)?;
+        //sequential and legacy formats have no salt, all spots are used for the message
+        if matches.opt_present("sequential") || matches.opt_present("legacy") {
+            Ok(message_capacity(layout.spots(width, height)? as usize))
+        } else {
+            Ok(capacity_for(layout, layout.spots(width, height)?))
+        }
+    }
+}
+
+//write non-secret parameters of the embedding into the JSON manifest, when it was requested
+pub fn write_manifest(matches: &Matches, in_filename: &Path, out_filename: &Path, digest: &PayloadDigest) {
+
+    let path = match matches.opt_str("manifest") {
+        Some(p) => os_arg(&p),
+        None => return,
+    };
+
+    let (format, algorithm, ecc) = parameters(matches);
+    let (sha256, payload_len) = {
+        let state = digest.0.borrow();
+        (state.0.clone().finalize().iter().map(|b| format!("{:02x}", b)).collect::<String>(), state.1)
+    };
+
+    let result = carrier_capacity(matches, out_filename).and_then(|capacity| {
+        let manifest = json!({
+            "stegegg_version": env!("CARGO_PKG_VERSION"),
+            "cover": in_filename.to_string_lossy(),
+            "output": out_filename.to_string_lossy(),
+            "format": format,
+            "algorithm": algorithm,
+            "depth": 1,
+            "ecc": ecc,
+            "context": matches.opt_present("context") || matches.opt_present("salt-from-name"),
+            "payload_bytes": payload_len,
+            "payload_sha256": sha256,
+            "capacity_bytes": capacity,
+            "capacity_used": if capacity > 0 { payload_len as f64 / capacity as f64 } else { 0.0 },
+        });
+        write_vec_to_file(&path, serde_json::to_string_pretty(&manifest).unwrap().as_bytes())
+    });
+
+    match result {
+        Ok(_) => println!("Manifest written to '{}'.", path.display()),
+        Err(s) => diag::error(format!("Error writing manifest '{}'. {}", path.display(), s)),
+    }
+}
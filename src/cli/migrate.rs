@@ -0,0 +1,47 @@
+use std::io::Cursor;
+use getopts::Matches;
+use zeroize::Zeroizing;
+use stegegg::*;
+use super::{diag, key_context, os_arg, output_name};
+use stegegg::files::open_image;
+use super::pixel::hide_file;
+
+//extract the message hidden in the legacy format (stegegg 0.1, no salt, all spots used for the data)
+//and hide it again into the same image in the current format with the random per-message salt
+pub fn migrate(matches: &Matches, in_filename: Option<&String>, out_filename: Option<&String>, key: &[u8]) {
+
+    let (in_filename, out_filename) = match (in_filename, out_filename) {
+        (Some(i), Some(o)) => (os_arg(i), os_arg(o)),
+        _ => { println!("Usage: stegegg migrate -k <key> <old image> <new image>"); return; },
+    };
+    if matches.opt_present("legacy") {
+        diag::error("Option --legacy can't be used with 'migrate'. The input is always read in the legacy format.");
+        return;
+    }
+    let out_filename = match output_name(matches, &in_filename, &out_filename) {
+        Ok(f) => f,
+        Err(s) => { diag::error(s); return; },
+    };
+
+    //legacy spots are shuffled by the key only
+    let result = key_context(matches, &in_filename).and_then(|context| {
+        let rgb_img = &open_image(&in_filename)?.into_rgb8();
+        let layout = SpotLayout { redundant: matches.opt_present("redundant"), depth: DEFAULT_DEPTH };
+        let mut prng_state = init_prng(key, &[], &context);
+        let indices = hidding_spots(rgb_img, layout, 0, &mut prng_state)?;
+        //legacy images are older than the trailer, but the ones hidden with --legacy later have it
+        let msg_len = read_header(rgb_img, layout, &indices)?;
+        verify_legacy_payload(rgb_img, layout, &indices, msg_len)?;
+        Ok(Zeroizing::new(extract_bytes(rgb_img, layout, &indices[3 * 8..], 0, msg_len)))
+    });
+    let msg = match result {
+        Ok(m) => m,
+        Err(s) => { diag::error(format!("Can't extract the legacy message from '{}'. {}", in_filename.display(), s)); return; },
+    };
+    println!("Legacy message with {} bytes extracted.", msg.len());
+
+    match hide_file(matches, &in_filename, &out_filename, key, &mut Cursor::new(msg.as_slice()), Some(msg.len())) {
+        Ok(_) => diag::log("Migration finished."),
+        Err(s) => diag::error(s),
+    }
+}
@@ -0,0 +1,388 @@
+use std::{env, io::BufReader, io::Cursor, io::Read};
+use std::fs::File;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use getopts::{Matches, Options};
+use image::ImageFormat;
+use stegegg::*;
+use stegegg::files::{read_file_to_vec, write_vec_to_file};
+use manifest::{DigestReader, PayloadDigest};
+use zeroize::Zeroizing;
+use i18n::tr;
+use options::parse_size;
+use pixel::output_format;
+
+//Command line of stegegg. The submodules parse the options into the calls of the library and print the results,
+//main.rs only dispatches the commands and modes to them.
+
+pub mod analyze;
+pub mod archive;
+pub mod attack;
+pub mod banded;
+pub mod batch;
+pub mod chunk;
+pub mod color;
+pub mod dataset;
+pub mod diag;
+pub mod estimate;
+pub mod export;
+pub mod features;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod i18n;
+pub mod info;
+pub mod inspect;
+pub mod keyfile;
+pub mod keygen;
+pub mod manifest;
+pub mod migrate;
+pub mod nest;
+pub mod openpgp;
+pub mod options;
+pub mod pixel;
+pub mod polyglot;
+pub mod probe;
+pub mod preprocess;
+pub mod pubkey;
+pub mod rank;
+pub mod report;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod spectrogram;
+pub mod split;
+pub mod stats;
+pub mod wav;
+
+//data looks binary when it contains invalid UTF-8 or control characters other than whitespace
+pub fn looks_binary(data: &[u8]) -> bool {
+
+    //multi-byte character cut at the end of the data is fine
+    let invalid = match std::str::from_utf8(data) {
+        Ok(_) => false,
+        Err(e) => e.error_len().is_some(),
+    };
+    invalid || data.iter().any(|&b| (b < 0x20 && !b"\t\n\r".contains(&b)) || b == 0x7f)
+}
+
+//marker of the command line arguments which aren't valid UTF-8. getopts takes only strings, so such arguments
+//(file names on some filesystems) are parsed as the marker followed by their position and resolved by os_arg.
+const NON_UTF8_ARG: char = '\u{fffe}';
+
+//command line arguments for getopts
+pub fn utf8_args() -> Vec<String> {
+    env::args_os().enumerate().map(|(i, a)| a.into_string().unwrap_or_else(|_| format!("{}{}", NON_UTF8_ARG, i))).collect()
+}
+
+//command line for the log, values of the key and message options are left out
+pub fn logged_args() -> String {
+    const SECRET: [&str; 5] = ["-k", "--key", "-m", "--message", "--inner-key"];
+    let mut args = Vec::new();
+    let mut secret_value = false;
+    for a in &utf8_args()[1..] {
+        args.push(if secret_value {
+            "<hidden>".to_string()
+        } else if let Some((name, _)) = a.split_once('=').filter(|(n, _)| SECRET.contains(n)) {
+            format!("{}=<hidden>", name)
+        } else if a.len() > 2 && (a.starts_with("-k") || a.starts_with("-m")) {
+            format!("{}<hidden>", &a[..2])
+        } else {
+            a.clone()
+        });
+        secret_value = !secret_value && SECRET.contains(&a.as_str());
+    }
+    args.join(" ")
+}
+
+//path given by the argument, non UTF-8 arguments are taken from the original command line
+pub fn os_arg(arg: &str) -> PathBuf {
+    arg.strip_prefix(NON_UTF8_ARG)
+        .and_then(|i| i.parse::<usize>().ok())
+        .and_then(|i| env::args_os().nth(i))
+        .map_or_else(|| PathBuf::from(arg), PathBuf::from)
+}
+
+//get message / data source from the user. Files and stdin ("-") are streamed into the image.
+//Digest of the read data is recorded for the manifest. Message for the recipients is encrypted as a whole.
+pub fn message_source(matches: &Matches, digest: &PayloadDigest) -> Result<(Box<dyn Read>, Option<usize>), String> {
+    //repeated -m and -M are concatenated in the order of the command line
+    let mut parts: Vec<(usize, bool, String)> = matches.opt_strs_pos("m").into_iter().map(|(p, m)| (p, false, m))
+        .chain(matches.opt_strs_pos("M").into_iter().map(|(p, f)| (p, true, f)))
+        .collect();
+    parts.sort_by_key(|p| p.0);
+    if parts.iter().filter(|(_, file, f)| *file && f == "-").count() > 1 {
+        return Err("Standard input ('-') can be used only once as the message file.".to_string());
+    }
+
+    let (mut reader, len): (Box<dyn Read>, Option<usize>) = if let Some(size) = matches.opt_str("random-payload") {
+        if !parts.is_empty() {
+            return Err("Option --random-payload can't be used together with -m or -M.".to_string());
+        }
+        let len = parse_size(&size)?;
+        if len > MAX_MSG_LEN {
+            return Err(format!("Random payload can have at most {} bytes.", MAX_MSG_LEN));
+        }
+        let mut data = vec![0; len];
+        getrandom::fill(&mut data).map_err(|s| format!("Can't generate random payload. {}", s))?;
+        (Box::new(DigestReader::new(Cursor::new(data), digest)), Some(len))
+
+    } else if matches.opt_present("archive") {
+        if parts.iter().any(|(_, file, f)| !*file || f == "-") || parts.is_empty() {
+            return Err("Option --archive packs the files and directories given by -M, it can't be used with -m or the standard input.".to_string());
+        }
+        let archive = archive::pack(matches)?;
+        let len = archive.len();
+        (Box::new(DigestReader::new(Cursor::new(archive), digest)), Some(len))
+
+    } else if !parts.is_empty() {
+        let mut reader: Box<dyn Read> = Box::new(std::io::empty());
+        let mut len = Some(0);
+        for (_, file, part) in parts {
+            let (next, part_len): (Box<dyn Read>, Option<usize>) = if !file {
+                let part_len = part.len();
+                (Box::new(Cursor::new(part.into_bytes())), Some(part_len))
+            } else if part == "-" {
+                (Box::new(std::io::stdin().lock()), None)
+            } else {
+                let path = os_arg(&part);
+                match File::open(&path) {
+                    Ok(f) => {
+                        let part_len = f.metadata().ok().map(|m| m.len() as usize);
+                        (Box::new(BufReader::new(f)), part_len)
+                    },
+                    Err(s) => return Err(format!("Can't open message file '{}'. {}", path.display(), s)),
+                }
+            };
+            reader = Box::new(reader.chain(next));
+            len = len.zip(part_len).map(|(a, b)| a + b);
+        }
+        (Box::new(DigestReader::new(reader, digest)), len)
+
+    } else {
+        return Err(tr!("message-not-specified"));
+    };
+
+    let (recipients, ssh_recipients) = (matches.opt_strs("recipient"), matches.opt_strs("recipient-ssh"));
+    let pgp_recipients = matches.opt_strs("pgp-recipient");
+    if recipients.is_empty() && ssh_recipients.is_empty() && pgp_recipients.is_empty() {
+        return Ok((reader, len));
+    }
+    if !pgp_recipients.is_empty() && (!recipients.is_empty() || !ssh_recipients.is_empty()) {
+        return Err("Option --pgp-recipient can't be used together with the age recipients.".to_string());
+    }
+
+    let mut msg = Zeroizing::new(Vec::new());
+    reader.read_to_end(&mut msg).map_err(|s| s.to_string())?;
+    let encrypted = if pgp_recipients.is_empty() {
+        pubkey::encrypt(&msg, &recipients, &ssh_recipients)?
+    } else {
+        openpgp::encrypt(&msg, &pgp_recipients)?
+    };
+    let len = encrypted.len();
+    println!("Message of {} encrypted to {} for the recipients.", human_size(msg.len()), human_size(len));
+    Ok((Box::new(Cursor::new(encrypted)), Some(len)))
+}
+
+//decrypt the extracted output file in place with the identity files or gpg, if it was requested
+pub fn decrypt_output(matches: &Matches, out_filename: &Path) -> Result<(), ExtractError> {
+    let identities = matches.opt_strs("i");
+    if identities.is_empty() && !matches.opt_present("pgp-decrypt") {
+        return Ok(());
+    }
+
+    let data = read_file_to_vec(out_filename)?;
+    let msg = if identities.is_empty() {
+        openpgp::decrypt(&data)
+    } else {
+        pubkey::decrypt(&data, &identities)
+    };
+    let msg = Zeroizing::new(msg.map_err(ExtractError::NoPayload)?);
+    write_vec_to_file(out_filename, &msg)?;
+    println!("{}", tr!("message-decrypted", count = msg.len()));
+    Ok(())
+}
+
+//context mixed into the key. Name of the carrier is the output on hiding and the input on extraction.
+pub fn key_context<P: AsRef<Path>>(matches: &Matches, carrier: P) -> Result<Vec<u8>, String> {
+    if let Some(c) = matches.opt_str("context") {
+        if matches.opt_present("salt-from-name") {
+            return Err("Options --context and --salt-from-name can't be used together.".to_string());
+        }
+        Ok(Vec::from(c.as_bytes()))
+
+    } else if matches.opt_present("salt-from-name") {
+        match carrier.as_ref().file_name() {
+            Some(n) => Ok(Vec::from(n.as_encoded_bytes())),
+            None => Err(format!("Can't get file name from '{}'.", carrier.as_ref().display())),
+        }
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+//extract with the key derivation of the options and, when nothing is found and --kdf isn't given, with the SHA-256
+//derivation which was the default of the older versions, so their carriers need no --kdf
+pub fn extract_any_kdf<T>(opts: &Options, matches: &Matches, key: &[u8], extract: impl Fn(&Matches, &[u8]) -> Result<T, ExtractError>) -> Result<T, ExtractError> {
+    let result = extract(matches, key);
+    if ["kdf", "auto", "legacy", "sequential", "seed"].iter().any(|o| matches.opt_present(o)) || !matches!(result, Err(ExtractError::NoPayload(_))) {
+        return result;
+    }
+    let args: Vec<String> = utf8_args()[1..].iter().cloned().chain(["--kdf".to_string(), "sha256".to_string()]).collect();
+    let legacy = opts.parse(&args).map_err(|s| ExtractError::Failed(s.to_string()))?;
+    match extract(&legacy, key) {
+        Err(ExtractError::NoPayload(_)) => result,
+        Ok(v) => {
+            diag::hint("the message was hidden with the SHA-256 key derivation (by an older version or the raw engine), give --kdf sha256 to skip scrypt.");
+            Ok(v)
+        },
+        other => other,
+    }
+}
+
+//check the label of --require-label before anything is extracted. Messages without the trailer have no label.
+pub fn require_label(matches: &Matches, trailer: Option<&Trailer>) -> Result<(), ExtractError> {
+    match (matches.opt_str("require-label"), trailer) {
+        (Some(label), Some(t)) => t.require_label(&label),
+        (Some(label), None) => Err(ExtractError::Failed(format!("Message has no label, '{}' is required.", label))),
+        (None, _) => Ok(()),
+    }
+}
+
+//refuse to overwrite the input and pick the output name which doesn't overwrite an existing file
+//("out-1.png", "out-2.png", ..) unless --force is given. Resumed extraction continues in the existing file.
+pub fn output_name(matches: &Matches, in_filename: &Path, out_filename: &Path) -> Result<PathBuf, String> {
+
+    let out = out_filename;
+    if out_filename == Path::new("-") || in_filename.is_dir() || (matches.opt_present("x") && matches.opt_present("archive")) {
+        return Ok(out_filename.to_path_buf());
+    }
+
+    if let (Ok(i), Ok(o)) = (std::fs::canonicalize(in_filename), std::fs::canonicalize(out)) {
+        if i == o {
+            return Err(tr!("same-file", file = in_filename.display().to_string()));
+        }
+    }
+
+    if !out.exists() || matches.opt_present("force") || matches.opt_present("resume") {
+        return Ok(out_filename.to_path_buf());
+    }
+
+    for i in 1.. {
+        let mut name = out.file_stem().unwrap_or_default().to_os_string();
+        name.push(format!("-{}", i));
+        if let Some(ext) = out.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let candidate = out.with_file_name(name);
+        if !candidate.exists() {
+            println!("{}", tr!("output-exists", file = out_filename.display().to_string(), other = candidate.display().to_string()));
+            return Ok(candidate);
+        }
+    }
+    unreachable!()
+}
+
+//output of the --in-place hiding is the cover itself. It is replaced only by the output of the same format,
+//optionally keeping the original in <file>.bak.
+fn in_place_output(matches: &Matches, in_filename: &Path) -> Result<PathBuf, String> {
+    if matches.opt_present("x") {
+        return Err("Option --in-place can't be used for the extraction.".to_string());
+    }
+    if matches.free.len() > 1 {
+        return Err("Output file can't be given together with --in-place.".to_string());
+    }
+    if in_filename.is_dir() {
+        return Err("Option --in-place needs a single image, not a directory.".to_string());
+    }
+
+    let format = if matches.opt_str("mode").as_deref() == Some("chunk") { ImageFormat::Png } else { output_format(matches, in_filename, Some(in_filename))? };
+    let cover = image::io::Reader::open(in_filename).map_err(|s| s.to_string())?.with_guessed_format().map_err(|s| s.to_string())?.format();
+    if cover != Some(format) {
+        return Err(format!("'{}' can't be modified in place, the output would be {:?}. Only lossless covers (PNG, BMP, TIFF) keep their format.", in_filename.display(), format));
+    }
+
+    if matches.opt_present("backup") {
+        let mut backup = OsString::from(in_filename);
+        backup.push(".bak");
+        std::fs::copy(in_filename, &backup).map_err(|s| format!("Can't make the backup '{}'. {}", Path::new(&backup).display(), s))?;
+        println!("Original image kept in '{}'.", Path::new(&backup).display());
+    }
+    Ok(in_filename.to_path_buf())
+}
+
+//number of attempts to enter the prompted key on extraction
+pub const KEY_ATTEMPTS: usize = 3;
+
+//read the key from the terminal without echo
+pub fn ask_key() -> Result<Vec<u8>, String> {
+    match rpassword::prompt_password(tr!("key-prompt")) {
+        Ok(k) => Ok(k.into_bytes()),
+        Err(s) => Err(format!("Can't read the key. {}", s)),
+    }
+}
+
+//lock the memory pages holding the secret, so they are not swapped out. Warns and continues when the platform forbids it.
+pub fn lock_secret(data: &[u8]) -> Option<region::LockGuard> {
+    if data.is_empty() {
+        return None;
+    }
+    match region::lock(data.as_ptr(), data.len()) {
+        Ok(guard) => Some(guard),
+        Err(s) => { diag::warning(tr!("key-not-locked", error = s.to_string())); None },
+    }
+}
+
+//log the result and print the error. Failed extraction exits with 2 when there is no message for the key and with 1 on other errors.
+pub fn report_result(result: Result<(), ExtractError>, extract: bool) {
+    let action = if extract { "Extraction" } else { "Hiding" };
+    let e = match result {
+        Ok(_) => { diag::log(format!("{} finished.", action)); return; },
+        Err(e) => e,
+    };
+    match &e {
+        ExtractError::NoPayload(s) => diag::error(tr!("no-payload", detail = s.as_str())),
+        e => diag::error(e),
+    }
+    diag::log(format!("{} failed.", action));
+    std::process::exit(if extract && matches!(e, ExtractError::NoPayload(_)) { 2 } else { 1 });
+}
+
+
+//get key from the user or use empty one if not specified
+pub fn user_key(matches: &Matches) -> Result<Vec<u8>, String> {
+
+    //seed replaces the key, both would give a false sense of security
+    if matches.opt_present("seed") && ["k", "K", "ask-key"].iter().any(|o| matches.opt_present(o)) {
+        return Err("Option --seed can't be used together with -k, -K or --ask-key.".to_string());
+    }
+
+    if matches.opt_present("ask-key") {
+        if matches.opt_present("k") || matches.opt_present("K") {
+            return Err("Option --ask-key can't be used together with -k or -K.".to_string());
+        }
+        ask_key()
+    } else if let Some(k) = matches.opt_str("k") {
+        Ok(k.into_bytes())
+    } else if let Some(file_path) = matches.opt_str("K") {
+        keyfile::read_key_file(&os_arg(&file_path))
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+//output file name. In-place output replaces the input through the temporary file.
+pub fn output_file(matches: &Matches, in_filename: &Path) -> Result<PathBuf, String> {
+    if matches.opt_present("in-place") {
+        return in_place_output(matches, in_filename);
+    }
+
+    //archive may be unpacked into --out-dir instead of the output argument
+    let out_filename = match (matches.free.get(1), matches.opt_str("out-dir")) {
+        (Some(_), Some(_)) => return Err("Output directory is given both by --out-dir and by the output argument.".to_string()),
+        (None, Some(d)) if matches.opt_present("x") && matches.opt_present("archive") => os_arg(&d),
+        (None, Some(_)) => return Err("Option --out-dir is the directory the archive is unpacked into, it needs -x --archive.".to_string()),
+        (Some(f), None) => os_arg(f),
+        (None, None) => return Err(tr!("output-not-specified")),
+    };
+    output_name(matches, in_filename, &out_filename)
+}
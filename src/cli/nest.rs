@@ -0,0 +1,72 @@
+use std::io::{Cursor, Read};
+use getopts::Matches;
+use zeroize::Zeroizing;
+use stegegg::*;
+use super::{diag, message_source, os_arg, output_name};
+use stegegg::files::read_file_to_vec;
+use super::options::spot_layout;
+use super::pixel::hide_file;
+use super::manifest::PayloadDigest;
+
+//message bytes which fit into the outer cover with the layout of the options
+fn outer_capacity(matches: &Matches, width: u32, height: u32) -> Result<usize, String> {
+    let layout = spot_layout(matches)?;
+    //sequential and legacy formats have no salt, all spots are used for the message
+    if matches.opt_present("sequential") || matches.opt_present("legacy") {
+        Ok(message_capacity(layout.spots(width, height)? as usize))
+    } else {
+        Ok(capacity_for(layout, layout.spots(width, height)?))
+    }
+}
+
+//hide the message into the inner cover with the inner key and the resulting PNG into the outer cover with the outer key.
+//Inner layer uses the default salted format, options of hiding apply to the outer layer.
+pub fn nest(matches: &Matches, args: &[String], outer_key: &[u8]) {
+
+    let (inner_cover, outer_cover, out_filename) = match args {
+        [i, o, out] => (os_arg(i), os_arg(o), os_arg(out)),
+        _ => { println!("Usage: stegegg nest -k <outer key> --inner-key <key> -m|-M <message> <inner cover> <outer cover> <output>"); return; },
+    };
+    let inner_key = match matches.opt_str("inner-key") {
+        Some(k) => Zeroizing::new(k.into_bytes()),
+        None => { diag::error("Option --inner-key is required for 'nest'."); return; },
+    };
+    let out_filename = match output_name(matches, &outer_cover, &out_filename) {
+        Ok(f) => f,
+        Err(s) => { diag::error(s); return; },
+    };
+
+    //inner layer is kept in memory only
+    let result = message_source(matches, &PayloadDigest::default()).and_then(|(mut reader, _)| {
+        let mut msg = Zeroizing::new(Vec::new());
+        reader.read_to_end(&mut msg).map_err(|s| s.to_string())?;
+        let cover = read_file_to_vec(&inner_cover).map_err(|s| format!("Can't read '{}'. {}", inner_cover.display(), s))?;
+        let (width, height, capacity) = buffer::image_capacity(&cover)?;
+        if msg.len() > capacity {
+            return Err(format!("Message has {} bytes, the inner cover {}x{} holds only {} bytes.", msg.len(), width, height, capacity));
+        }
+        buffer::embed_image(&cover, &inner_key, &msg)
+    });
+    let inner = match result {
+        Ok(i) => i,
+        Err(s) => { diag::error(format!("Inner layer failed. {}", s)); return; },
+    };
+
+    //the inner stego image is the message of the outer layer
+    let capacity = match image::image_dimensions(&outer_cover).map_err(|s| format!("Can't read '{}'. {}", outer_cover.display(), s))
+        .and_then(|(width, height)| outer_capacity(matches, width, height)) {
+        Ok(c) => c,
+        Err(s) => { diag::error(s); return; },
+    };
+    println!("Inner stego image has {} bytes, the outer cover holds {} bytes.", inner.len(), capacity);
+    if inner.len() > capacity {
+        diag::error("Inner stego image doesn't fit into the outer cover.");
+        diag::hint("use a smaller inner cover or a larger outer cover.");
+        return;
+    }
+
+    match hide_file(matches, &outer_cover, &out_filename, outer_key, &mut Cursor::new(&inner), Some(inner.len())) {
+        Ok(_) => diag::log("Nested hiding finished."),
+        Err(s) => diag::error(format!("Outer layer failed. {}", s)),
+    }
+}
@@ -0,0 +1,48 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+//OpenPGP encryption of the payload by the gpg invocation, so the extracted message can go into the existing
+//PGP workflows. gpg (or the program in the STEGEGG_GPG variable) must be installed, keys come from its keyring.
+
+//run gpg with the arguments, feed the data into its stdin and return its stdout
+fn run_gpg(args: &[&str], data: &[u8]) -> Result<Vec<u8>, String> {
+    let program = std::env::var("STEGEGG_GPG").unwrap_or_else(|_| "gpg".to_string());
+    let mut child = Command::new(&program)
+        .args(["--batch", "--yes", "--quiet", "--output", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|s| format!("Can't run '{}'. {}", program, s))?;
+
+    //stdin is written from the other thread, so gpg never blocks on the full stdout pipe
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    let mut out = Vec::with_capacity(data.len() + 1024);
+    let written = std::thread::scope(|s| {
+        let writer = s.spawn(move || stdin.write_all(data));
+        let read = stdout.read_to_end(&mut out);
+        writer.join().unwrap().and(read)
+    });
+
+    let status = child.wait().map_err(|s| s.to_string())?;
+    if !status.success() {
+        return Err(format!("'{}' failed ({}).", program, status));
+    }
+    written.map_err(|s| s.to_string())?;
+    Ok(out)
+}
+
+//encrypt the data to the OpenPGP recipients (key id, fingerprint or user id in the gpg keyring)
+pub fn encrypt(data: &[u8], recipients: &[String]) -> Result<Vec<u8>, String> {
+    let mut args = vec!["--encrypt"];
+    for r in recipients {
+        args.extend(["--recipient", r.as_str()]);
+    }
+    run_gpg(&args, data).map_err(|s| format!("OpenPGP encryption failed. {}", s))
+}
+
+//decrypt the OpenPGP message with the secret keys from the gpg keyring
+pub fn decrypt(data: &[u8]) -> Result<Vec<u8>, String> {
+    run_gpg(&["--decrypt"], data).map_err(|s| format!("Can't decrypt the OpenPGP message. {}", s))
+}
@@ -0,0 +1,326 @@
+use std::path::Path;
+use getopts::{Matches, Options};
+use stegegg::*;
+use super::preprocess;
+
+//options of the command line, listed by --help
+pub fn command_line() -> Options {
+    let mut opts = Options::new();
+    opts.optflag("b","bmp", "Output image in BMP format.");
+    opts.optflag("", "strict-color", "Refuse covers whose conversion to 8-bit RGB loses information (16-bit or float samples, dropped alpha).");
+    opts.optflag("", "drop-alpha", "Don't put the alpha channel of the cover back into the output image.");
+    opts.optflag("", "use-alpha", "Hide into the alpha channel of the cover too. Must be used for both hiding and extraction.");
+    opts.optopt("", "format", "Output image format. Default is the format of the output file name or the cover (when lossless), otherwise PNG.", "png|bmp|tiff");
+    opts.optflag("x","extract", "Extract message from the image. Requires correct key.");
+    opts.optopt("k", "key", "Key for embedding or extracting data.", "");
+    opts.optopt("K", "key-file", "Key file for embedding or extracting data.", "");
+    opts.optopt("o", "output", "Output key file of 'keygen'.", "FILE");
+    opts.optopt("", "size", "Size of the key generated by 'keygen' in bytes (default 32).", "SIZE");
+    opts.optflag("", "protect", "Encrypt the key generated by 'keygen' with the passphrase.");
+    opts.optmulti("m", "message", "Data / message to hide into the image. Repeated -m and -M are concatenated in order.", "");
+    opts.optmulti("M", "message-file", "File with data / message to hide into the image. Use '-' for stdin.", "");
+    opts.optopt("", "random-payload", "Hide the given number of random bytes (e.g. 10k) instead of the message. For decoys and benchmarks.", "SIZE");
+    opts.optopt("d", "depth", "Bits used in each channel on hiding, N (1 to 4) for all channels or e.g. r:1,g:0,b:2 (0 to 8, default 1). Recorded in the image.", "N|r:N,g:N,b:N");
+    opts.optopt("", "resize", "Resize the cover before hiding, e.g. 4000x3000, or 4000x keeping the aspect ratio.", "WxH");
+    opts.optopt("", "crop", "Crop the cover before hiding, WxH from the center or WxH+X+Y.", "WxH[+X+Y]");
+    opts.optflag("", "denoise", "Remove the noise of the cover by the median filter before hiding.");
+    opts.optopt("", "add-grain", "Add random grain of the given amplitude (1 to 16) to the cover before hiding.", "N");
+    opts.optopt("", "lsb-plane", "With 'analyze', write the LSB plane of the image stretched to black and white for the visual inspection.", "FILE");
+    opts.optflag("", "lsb-matching", "Change the samples by +-1 in the random direction instead of replacing their LSBs. Extraction is unchanged.");
+    opts.optflag("", "restore-histogram", "After hiding, adjust unused pixels to restore the original per-channel histogram.");
+    opts.optopt("", "payload-size", "Size of the payload used by the 'rank' command, in bytes or with a unit (500k, 2MiB).", "SIZE");
+    opts.optopt("", "report-format", "Format of the 'capacity-report' output. 'csv' (default) or 'json'.", "FORMAT");
+    opts.optflagopt("", "stats", "After hiding, print how many samples were changed, per channel and relative to the capacity. Use --stats=json for JSON.", "text|json");
+    opts.optflag("", "check-cover", "Before hiding, check the LSB statistics of the cover and warn when it may already contain hidden data.");
+    opts.optflag("", "detectability", "After hiding, run the steganalysis detectors on the output and print the detectability score (0 to 1).");
+    opts.optopt("", "max-fill", "Refuse to use more than this fraction of the capacity (e.g. 0.3 or 30%). Also used by 'estimate'.", "RATE");
+    opts.optopt("", "max-detectability", "Refuse to write the output when its detectability score is above the value (0 to 1).", "SCORE");
+    opts.optopt("", "inner-key", "Key of the inner layer for the 'nest' command.", "KEY");
+    opts.optflag("", "parity", "With 'split', hide the XOR of the other shards into the last cover, so the message is joined even when any single carrier is lost.");
+    opts.optopt("", "polyglot-zip", "Append the ZIP archive to the output PNG, so the output is also a valid ZIP file.", "ARCHIVE");
+    opts.optopt("", "cost-map", "Write the embedding cost of each cover pixel as a grayscale PNG, bright in the flat areas where LSB changes are easy to detect. On hiding and with 'analyze'.", "FILE");
+    opts.optopt("", "embed-map", "Write map of modified samples. PNG mask or coordinate list when the file ends with .txt or .csv.", "FILE");
+    opts.optflag("", "legacy", "Use legacy format without the random per-message salt (images created by stegegg 0.1).");
+    opts.optopt("", "seed", "Insecure. Shuffle the spots by this number (e.g. 0x2a) instead of the key, for reproducible research and tests.", "N");
+    opts.optflag("", "auto", "On extraction try the layouts (--redundant, --kdf scrypt, --legacy, --sequential, --salt-from-name) until the header matches the key.");
+    opts.optopt("", "kdf", "Key derivation of the spot order and the encryption key. 'scrypt' (default), which makes guessing the key slow, or 'sha256' of the older carriers. Extraction tries 'sha256' when nothing is found with 'scrypt'.", "KDF");
+    opts.optflag("", "archive", "Pack the -M files and directories with their names into an archive. With -x the output is a directory the files are unpacked into.");
+    opts.optmulti("", "entry", "With -x --archive unpack only the NAME file or the files in the NAME directory of the archive. Can be given more times.", "NAME");
+    opts.optopt("", "out-dir", "With -x --archive unpack the files into the DIR directory, instead of the output argument.", "DIR");
+    opts.optflag("", "flatten", "With -x --archive unpack all files right into the output directory, without their directories.");
+    opts.optflag("", "compress", "Compress the message before hiding, when it gets smaller. Extraction decompresses it automatically.");
+    opts.optopt("", "codec", "Codec of --compress: deflate, lz4, brotli or auto (default), which picks the best one by compressing a sample of the message.", "NAME");
+    opts.optflag("", "encrypt", "Encrypt and authenticate the message with ChaCha20-Poly1305 by the key, the default when the key is given (except --legacy, --tiled, --banded, --low-memory and the chunk mode). Extraction decrypts it automatically.");
+    opts.optflag("", "no-encrypt", "Hide the plain message, so it can be extracted with --range or --resume.");
+    opts.optflag("", "sequential", "Low security. Use spots in plain raster order without the key and salt, as most simple LSB tools do.");
+    opts.optflag("", "salt-from-name", "Mix the carrier file name (output on hiding, input on extraction) into the key.");
+    opts.optopt("", "context", "Mix the context string into the key.", "STRING");
+    opts.optflag("", "redundant", "Store each bit in all three channels of the pixel and majority vote on extraction. Must be used for both hiding and extraction.");
+    opts.optopt("", "confidence-file", "Write per-byte confidence of the redundant extraction as CSV.", "FILE");
+    opts.optflag("", "resume", "Record extraction progress in <output>.resume and continue the interrupted extraction.");
+    opts.optopt("", "range", "Extract only LEN bytes of the message starting at OFFSET. Sizes can have a unit (e.g. 1MiB:64k).", "OFFSET:LEN");
+    opts.optflag("", "banded", "Process PNG image in row bands instead of loading it whole. The shuffled spot list still takes 4 bytes per spot, use --low-memory to bound the memory.");
+    opts.optflag("", "low-memory", "Use the strategies with the smallest memory footprint for constrained devices: the banded processing and the spot order computed for each spot. Extraction needs the option too.");
+    opts.optflag("", "ask-key", "Prompt for the key without echo. Extraction asks again (up to 3 attempts) when the key is wrong.");
+    opts.optmulti("", "recipient", "Encrypt the message to the age recipient (age1...). Can be given multiple times.", "RECIPIENT");
+    opts.optmulti("", "recipient-ssh", "Encrypt the message to the SSH public keys in the file (e.g. ~/.ssh/id_ed25519.pub). Can be given multiple times.", "FILE");
+    opts.optmulti("i", "identity", "Decrypt the extracted message with the age identity file or SSH private key. Can be given multiple times.", "FILE");
+    opts.optmulti("", "pgp-recipient", "Encrypt the message to the OpenPGP recipient from the gpg keyring (key id, fingerprint or user id). Can be given multiple times.", "USER");
+    opts.optflag("", "pgp-decrypt", "Decrypt the extracted OpenPGP message with gpg.");
+    opts.optopt("", "manifest", "Write non-secret parameters of the embedding (format, algorithm, payload digest, capacity used) as JSON.", "FILE");
+    opts.optflag("", "in-place", "Hide the message into the input image and replace it (only lossless covers keeping their format).");
+    opts.optflag("", "backup", "Keep the original image in <input>.bak when --in-place is used.");
+    opts.optflag("", "force", "Overwrite the existing output file instead of writing into a new one.");
+    opts.optflag("", "force-binary", "Write binary extracted data to stdout (output '-') even when it is a terminal.");
+    opts.optflag("", "lock-memory", "Lock the key in memory (mlock / VirtualLock), so it is never written into the swap.");
+    opts.optflag("", "optimize", "Losslessly optimize the output PNG (oxipng) so it looks like a typical optimized PNG.");
+    opts.optopt("", "label", "Store the TEXT label (up to 255 bytes) with the message, it is checked by --require-label and printed by inspect-header. It isn't encrypted.", "TEXT");
+    opts.optopt("", "require-label", "Extract only the message with the TEXT label, so the wrong carrier or key isn't used by mistake.", "TEXT");
+    opts.optopt("", "max-extract-size", "Refuse to extract messages larger than SIZE (e.g. 10MiB), so garbage images can't make large allocations.", "SIZE");
+    opts.optopt("", "threads", "Number of threads for the PNG compression, 'dataset', 'serve' and 'grpc' (default: all cores).", "N");
+    opts.optopt("", "listen", "Address of the 'serve' HTTP API (default 127.0.0.1:8080) or the 'grpc' service (default 127.0.0.1:50051).", "ADDR");
+    opts.optopt("", "rates", "Payload rates in bits per color sample for the 'dataset' command (default 0.05,0.1,0.2,0.4).", "LIST");
+    opts.optopt("", "algorithms", "Embedding algorithms for the 'dataset' command. 'lsb' (default) or 'redundant'.", "LIST");
+    opts.optopt("", "mode", "Where to hide the message. 'pixel' (default) or 'chunk' for ancillary PNG chunks, which leave the pixels untouched but are easy to find.", "MODE");
+    opts.optflag("", "tiled", "Crop-resilient mode. Message is split into fragments replicated over image tiles. Must be used for both hiding and extraction.");
+    opts.optopt("", "tile-size", "Tile size in pixels for the --tiled mode (default 64).", "N");
+    opts.optopt("", "ops", "Operations for the 'attack' command: resize:F, jpeg:Q, crop:PX, noise:A, rotate:DEG, zoom:F, blur:S, png.", "LIST");
+    opts.optopt("", "log-file", "Append verbose diagnostics (parameters, timings, per-file batch results) to the file.", "FILE");
+    opts.optflag("", "no-color", "Don't color the error, warning and hint labels (also when NO_COLOR is set or the output isn't a terminal).");
+    opts.optflag("h","help", "Print this help and exit.");
+    opts
+}
+
+//size in bytes given as a number with an optional unit. k, M and G are decimal (500k = 500000 bytes),
+//KiB, MiB and GiB binary (2MiB = 2097152 bytes). Units are case insensitive, "B" and "bytes" can be left out.
+pub fn parse_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let invalid = || format!("Invalid size '{}'. Use bytes or units k, M, G, KiB, MiB, GiB (e.g. 500k, 2MiB).", size);
+    let split = size.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let multiplier: u64 = match unit.trim().to_lowercase().trim_end_matches("bytes").trim_end_matches('b') {
+        "" => 1,
+        "k" => 1_000,
+        "m" => 1_000_000,
+        "g" => 1_000_000_000,
+        "ki" => 1 << 10,
+        "mi" => 1 << 20,
+        "gi" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+    match number.parse::<f64>().map(|n| n * multiplier as f64) {
+        //usize::MAX as f64 rounds up to 2^64, which doesn't fit
+        Ok(n) if (0.0..usize::MAX as f64).contains(&n) => Ok(n as usize),
+        _ => Err(invalid()),
+    }
+}
+
+//layout of the hidding spots from --redundant and --depth ("r:1,g:0,b:2", channels not given keep 1 bit,
+//or "N" for N bits in all channels)
+pub fn spot_layout(matches: &Matches) -> Result<SpotLayout, String> {
+    let mut depth = DEFAULT_DEPTH;
+    if let Some(d) = matches.opt_str("depth") {
+        let invalid = || format!("Invalid depth '{}'. Expected 1 to 4 bits in all channels or e.g. 'r:1,g:0,b:2' with 0 to 8 bits per channel.", d);
+        if let Ok(bits) = d.trim().parse::<u8>() {
+            let bits = Some(bits).filter(|b| (1..=4).contains(b)).ok_or_else(invalid)?;
+            return Ok(SpotLayout { redundant: matches.opt_present("redundant"), depth: [bits; 3] });
+        }
+        for part in d.split(',') {
+            let (channel, bits) = part.split_once(':').ok_or_else(invalid)?;
+            let c = ["r", "g", "b"].iter().position(|&n| n == channel.trim().to_lowercase()).ok_or_else(invalid)?;
+            depth[c] = bits.trim().parse().ok().filter(|&b| b <= 8).ok_or_else(invalid)?;
+        }
+        if depth == [0; 3] {
+            return Err("At least one channel must carry bits in --depth.".to_string());
+        }
+    }
+    Ok(SpotLayout { redundant: matches.opt_present("redundant"), depth })
+}
+
+//encryption of the message on hiding. The whole image pixel modes and WAV carriers encrypt by default (when the key
+//isn't empty), --no-encrypt hides the plain message. The other modes don't hold the whole message and never encrypt
+//by default, neither does --legacy, which writes the format of stegegg 0.1.
+pub fn encrypt(matches: &Matches) -> bool {
+    matches.opt_present("encrypt") || !with_any(matches, &["no-encrypt", "x", "legacy", "tiled", "banded", "low-memory"])
+}
+
+//derivation of the spot order and encryption key from --kdf, scrypt by default
+pub fn kdf(matches: &Matches) -> Result<Kdf, String> {
+    match matches.opt_str("kdf").as_deref() {
+        None | Some("scrypt") => Ok(Kdf::Scrypt),
+        Some("sha256") => Ok(Kdf::Sha256),
+        Some(k) => Err(format!("Unknown key derivation '{}'. Use 'sha256' or 'scrypt'.", k)),
+    }
+}
+
+//explicit seed of the spot order from --seed, hexadecimal with the 0x prefix or decimal
+pub fn seed(matches: &Matches) -> Result<Option<u64>, String> {
+    let s = match matches.opt_str("seed") {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let seed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    seed.map(Some).map_err(|_| format!("Invalid seed '{}'. Expected a 64-bit number, e.g. 0x2a or 42.", s))
+}
+
+//codec of --compress given by --codec, None for the automatic choice
+pub fn codec(matches: &Matches) -> Result<Option<compress::Codec>, String> {
+    match matches.opt_str("codec").as_deref() {
+        None | Some("auto") => Ok(None),
+        Some(name) => compress::Codec::from_name(name).map(Some)
+            .ok_or_else(|| format!("Unknown codec '{}'. Use deflate, lz4, brotli or auto.", name)),
+    }
+}
+
+//trailer params of the message with the flags. The key derivation is recorded, unless nothing was derived from
+//the key (unkeyed spots of --legacy, --sequential and --seed without encryption).
+pub fn trailer_params(matches: &Matches, flags: u8) -> Result<TrailerParams, String> {
+    let keyed = !["legacy", "sequential", "seed"].iter().any(|o| matches.opt_present(o));
+    let kdf = if keyed || flags & FLAG_ENCRYPTED != 0 { Some(kdf(matches)?) } else { None };
+    let label = matches.opt_str("label");
+    if let Some(label) = &label {
+        check_label(label)?;
+    }
+    Ok(TrailerParams { flags, kdf, label })
+}
+
+//largest message accepted on extraction, the whole format limit by default
+pub fn max_extract_size(matches: &Matches) -> Result<usize, String> {
+    matches.opt_str("max-extract-size").map_or(Ok(MAX_MSG_LEN), |s| parse_size(&s))
+}
+
+//maximal used fraction of the capacity from --max-fill, "0.3" or "30%"
+pub fn max_fill(matches: &Matches) -> Result<f64, String> {
+    let s = match matches.opt_str("max-fill") {
+        Some(s) => s,
+        None => return Ok(1.0),
+    };
+    let fill = match s.strip_suffix('%') {
+        Some(p) => p.trim().parse::<f64>().map(|p| p / 100.0),
+        None => s.parse::<f64>(),
+    };
+    fill.ok().filter(|f| *f > 0.0 && *f <= 1.0).ok_or_else(|| format!("Invalid fill rate '{}'. Expected a number above 0 up to 1, or a percentage.", s))
+}
+
+//number of threads for the parallel work. All cores are used by default.
+pub fn threads(matches: &Matches) -> Result<usize, String> {
+    match matches.opt_str("threads").map(|s| s.parse::<usize>()) {
+        None => Ok(std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+        Some(Ok(n)) if n >= 1 => Ok(n),
+        _ => Err("Number of threads must be at least 1.".to_string()),
+    }
+}
+
+//tile size of the tiled embedding
+pub fn tile_size(matches: &Matches) -> Result<u32, String> {
+    match matches.opt_str("tile-size").map(|s| s.parse::<u32>()) {
+        None => Ok(64),
+        Some(Ok(t)) if t >= 8 => Ok(t),
+        _ => Err("Tile size must be a number of at least 8 pixels.".to_string()),
+    }
+}
+
+//option is given together with one of the options or with the chunk mode
+fn with_any(matches: &Matches, options: &[&str]) -> bool {
+    options.iter().any(|o| matches.opt_present(o)) || matches.opt_str("mode").as_deref() == Some("chunk")
+}
+
+//refuse the options which can't be used together or with the mode of the carrier
+pub fn check_combinations(matches: &Matches, out_filename: &Path) -> Result<(), String> {
+
+    //encrypted message is decrypted in the output file
+    if matches.opt_present("x") && (matches.opt_present("i") || matches.opt_present("pgp-decrypt")) && (out_filename == Path::new("-") || matches.opt_present("range")) {
+        return Err("Options --identity and --pgp-decrypt can't be used with the stdout output or --range.".to_string());
+    }
+
+    //custom depth is recorded only by the default salted pixel mode
+    if matches.opt_present("depth") && with_any(matches, &["redundant", "legacy", "sequential", "tiled", "banded", "low-memory"]) {
+        return Err("Option --depth can't be used with --redundant, --legacy, --sequential, --tiled, --banded, --low-memory or the chunk mode.".to_string());
+    }
+
+    //cover is prepared only by the modes which hide into the decoded pixels
+    if preprocess::requested(matches) && with_any(matches, &["x", "banded", "low-memory"]) {
+        return Err("Options --resize, --crop, --denoise and --add-grain prepare the cover on hiding and can't be used with -x, --banded, --low-memory or the chunk mode.".to_string());
+    }
+
+    //encryption is done by the whole image pixel modes, which hold the whole message
+    if matches.opt_present("encrypt") && with_any(matches, &["no-encrypt", "tiled", "banded", "low-memory", "range", "resume"]) {
+        return Err("Option --encrypt can't be used with --no-encrypt, --tiled, --banded, --low-memory, --range, --resume or the chunk mode.".to_string());
+    }
+
+    //matching moves the values across the pairs, so it works only on the LSB plane of the whole image pixel modes
+    if matches.opt_present("lsb-matching") && with_any(matches, &["x", "depth", "restore-histogram", "tiled", "banded", "low-memory"]) {
+        return Err("Option --lsb-matching is used only for hiding and can't be used with --depth, --restore-histogram, --tiled, --banded, --low-memory or the chunk mode.".to_string());
+    }
+
+    //compression is recorded in the trailer of the whole image pixel modes
+    if matches.opt_present("compress") && with_any(matches, &["x", "tiled", "banded", "low-memory"]) {
+        return Err("Option --compress is used only for hiding and can't be used with --tiled, --banded, --low-memory or the chunk mode.".to_string());
+    }
+    if matches.opt_present("codec") && !matches.opt_present("compress") {
+        return Err("Option --codec can be used only with --compress.".to_string());
+    }
+
+    //label is stored in the trailer of the whole carrier modes
+    if matches.opt_present("label") && with_any(matches, &["x", "tiled", "banded", "low-memory"]) {
+        return Err("Option --label is used only for hiding and can't be used with --tiled, --banded, --low-memory or the chunk mode.".to_string());
+    }
+    if matches.opt_present("require-label") && (!matches.opt_present("x") || with_any(matches, &["tiled", "banded", "low-memory"])) {
+        return Err("Option --require-label is used only for extraction and can't be used with --tiled, --banded, --low-memory or the chunk mode.".to_string());
+    }
+
+    //scrypt stretches the key with the salt of the whole image salted modes
+    if matches.opt_present("kdf") && with_any(matches, &["legacy", "sequential", "tiled", "banded", "low-memory"]) {
+        return Err("Option --kdf can't be used with --legacy, --sequential, --tiled, --banded, --low-memory or the chunk mode.".to_string());
+    }
+
+    //seed is used only by the whole image pixel modes
+    if matches.opt_present("seed") && with_any(matches, &["sequential", "tiled", "banded", "low-memory"]) {
+        return Err("Option --seed can't be used with --sequential, --tiled, --banded, --low-memory or the chunk mode.".to_string());
+    }
+
+    //archive is restored by the whole carrier modes, into the output directory
+    if matches.opt_present("archive") && (with_any(matches, &["tiled", "banded", "low-memory", "range", "resume", "in-place"]) || out_filename == Path::new("-")) {
+        return Err("Option --archive can't be used with --tiled, --banded, --low-memory, --range, --resume, --in-place, the chunk mode or the stdout output.".to_string());
+    }
+    if (matches.opt_present("entry") || matches.opt_present("flatten")) && !(matches.opt_present("x") && matches.opt_present("archive")) {
+        return Err("Options --entry and --flatten select how -x --archive unpacks the files, they can't be used without them.".to_string());
+    }
+
+    //alpha samples are packed into the whole decoded image
+    if matches.opt_present("use-alpha") && with_any(matches, &["drop-alpha", "tiled", "banded", "low-memory", "auto"]) {
+        return Err("Option --use-alpha can't be used with --drop-alpha, --tiled, --banded, --low-memory, --auto or the chunk mode.".to_string());
+    }
+
+    //the probed layout replaces the layout options
+    if matches.opt_present("auto") && (!matches.opt_present("x") || with_any(matches, &super::probe::LAYOUT_OPTIONS)) {
+        return Err("Option --auto is used only for the extraction and can't be used with --redundant, --legacy, --sequential, --tiled, --banded, --low-memory or the chunk mode.".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_units_are_decimal_or_binary() {
+        assert_eq!(parse_size("1234"), Ok(1234));
+        assert_eq!(parse_size("500k"), Ok(500_000));
+        assert_eq!(parse_size(" 2 MiB "), Ok(2 << 20));
+        assert_eq!(parse_size("1.5kb"), Ok(1500));
+        assert_eq!(parse_size("3G"), Ok(3_000_000_000));
+        assert_eq!(parse_size("1gib"), Ok(1 << 30));
+        assert_eq!(parse_size("10 bytes"), Ok(10));
+    }
+
+    #[test]
+    fn overflowing_or_junk_size_is_refused() {
+        for size in ["", "k", "12x", "1.2.3M", "-5", "5TB", "inf", "NaN", "18446744073709551616", "20000000000G"] {
+            assert!(parse_size(size).is_err(), "{}", size);
+        }
+    }
+}
@@ -0,0 +1,665 @@
+use std::io::{BufWriter, Cursor, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::fs::File;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use getopts::Matches;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use stegegg::*;
+use zeroize::Zeroizing;
+use super::i18n::tr;
+use super::manifest::{self, PayloadDigest};
+use super::{analyze, color, diag, polyglot, preprocess, stats, wav};
+use stegegg::pngenc;
+use stegegg::steganalysis;
+use super::{decrypt_output, key_context, looks_binary, message_source, os_arg, require_label};
+use stegegg::files::{open_image, write_atomic, write_vec_to_file};
+use super::options::{codec, encrypt, kdf, max_extract_size, max_fill, parse_size, seed, spot_layout, threads, tile_size, trailer_params};
+
+//extract only the byte window given as "OFFSET:LEN" of the message of msg_len bytes. Window is clipped at the end
+//of the message, only its spots are read.
+pub fn extract_range(image: &RgbImage, layout: SpotLayout, indices: &[u32], msg_len: usize, range: &str) -> Result<Vec<u8>, ExtractError> {
+
+    let (offset, len) = match range.split_once(':').map(|(o, l)| (parse_size(o), parse_size(l))) {
+        Some((Ok(o), Ok(l))) => (o, l),
+        _ => return Err(format!("Invalid range '{}'. Use OFFSET:LEN.", range).into()),
+    };
+    carrier::read_range(&ImageCarrier { image, layout }, indices, msg_len, offset, len)
+}
+
+//extract data of the message of msg_len bytes into the file in chunks and record progress into the state file,
+//so the interrupted extraction can continue from the last written byte. The checksum is computed along and kept in
+//the state file, so the resumed run doesn't read the extracted part again. Returns number of written bytes.
+pub fn extract_resumable(image: &RgbImage, layout: SpotLayout, indices: &[u32], msg_len: usize, trailer: Option<&Trailer>, path: &Path) -> Result<usize, ExtractError> {
+
+    let mut state_path = OsString::from(path);
+    state_path.push(".resume");
+    let state_path = PathBuf::from(state_path);
+
+    //state file contains "offset/length/crc32" of the already extracted data
+    let (mut offset, mut crc) = (0, 0);
+    if let Ok(state) = std::fs::read_to_string(&state_path) {
+        let fields: Vec<&str> = state.trim().split('/').collect();
+        match fields.as_slice() {
+            [o, l, c] => match (o.parse::<usize>(), l.parse::<usize>(), c.parse::<u32>()) {
+                (Ok(o), Ok(l), Ok(c)) if l == msg_len && o <= msg_len => {
+                    println!("Resuming extraction at byte {} of {}.", o, msg_len);
+                    (offset, crc) = (o, c);
+                },
+                _ => println!("State file '{}' doesn't match this image. Starting from the beginning.", state_path.display()),
+            },
+            _ => println!("State file '{}' doesn't match this image. Starting from the beginning.", state_path.display()),
+        }
+    }
+
+    let mut f = match std::fs::OpenOptions::new().create(true).write(true).truncate(false).open(path) {
+        Ok(f) => f,
+        Err(s) => return Err(s.to_string().into()),
+    };
+    if let Err(s) = f.set_len(offset as u64).and_then(|_| f.seek(SeekFrom::End(0))) {
+        return Err(s.to_string().into());
+    }
+
+    const CHUNK: usize = 1 << 20;
+    while offset < msg_len {
+        let len = CHUNK.min(msg_len - offset);
+        let data = Zeroizing::new(extract_bytes(image, layout, &indices[3 * 8..], offset, len));
+        if let Err(s) = f.write_all(&data).and_then(|_| f.sync_data()) {
+            return Err(s.to_string().into());
+        }
+
+        let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+        hasher.update(&data);
+        crc = hasher.finalize();
+        offset += len;
+        write_vec_to_file(&state_path, format!("{}/{}/{}", offset, msg_len, crc).as_bytes())?;
+    }
+
+    //extraction is complete, state is not needed anymore. Legacy messages may have no trailer to check.
+    let _ = std::fs::remove_file(&state_path);
+    if let Some(trailer) = trailer {
+        let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+        hasher.update(&trailer.label_bytes());
+        if hasher.finalize() != trailer.crc32 {
+            return Err(ExtractError::Decode("Message checksum doesn't match. The key is right, but the carrier was modified or damaged.".to_string()));
+        }
+    }
+    Ok(msg_len)
+}
+
+//print summary of the extracted bytes which were not unanimously voted
+pub fn report_confidence(confidence: &[f32]) {
+
+    //group consecutive low confidence bytes into ranges
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in confidence.iter().enumerate().filter(|(_, &c)| c < 1.0) {
+        match ranges.last_mut() {
+            Some(r) if r.1 == i => r.1 = i + 1,
+            _ => ranges.push((i, i + 1)),
+        }
+    }
+
+    if ranges.is_empty() {
+        println!("All bytes extracted with full confidence.");
+        return;
+    }
+
+    let low = confidence.iter().filter(|&&c| c < 1.0).count();
+    let mean = confidence.iter().sum::<f32>() / confidence.len() as f32;
+    diag::warning(format!("{} of {} bytes have low confidence (mean confidence {:.3}). Output may be corrupted at:", low, confidence.len(), mean));
+    for (start, end) in ranges.iter().take(10) {
+        println!("  bytes {}..{}", start, end);
+    }
+    if ranges.len() > 10 {
+        println!("  ... and {} more ranges", ranges.len() - 10);
+    }
+}
+
+//write map of samples which differ between cover and stego image. Returns number of modified samples.
+//PNG mask has changed channel set to 255, text output is a list of "x,y,channel" lines.
+pub fn write_embedding_map(cover: &RgbImage, image: &RgbImage, path: &Path) -> Result<usize, String> {
+
+    let mut mask = RgbImage::new(image.width(), image.height());
+    let mut coords = String::from("x,y,channel\n");
+    let mut changed = 0;
+
+    for (x, y, p) in image.enumerate_pixels() {
+        let cp = cover.get_pixel(x, y);
+        for c in 0..3 {
+            if p[c] != cp[c] {
+                mask.get_pixel_mut(x, y)[c] = 255;
+                coords.push_str(&format!("{},{},{}\n", x, y, ["r", "g", "b"][c]));
+                changed += 1;
+            }
+        }
+    }
+
+    if matches!(path.extension().and_then(|e| e.to_str()), Some("txt") | Some("csv")) {
+        write_vec_to_file(path, coords.as_bytes())?;
+    } else {
+        write_atomic(path, |tmp| mask.save_with_format(tmp, ImageFormat::Png).map_err(|s| s.to_string()))?;
+    }
+    Ok(changed)
+}
+
+//open the carrier image, derive the key and shuffle hidding spots. Returns the rgb8 image, the alpha channel
+//of the cover, the layout, the salt and the shuffled spots.
+pub fn prepare_image(matches: &Matches, in_filename: &Path, key: &[u8], context: &[u8], extract: bool) -> Result<(color::Cover, SpotLayout, Vec<u8>, Vec<u32>), String> {
+
+    //open image, prepare the cover on hiding and convert it into rgb8 image
+    let mut cover = open_image(in_filename)?;
+    if !extract && preprocess::requested(matches) {
+        cover = preprocess::preprocess(cover, matches)?;
+    }
+    let (mut img, alpha) = color::normalize(cover, matches)?;
+    diag::log(format!("Image '{}' decoded, {}x{}.", in_filename.display(), img.width(), img.height()));
+    let (width, height) = (img.width(), img.height());
+
+    //alpha samples are packed below the image and carry the message too
+    if matches.opt_present("use-alpha") {
+        let alpha = alpha.as_ref().ok_or("Image has no alpha channel for --use-alpha.")?;
+        img = DynamicImage::ImageRgb8(color::pack_alpha(img.as_rgb8().unwrap(), alpha));
+    }
+    let rgb_img = img.as_mut_rgb8().unwrap();
+
+    let (layout, salt, mut indices) = prepare_spots(matches, rgb_img, key, context, extract)?;
+    if matches.opt_present("use-alpha") {
+        indices.retain(|&s| color::packed_spot(layout, s, width, height));
+    }
+    Ok(((img, alpha), layout, salt, indices))
+}
+
+//derive the key and shuffle hidding spots of the image. Salt is read from the image on extraction
+//or created for the new message on hiding. Custom depth is read from the image after the salt.
+pub fn prepare_spots(matches: &Matches, rgb_img: &RgbImage, key: &[u8], context: &[u8], extract: bool) -> Result<(SpotLayout, Vec<u8>, Vec<u32>), String> {
+
+    //legacy images have no salt and use all spots for the data
+    let legacy = matches.opt_present("legacy");
+    let mut layout = spot_layout(matches)?;
+
+    //sequential mode ignores the key and uses all spots in raster order
+    if matches.opt_present("sequential") {
+        if !extract {
+            diag::warning("--sequential doesn't use the key. Anyone can extract the message.");
+        }
+        return Ok((layout, Vec::new(), (0..layout.spot_count(rgb_img)?).collect()));
+    }
+    if !legacy && layout.spot_count(rgb_img)? < layout.first_spot() {
+        return Err("Image is too small!".to_string());
+    }
+
+    //read the salt from the image or create a new random one for each hidden message
+    let salt = if legacy {
+        Vec::new()
+    } else if extract {
+        read_salt(rgb_img, layout)
+    } else {
+        random_salt()?
+    };
+    //legacy images seed the spot order by SHA256 of the key as it is, as stegegg 0.1 did
+    let kdf = if legacy { Kdf::Sha256 } else { kdf(matches)? };
+    let key = &kdf.derive(key, &salt)?;
+    if extract && !legacy && !layout.redundant {
+        layout.depth = read_depth(rgb_img, key, &salt, context).unwrap_or(DEFAULT_DEPTH);
+    }
+
+    //init random generator with SHA256 from the user key and salt and shuffle hidding spots into the key dependent order.
+    //Explicit seed gives the same order for any salt, which is insecure but reproducible.
+    let mut prng_state = match seed(matches)? {
+        Some(seed) => {
+            if !extract {
+                diag::warning("--seed replaces the key. Anyone knowing the seed can extract the message.");
+            }
+            seeded_prng(seed)
+        },
+        None => init_prng(key, &salt, context),
+    };
+    let indices = hidding_spots(rgb_img, layout, if legacy { 0 } else { layout.first_spot() }, &mut prng_state)?;
+    Ok((layout, salt, indices))
+}
+
+//compare the size of the output with the cover and warn when it is much larger. Size anomalies are a tell.
+pub fn report_size_delta(in_filename: &Path, out_filename: &Path, optimized: bool, format: ImageFormat) {
+
+    //in-place output has already replaced the cover
+    if in_filename == out_filename {
+        return;
+    }
+
+    let (in_size, out_size) = match (std::fs::metadata(in_filename), std::fs::metadata(out_filename)) {
+        (Ok(i), Ok(o)) => (i.len(), o.len()),
+        _ => return,
+    };
+    if in_size == 0 {
+        return;
+    }
+
+    let delta = (out_size as f64 / in_size as f64 - 1.0) * 100.0;
+    println!("{}", tr!("output-size", size = out_size, delta = format!("{:+.1}", delta)));
+
+    if delta > 25.0 {
+        diag::warning(tr!("output-larger"));
+        if format != ImageFormat::Png {
+            diag::hint(format!("{:?} output is uncompressed. Use PNG output (--format png) for compressed covers.", format));
+        } else if !optimized {
+            diag::hint("use --optimize to losslessly recompress the output PNG.");
+        }
+        if !matches!(ImageFormat::from_path(in_filename), Ok(ImageFormat::Png) | Ok(ImageFormat::Bmp)) {
+            diag::hint("covers in lossy formats (e.g. JPEG) always grow when saved losslessly. Prefer PNG covers.");
+        }
+    }
+}
+
+//image with the verified header and trailer of the hidden message
+struct HiddenMessage {
+    img: DynamicImage,
+    layout: SpotLayout,
+    indices: Vec<u32>,
+    msg_len: usize,
+    trailer: Option<Trailer>,
+    max_len: usize,
+}
+
+impl HiddenMessage {
+
+    //open the image and check the header (and the size limit), the trailer and the label before anything is allocated
+    //by the header or written. Without verify the checksum of the whole message is left out, for the reads of its parts.
+    //The shuffled spot list is built whole anyway, the place of every spot depends on all swaps of the shuffle.
+    fn open(matches: &Matches, in_filename: &Path, key: &[u8], verify: bool) -> Result<HiddenMessage, ExtractError> {
+        let context = key_context(matches, in_filename)?;
+        let ((img, _), layout, _, indices) = prepare_image(matches, in_filename, key, &context, true).map_err(ExtractError::Decode)?;
+        let rgb_img = img.as_rgb8().unwrap();
+
+        let max_len = max_extract_size(matches)?;
+        let msg_len = read_header_limited(rgb_img, layout, &indices, stored_limit(max_len))?;
+        //salted images must have the trailer, the legacy ones may be older than it
+        let trailer = match (matches.opt_present("legacy"), verify) {
+            (true, true) => verify_legacy_payload(rgb_img, layout, &indices, msg_len)?,
+            (true, false) => match read_trailer(rgb_img, layout, &indices, msg_len) {
+                Err(ExtractError::NoPayload(_)) => None,
+                t => Some(t?),
+            },
+            (false, true) => Some(verify_payload(rgb_img, layout, &indices, msg_len)?),
+            (false, false) => Some(read_trailer(rgb_img, layout, &indices, msg_len)?),
+        };
+        require_label(matches, trailer.as_ref())?;
+        if !trailer.as_ref().is_some_and(|t| t.encrypted()) && msg_len > max_len {
+            return Err(ExtractError::Failed(format!("Message has {}, which is above the extraction limit of {}.", human_size(msg_len), human_size(max_len))));
+        }
+        Ok(HiddenMessage { img, layout, indices, msg_len, trailer, max_len })
+    }
+
+    fn rgb(&self) -> &RgbImage {
+        self.img.as_rgb8().unwrap()
+    }
+
+    //whole message, decrypted and decompressed as the trailer flags tell
+    fn read(&self, key: &[u8]) -> Result<Zeroizing<Vec<u8>>, ExtractError> {
+        let msg = Zeroizing::new(extract_bytes(self.rgb(), self.layout, &self.indices[3 * 8..], 0, self.msg_len));
+        unpack_message(key, msg, self.trailer.as_ref(), self.max_len)
+    }
+}
+
+//whole message of the image or WAV carrier in the memory, for the commands which don't write it
+pub fn read_message(matches: &Matches, in_filename: &Path, key: &[u8]) -> Result<Zeroizing<Vec<u8>>, ExtractError> {
+    if wav::is_wav(in_filename) {
+        return wav::read_wav(matches, in_filename, key);
+    }
+    if matches.opt_present("tiled") {
+        return read_tiled(matches, in_filename, key).map(|(msg, _)| msg);
+    }
+    let (format, _, _) = manifest::parameters(matches);
+    if ["banded", "low-memory"].iter().any(|o| matches.opt_present(o)) || format == "chunk" {
+        return Err(ExtractError::Failed("Only the whole image pixel modes, the tiled mode and WAV carriers can be read into the memory.".to_string()));
+    }
+    HiddenMessage::open(matches, in_filename, key, true)?.read(key)
+}
+
+//extract data from the image into the output file
+pub fn extract_file(matches: &Matches, in_filename: &Path, out_filename: &Path, key: &[u8]) -> Result<(), ExtractError> {
+
+    //window of --range is read without the checksum of the whole message, --resume checks it along the extraction
+    let hidden = HiddenMessage::open(matches, in_filename, key, !matches.opt_present("range") && !matches.opt_present("resume"))?;
+    let (rgb_img, layout, indices, msg_len, trailer) = (hidden.rgb(), hidden.layout, hidden.indices.as_slice(), hidden.msg_len, &hidden.trailer);
+    let encrypted = trailer.as_ref().is_some_and(|t| t.encrypted());
+    let compressed = trailer.as_ref().is_some_and(|t| t.codec().is_some());
+
+    //encrypted message is authenticated and compressed one decompressed whole before anything is written
+    if encrypted || compressed {
+        if matches.opt_present("range") || matches.opt_present("resume") {
+            let how = match (encrypted, compressed) {
+                (true, true) => "encrypted and compressed",
+                (true, false) => "encrypted",
+                _ => "compressed",
+            };
+            return Err(ExtractError::Failed(format!("Message is {}, it can't be extracted with --range or --resume. Hide it with --no-encrypt (and without --compress) to read its parts.", how)));
+        }
+        let msg = hidden.read(key)?;
+        if out_filename == Path::new("-") {
+            if std::io::stdout().is_terminal() && !matches.opt_present("force-binary") && looks_binary(&msg[..msg.len().min(8192)]) {
+                return Err(tr!("binary-to-terminal").into());
+            }
+            std::io::stdout().lock().write_all(&msg).map_err(|s| tr!("file-error", file = "-", error = s.to_string()))?;
+            eprintln!("{}", tr!("bytes-written-stdout", count = msg.len()));
+        } else {
+            let n = write_vec_to_file(out_filename, &msg).map_err(|s| tr!("file-error", file = out_filename.display().to_string(), error = s))?;
+            println!("{}", tr!("bytes-written", count = n, file = out_filename.display().to_string()));
+        }
+        return decrypt_output(matches, out_filename);
+    }
+
+    if matches.opt_present("resume") {
+        let n = extract_resumable(rgb_img, layout, indices, msg_len, trailer.as_ref(), out_filename)?;
+        println!("{}", tr!("bytes-written", count = n, file = out_filename.display().to_string()));
+
+    } else if let Some(range) = matches.opt_str("range") {
+        let v = Zeroizing::new(extract_range(rgb_img, layout, indices, msg_len, &range)?);
+        match write_vec_to_file(out_filename, &v) {
+            Ok(n) => println!("{}", tr!("bytes-written", count = n, file = out_filename.display().to_string())),
+            Err(s) => return Err(tr!("file-error", file = out_filename.display().to_string(), error = s).into()),
+        }
+
+    } else {
+
+        //"-" writes to stdout. Binary data isn't printed to the terminal, as it may corrupt it.
+        let stdout = out_filename == Path::new("-");
+        let result = if stdout {
+            if std::io::stdout().is_terminal() && !matches.opt_present("force-binary")
+                && looks_binary(&extract_bytes(rgb_img, layout, &indices[3 * 8..], 0, msg_len.min(8192))) {
+                return Err(tr!("binary-to-terminal").into());
+            }
+            carrier::write_message(&ImageCarrier { image: rgb_img, layout }, indices, msg_len, &mut std::io::stdout().lock()).map(|_| msg_len).map_err(|s| s.to_string())
+        } else {
+            write_atomic(out_filename, |tmp| {
+                let mut w = BufWriter::new(File::create(tmp).map_err(|s| s.to_string())?);
+                carrier::write_message(&ImageCarrier { image: rgb_img, layout }, indices, msg_len, &mut w).map_err(|s| s.to_string())?;
+                w.flush().map_err(|s| s.to_string())?;
+                Ok(msg_len)
+            })
+        };
+
+        let n = match result {
+            Ok(n) => n,
+            Err(s) => return Err(tr!("file-error", file = out_filename.display().to_string(), error = s).into()),
+        };
+        if stdout {
+            eprintln!("{}", tr!("bytes-written-stdout", count = n));
+        } else {
+            println!("{}", tr!("bytes-written", count = n, file = out_filename.display().to_string()));
+        }
+
+        //redundant spots give the confidence of each extracted byte. Summary would mix with the data on stdout.
+        if layout.redundant {
+            let confidence = byte_confidence(rgb_img, layout, indices, n);
+            if !stdout {
+                report_confidence(&confidence);
+            }
+
+            if let Some(path) = matches.opt_str("confidence-file").map(|p| os_arg(&p)) {
+                let mut csv = String::from("offset,confidence\n");
+                for (i, c) in confidence.iter().enumerate() {
+                    csv.push_str(&format!("{},{:.3}\n", i, c));
+                }
+                if let Err(s) = write_vec_to_file(&path, csv.as_bytes()) {
+                    return Err(tr!("file-error", file = path.display().to_string(), error = s).into());
+                }
+            }
+        }
+    }
+    decrypt_output(matches, out_filename)
+}
+
+//message read whole into the memory
+type PackedMessage = Cursor<Zeroizing<Vec<u8>>>;
+
+//compress the message (when it gets smaller) and encrypt it by the key, as --compress and --encrypt request.
+//Returns the message to hide, None when it is hidden from the reader as it is, and the trailer params.
+pub fn pack_message(matches: &Matches, key: &[u8], reader: &mut dyn Read) -> Result<(Option<PackedMessage>, TrailerParams), String> {
+    //the default encryption is skipped without the key, --encrypt without it is refused below
+    let seal = encrypt(matches) && (matches.opt_present("encrypt") || !key.is_empty());
+    if !matches.opt_present("compress") && !seal {
+        return Ok((None, trailer_params(matches, 0)?));
+    }
+    let mut msg = Zeroizing::new(Vec::new());
+    reader.read_to_end(&mut msg).map_err(|s| s.to_string())?;
+
+    let mut flags = 0;
+    if matches.opt_present("compress") {
+        match compress::compress(&msg, codec(matches)?) {
+            Some((codec, compressed)) => {
+                println!("Message compressed by {}, {} -> {}.", codec.name(), human_size(msg.len()), human_size(compressed.len()));
+                msg = compressed;
+                flags |= codec_flags(codec);
+            },
+            None => println!("Message doesn't compress, it is hidden uncompressed."),
+        }
+    }
+
+    //encrypt and authenticate the message by the key, so the hidden bytes aren't plaintext
+    if seal {
+        if key.is_empty() {
+            return Err("Option --encrypt needs the key, give it with -k, -K or --ask-key.".to_string());
+        }
+        msg = Zeroizing::new(aead::seal(key, &msg, kdf(matches)?)?);
+        flags |= FLAG_ENCRYPTED;
+    }
+    Ok((Some(Cursor::new(msg)), trailer_params(matches, flags)?))
+}
+
+//hide message from the reader into the image and save it into the output file
+pub fn hide_file(matches: &Matches, in_filename: &Path, out_filename: &Path, key: &[u8], reader: &mut dyn Read, len: Option<usize>) -> Result<(), String> {
+
+    let (mut packed, params) = pack_message(matches, key, reader)?;
+    let (reader, len): (&mut dyn Read, Option<usize>) = match &mut packed {
+        Some(p) => { let len = p.get_ref().len(); (p, Some(len)) },
+        None => (reader, len),
+    };
+
+    let context = key_context(matches, out_filename)?;
+    let ((mut img, alpha), layout, salt, indices) = prepare_image(matches, in_filename, key, &context, false)?;
+    let rgb_img = img.as_mut_rgb8().unwrap();
+
+    //keep the original image for the histogram restoration, the embedding map and the statistics
+    let embed_map = matches.opt_str("embed-map").map(|p| os_arg(&p));
+    let cover = if ["restore-histogram", "stats", "lsb-matching"].iter().any(|o| matches.opt_present(o)) || embed_map.is_some() { Some(rgb_img.clone()) } else { None };
+
+    //warn before the hidden data already in the cover is overwritten
+    if matches.opt_present("check-cover") {
+        let d = steganalysis::detectability(rgb_img);
+        diag::log(format!("Cover check: RS analysis {:.3}, sample pair analysis {:.3}, chi-square {:.3}.", d.rs, d.spa, d.chi_square));
+        if d.suspicious() {
+            diag::warning(format!("Cover '{}' has abnormal LSB statistics (estimated embedding rate {:.0}%). It may already contain hidden data, which hiding overwrites.",
+                in_filename.display(), d.rs.max(d.spa) * 100.0));
+        } else {
+            println!("Cover LSB statistics look natural (estimated embedding rate {:.0}%).", d.rs.max(d.spa) * 100.0);
+        }
+    }
+
+    //costs of the cover, the embedded spots would add their own noise
+    if let Some(path) = matches.opt_str("cost-map").map(|p| os_arg(&p)) {
+        let mean = analyze::write_cost_map(rgb_img, &path).map_err(|s| tr!("file-error", file = path.display().to_string(), error = s))?;
+        println!("Cost map written to '{}', mean embedding cost {:.3}.", path.display(), mean);
+    }
+
+    write_salt(rgb_img, layout, &salt);
+    if layout.depth != DEFAULT_DEPTH {
+        write_depth(rgb_img, &kdf(matches)?.derive(key, &salt)?, &salt, &context, layout.depth);
+    }
+    let max_fill = max_fill(matches)?;
+    let used = carrier::hide_message(reader, len, &mut ImageCarrier { image: &mut *rgb_img, layout }, &indices, params)?;
+    let capacity = message_capacity(indices.len());
+    let payload = used / 8 - 3 - TRAILER_LEN;
+    let fill = payload as f64 / capacity.max(1) as f64;
+    println!("Payload of {} hidden, {:.1}% of the {} capacity used.", human_size(payload), fill * 100.0, human_size(capacity));
+    if fill > max_fill {
+        return Err(format!("Payload uses {:.1}% of the capacity, above the maximum {:.1}%. Output is not written, use a shorter message or a larger cover.", fill * 100.0, max_fill * 100.0));
+    }
+
+    if let Some(cover) = &cover {
+        if matches.opt_present("lsb-matching") {
+            let mut seed = [0; 32];
+            getrandom::fill(&mut seed).map_err(|s| format!("Can't generate random directions. {}", s))?;
+            let n = lsb_matching(cover, rgb_img, &mut init_prng(&seed, &[], &[]));
+            diag::log(format!("LSB matching adjusted {} samples.", n));
+        }
+        if matches.opt_present("restore-histogram") {
+            match restore_histogram(cover, rgb_img, layout, &indices, used) {
+                0 => println!("Original histogram restored."),
+                n => println!("Histogram partially restored. {} samples are still off.", n),
+            }
+        }
+        if matches.opt_present("stats") {
+            stats::report_stats(cover, rgb_img, used, indices.len(), matches.opt_str("stats").as_deref())?;
+        }
+    }
+
+    //score the stego image by the steganalysis detectors and refuse to write too detectable output
+    let max_detectability = match matches.opt_str("max-detectability") {
+        Some(s) => match s.parse::<f64>() {
+            Ok(m) if (0.0..=1.0).contains(&m) => Some(m),
+            _ => return Err(format!("Invalid maximum detectability '{}'. Expected a number from 0 to 1.", s)),
+        },
+        None => None,
+    };
+    if matches.opt_present("detectability") || max_detectability.is_some() {
+        let d = steganalysis::detectability(rgb_img);
+        println!("Detectability score {:.3} (RS analysis {:.3}, sample pair analysis {:.3}, chi-square {:.3}).", d.score, d.rs, d.spa, d.chi_square);
+        diag::log(format!("Detectability score {:.3}.", d.score));
+        if let Some(max) = max_detectability.filter(|&m| d.score > m) {
+            return Err(format!("Detectability score {:.3} is above the maximum {}. Output is not written, use a shorter message or a larger cover.", d.score, max));
+        }
+    }
+
+    //write map of all modified samples
+    if let (Some(cover), Some(map_path)) = (&cover, &embed_map) {
+        match write_embedding_map(cover, rgb_img, map_path) {
+            Ok(n) => println!("Embedding map with {} modified samples written to '{}'.", n, map_path.display()),
+            Err(s) => diag::error(format!("Error writing embedding map '{}'. {}", map_path.display(), s)),
+        }
+    }
+
+    save_output(matches, &color::restore(img, alpha.as_ref()), in_filename, out_filename)
+}
+
+//save output image and report its size. PNG is compressed in parallel (all cores unless --threads is given).
+pub fn save_output(matches: &Matches, img: &DynamicImage, in_filename: &Path, out_filename: &Path) -> Result<(), String> {
+
+    let format = output_format(matches, in_filename, Some(out_filename))?;
+    let optimize = matches.opt_present("optimize") && format == ImageFormat::Png;
+    let zip = matches.opt_str("polyglot-zip").map(|p| os_arg(&p));
+    if zip.is_some() && format != ImageFormat::Png {
+        return Err("Option --polyglot-zip requires PNG output.".to_string());
+    }
+    let result = write_atomic(out_filename, |tmp| {
+        if format == ImageFormat::Png {
+            let threads = threads(matches)?;
+            match img.as_rgba8() {
+                Some(rgba) => pngenc::save_png_rgba(rgba, tmp, threads)?,
+                None => pngenc::save_png(img.as_rgb8().unwrap(), tmp, threads)?,
+            }
+        } else {
+            img.save_with_format(tmp, format).map_err(|s| s.to_string())?;
+        }
+
+        //optimization keeps the pixels (and the hidden message) intact
+        let optimized = if optimize { Some(pngenc::optimize_png(tmp)) } else { None };
+
+        //archive goes after the optimization, which would drop it
+        let appended = zip.as_ref().map(|z| polyglot::append_zip(tmp, z)).transpose()?;
+        Ok((optimized, appended))
+    });
+
+    match result {
+        Ok((optimized, appended)) => {
+            println!("{}", tr!("message-hidden", file = out_filename.display().to_string()));
+            if let Some(n) = appended {
+                println!("ZIP archive with {} bytes appended, the output is also a valid ZIP file.", n);
+            }
+            match optimized {
+                Some(Ok((before, after))) => println!("Output optimized from {} to {} bytes.", before, after),
+                Some(Err(s)) => diag::warning(format!("Can't optimize '{}'. {}", out_filename.display(), s)),
+                None => {},
+            }
+        },
+        Err(s) => return Err(tr!("file-error", file = out_filename.display().to_string(), error = s)),
+    }
+
+    report_size_delta(in_filename, out_filename, matches.opt_present("optimize"), format);
+    Ok(())
+}
+
+//format of the output image. Explicit -b / --format goes first, then the extension of the output file. Otherwise
+//the container of the cover is kept when it is lossless (PNG, BMP, TIFF), so the file doesn't change its fingerprint.
+pub fn output_format(matches: &Matches, in_filename: &Path, out_filename: Option<&Path>) -> Result<ImageFormat, String> {
+    let lossless = |f: &ImageFormat| matches!(f, ImageFormat::Png | ImageFormat::Bmp | ImageFormat::Tiff);
+
+    if matches.opt_present("b") {
+        return Ok(ImageFormat::Bmp);
+    }
+    if let Some(f) = matches.opt_str("format") {
+        return match f.to_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "bmp" => Ok(ImageFormat::Bmp),
+            "tif" | "tiff" => Ok(ImageFormat::Tiff),
+            _ => Err(format!("Unsupported output format '{}'. Use png, bmp or tiff.", f)),
+        };
+    }
+    if let Some(f) = out_filename.and_then(|o| ImageFormat::from_path(o).ok()).filter(lossless) {
+        return Ok(f);
+    }
+
+    let cover = image::io::Reader::open(in_filename).ok().and_then(|r| r.with_guessed_format().ok()).and_then(|r| r.format());
+    Ok(cover.filter(lossless).unwrap_or(ImageFormat::Png))
+}
+
+//message of the tiled image in the memory and the statistics of its tiles
+pub fn read_tiled(matches: &Matches, in_filename: &Path, key: &[u8]) -> Result<(Zeroizing<Vec<u8>>, tiled::TileStats), ExtractError> {
+    //tiles have no salt, so the spot order is the same for all of them
+    let context = key_context(matches, in_filename)?;
+    let (img, _) = color::normalize(open_image(in_filename).map_err(ExtractError::Decode)?, matches)?;
+    let (msg, stats) = tiled::extract_tiled(img.as_rgb8().unwrap(), tile_size(matches)?, &mut init_prng(key, &[], &context))?;
+    Ok((Zeroizing::new(msg), stats))
+}
+
+//hide or extract the message using the crop-resilient tiled embedding
+pub fn process_tiled(matches: &Matches, in_filename: &Path, out_filename: &Path, key: &[u8]) -> Result<(), ExtractError> {
+
+    if matches.opt_present("x") {
+        let (msg, stats) = read_tiled(matches, in_filename, key)?;
+        match write_vec_to_file(out_filename, &msg) {
+            Ok(n) => println!("{}", tr!("bytes-written", count = n, file = out_filename.display().to_string())),
+            Err(s) => return Err(tr!("file-error", file = out_filename.display().to_string(), error = s).into()),
+        }
+
+        //tiles failing the CRC check show how close the carrier was to losing the message
+        println!("{} of {} tiles valid, {} of {} fragments recovered by majority vote of damaged replicas.", stats.valid_tiles, stats.tiles, stats.voted_fragments, stats.fragments);
+        if stats.valid_tiles < stats.tiles {
+            diag::warning(format!("{} tiles are damaged.", stats.tiles - stats.valid_tiles));
+        }
+        if stats.geometry != tiled::Geometry::ORIGINAL {
+            println!("Tiles found in the image {}.", stats.geometry);
+        }
+        decrypt_output(matches, out_filename)
+    } else {
+        let tile_size = tile_size(matches)?;
+        let context = key_context(matches, out_filename)?;
+        let mut prng_state = init_prng(key, &[], &context);
+
+        let mut cover = open_image(in_filename).map_err(ExtractError::Decode)?;
+        if preprocess::requested(matches) {
+            cover = preprocess::preprocess(cover, matches)?;
+        }
+        let (mut img, alpha) = color::normalize(cover, matches)?;
+        let rgb_img = img.as_mut_rgb8().unwrap();
+
+        let digest = PayloadDigest::default();
+        let (mut reader, _) = message_source(matches, &digest)?;
+        let mut msg = Zeroizing::new(Vec::new());
+        reader.read_to_end(&mut msg).map_err(|s| s.to_string())?;
+
+        let copies = tiled::hide_tiled(&msg, rgb_img, tile_size, &mut prng_state)?;
+        println!("Each part of the message is stored in {} tiles.", copies);
+        save_output(matches, &color::restore(img, alpha.as_ref()), in_filename, out_filename)?;
+        manifest::write_manifest(matches, in_filename, out_filename, &digest);
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use stegegg::files::read_file_to_vec;
+
+//PNG/ZIP polyglot. ZIP readers find the archive by the end of central directory record at the end of the file,
+//PNG decoders stop at the IEND chunk, so the archive appended after the image is readable by both. All offsets
+//in the archive are moved by the size of the PNG.
+
+const EOCD_SIGNATURE: u32 = 0x06054b50;
+const CENTRAL_SIGNATURE: u32 = 0x02014b50;
+const EOCD_LEN: usize = 22;
+
+fn read_u16(data: &[u8], pos: usize) -> usize {
+    u16::from_le_bytes([data[pos], data[pos + 1]]) as usize
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+//add the shift to the u32 offset at the position
+fn shift_offset(data: &mut [u8], pos: usize, shift: u32) -> Result<(), String> {
+    let offset = read_u32(data, pos);
+    if offset == u32::MAX {
+        return Err("ZIP64 archives are not supported.".to_string());
+    }
+    let shifted = offset.checked_add(shift).ok_or("Archive is too large for the polyglot.")?;
+    data[pos..pos + 4].copy_from_slice(&shifted.to_le_bytes());
+    Ok(())
+}
+
+//move the local header offsets of all entries and the central directory offset by the shift
+fn relocate_zip(zip: &mut [u8], shift: u32) -> Result<(), String> {
+
+    //end of central directory record is followed only by the comment of up to 64 KiB
+    if zip.len() < EOCD_LEN {
+        return Err("File is not a ZIP archive.".to_string());
+    }
+    let first = zip.len().saturating_sub(EOCD_LEN + 0xffff);
+    let eocd = (first..=zip.len() - EOCD_LEN).rev()
+        .find(|&p| read_u32(zip, p) == EOCD_SIGNATURE && p + EOCD_LEN + read_u16(zip, p + 20) == zip.len())
+        .ok_or("File is not a ZIP archive.")?;
+
+    let entries = read_u16(zip, eocd + 10);
+    let mut pos = read_u32(zip, eocd + 16) as usize;
+    for _ in 0..entries {
+        if pos + 46 > eocd || read_u32(zip, pos) != CENTRAL_SIGNATURE {
+            return Err("Central directory of the ZIP archive is damaged.".to_string());
+        }
+        shift_offset(zip, pos + 42, shift)?;
+        pos += 46 + read_u16(zip, pos + 28) + read_u16(zip, pos + 30) + read_u16(zip, pos + 32);
+    }
+    shift_offset(zip, eocd + 16, shift)
+}
+
+//append the ZIP archive to the PNG file, returns the size of the archive
+pub fn append_zip(png: &Path, zip: &Path) -> Result<usize, String> {
+    let mut archive = read_file_to_vec(zip).map_err(|s| format!("Can't read '{}'. {}", zip.display(), s))?;
+    let mut file = OpenOptions::new().append(true).open(png).map_err(|s| s.to_string())?;
+    let png_len = file.metadata().map_err(|s| s.to_string())?.len();
+
+    let shift = u32::try_from(png_len).map_err(|_| "Image is too large for the ZIP polyglot.")?;
+    relocate_zip(&mut archive, shift).map_err(|s| format!("Can't use '{}'. {}", zip.display(), s))?;
+    file.write_all(&archive).map_err(|s| s.to_string())?;
+    Ok(archive.len())
+}
@@ -0,0 +1,115 @@
+use getopts::Matches;
+use image::DynamicImage;
+use image::imageops::FilterType;
+use stegegg::*;
+use super::diag;
+
+//Cover preparation before embedding. Any editing after embedding destroys the payload, so resizing, cropping
+//and noise are done here on the cover and the message is hidden into the final pixels.
+
+//is any of the preprocessing options given
+pub fn requested(matches: &Matches) -> bool {
+    ["resize", "crop", "denoise", "add-grain"].iter().any(|o| matches.opt_present(o))
+}
+
+//parse "WxH" where one of the sizes may be left out to keep the aspect ratio
+fn parse_dimensions(s: &str, width: u32, height: u32) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid size '{}'. Expected e.g. 4000x3000, 4000x or x3000.", s);
+    let (w, h) = s.split_once('x').ok_or_else(invalid)?;
+    let parse = |v: &str| if v.is_empty() { Ok(None) } else { v.parse::<u32>().ok().filter(|&n| n > 0).map(Some).ok_or_else(invalid) };
+    match (parse(w)?, parse(h)?) {
+        (Some(w), Some(h)) => Ok((w, h)),
+        (Some(w), None) => Ok((w, ((height as u64 * w as u64) / width.max(1) as u64).max(1) as u32)),
+        (None, Some(h)) => Ok((((width as u64 * h as u64) / height.max(1) as u64).max(1) as u32, h)),
+        (None, None) => Err(invalid()),
+    }
+}
+
+//crop "WxH" from the center or "WxH+X+Y" from the given corner
+fn crop(img: DynamicImage, spec: &str) -> Result<DynamicImage, String> {
+    let (size, offset) = spec.split_once('+').map_or((spec, None), |(s, o)| (s, Some(o)));
+    let (w, h) = parse_dimensions(size, img.width(), img.height())?;
+    let (x, y) = match offset {
+        Some(o) => {
+            let (x, y) = o.split_once('+').ok_or_else(|| format!("Invalid crop offset '{}'. Expected e.g. 800x600+10+20.", spec))?;
+            (x.parse::<u32>().map_err(|_| format!("Invalid crop offset '{}'.", spec))?, y.parse::<u32>().map_err(|_| format!("Invalid crop offset '{}'.", spec))?)
+        },
+        None => (img.width().saturating_sub(w) / 2, img.height().saturating_sub(h) / 2),
+    };
+    if x as u64 + w as u64 > img.width() as u64 || y as u64 + h as u64 > img.height() as u64 {
+        return Err(format!("Crop '{}' is outside of the {}x{} cover.", spec, img.width(), img.height()));
+    }
+    Ok(img.crop_imm(x, y, w, h))
+}
+
+//3x3 median filter of the color channels, alpha is kept
+fn denoise(img: DynamicImage) -> DynamicImage {
+    let has_alpha = img.color().has_alpha();
+    let src = img.to_rgba8();
+    let mut out = src.clone();
+    let (width, height) = src.dimensions();
+    for y in 0..height {
+        for x in 0..width {
+            let mut window = [[0u8; 9]; 3];
+            let mut n = 0;
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let p = src.get_pixel(nx, ny);
+                    for c in 0..3 {
+                        window[c][n] = p[c];
+                    }
+                    n += 1;
+                }
+            }
+            let p = out.get_pixel_mut(x, y);
+            for c in 0..3 {
+                window[c][..n].sort_unstable();
+                p[c] = window[c][n / 2];
+            }
+        }
+    }
+    if has_alpha { DynamicImage::ImageRgba8(out) } else { DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(out).into_rgb8()) }
+}
+
+//add uniform noise of the given amplitude to the color channels, natural looking grain masks the LSB changes
+fn add_grain(img: DynamicImage, amplitude: u8) -> Result<DynamicImage, String> {
+    let has_alpha = img.color().has_alpha();
+    let mut rgba = img.into_rgba8();
+    let mut seed = [0; 32];
+    getrandom::fill(&mut seed).map_err(|s| format!("Can't generate random grain. {}", s))?;
+    let mut prng_state = init_prng(&seed, &[], &[]);
+    for p in rgba.pixels_mut() {
+        for c in 0..3 {
+            let n = (xoshiro256pp(&mut prng_state) % (amplitude as u64 * 2 + 1)) as i32 - amplitude as i32;
+            p[c] = (p[c] as i32 + n).clamp(0, 255) as u8;
+        }
+    }
+    Ok(if has_alpha { DynamicImage::ImageRgba8(rgba) } else { DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).into_rgb8()) })
+}
+
+//prepare the cover by the given options in the order crop, resize, denoise and grain
+pub fn preprocess(mut img: DynamicImage, matches: &Matches) -> Result<DynamicImage, String> {
+    let (width, height) = (img.width(), img.height());
+
+    if let Some(spec) = matches.opt_str("crop") {
+        img = crop(img, &spec)?;
+    }
+    if let Some(spec) = matches.opt_str("resize") {
+        let (w, h) = parse_dimensions(&spec, img.width(), img.height())?;
+        img = img.resize_exact(w, h, FilterType::Lanczos3);
+    }
+    if matches.opt_present("denoise") {
+        img = denoise(img);
+    }
+    if let Some(a) = matches.opt_str("add-grain") {
+        let amplitude = a.parse::<u8>().ok().filter(|a| (1..=16).contains(a)).ok_or_else(|| format!("Invalid grain amplitude '{}'. Expected 1 to 16.", a))?;
+        img = add_grain(img, amplitude)?;
+    }
+
+    if (img.width(), img.height()) != (width, height) {
+        println!("Cover prepared, {}x{} -> {}x{}.", width, height, img.width(), img.height());
+    }
+    diag::log(format!("Cover preprocessed to {}x{}.", img.width(), img.height()));
+    Ok(img)
+}
+
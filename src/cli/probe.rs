@@ -0,0 +1,73 @@
+use std::path::Path;
+use getopts::{Matches, Options};
+use stegegg::*;
+use super::{color, key_context, utf8_args};
+use stegegg::files::open_image;
+use super::options::max_extract_size;
+use super::pixel::prepare_spots;
+
+//layout options tried by --auto, the default salted format first and the legacy key derivation next. Custom depths are read from the keyed
+//descriptor of the salted format, so they need no extra candidates.
+const CANDIDATES: [&[&str]; 8] = [
+    &[],
+    &["--redundant"],
+    &["--kdf", "sha256"],
+    &["--redundant", "--kdf", "sha256"],
+    &["--legacy"],
+    &["--legacy", "--redundant"],
+    &["--sequential"],
+    &["--sequential", "--redundant"],
+];
+
+//layout options which can't be given together with --auto
+pub const LAYOUT_OPTIONS: [&str; 6] = ["redundant", "legacy", "sequential", "tiled", "banded", "low-memory"];
+
+//try the layout options until the header (and the trailer) read with the key fits into the image. Returns the command line
+//options with the matching ones added, so the extraction continues as if they were given.
+pub fn probe_options(opts: &Options, matches: &Matches, in_filename: &Path, key: &[u8], to_stdout: bool) -> Result<Matches, ExtractError> {
+
+    let (img, _) = color::normalize(open_image(in_filename).map_err(ExtractError::Decode)?, matches)?;
+    let rgb_img = img.as_rgb8().unwrap();
+
+    //carrier name may have been mixed into the key, unless the context is given explicitly
+    let contexts: &[&[&str]] = if matches.opt_present("context") || matches.opt_present("salt-from-name") { &[&[]] } else { &[&[], &["--salt-from-name"]] };
+
+    //layouts with the trailer go first. Images of stegegg 0.1 have none, so the legacy layouts are tried without it only
+    //when nothing else matched, otherwise the old message left in the spots of another layout could be picked.
+    let passes = [true, false].into_iter().flat_map(|t| CANDIDATES.into_iter().map(move |flags| (t, flags)));
+    for (with_trailer, flags) in passes {
+        for context_flags in contexts {
+
+            //sequential mode ignores the key and the context, key derivation given explicitly isn't probed
+            if (flags.contains(&"--sequential") && !context_flags.is_empty()) || (flags.contains(&"--kdf") && matches.opt_present("kdf"))
+                || (!with_trailer && !flags.contains(&"--legacy")) {
+                continue;
+            }
+            let args: Vec<String> = utf8_args()[1..].iter().cloned().chain(flags.iter().chain(context_flags.iter()).map(|f| f.to_string())).collect();
+            let candidate = opts.parse(&args).map_err(|s| ExtractError::Failed(s.to_string()))?;
+
+            let context = key_context(&candidate, in_filename)?;
+            let (layout, _, indices) = match prepare_spots(&candidate, rgb_img, key, &context, true) {
+                Ok(s) => s,
+                Err(_) => continue,
+            };
+            let msg_len = match read_header_limited(rgb_img, layout, &indices, max_extract_size(&candidate)?) {
+                Ok(len) => len,
+                Err(_) => continue,
+            };
+            if with_trailer && read_trailer(rgb_img, layout, &indices, msg_len).is_err() {
+                continue;
+            }
+
+            let found: Vec<&str> = flags.iter().chain(context_flags.iter()).copied().collect();
+            let msg = if found.is_empty() {
+                "Message found with the default options.".to_string()
+            } else {
+                format!("Message found with {}. Give the options next time to skip the probing.", found.join(" "))
+            };
+            if to_stdout { eprintln!("{}", msg) } else { println!("{}", msg) }
+            return Ok(candidate);
+        }
+    }
+    Err(ExtractError::NoPayload("No combination of the layout options reads a valid header with this key.\nDid you use the correct key?!".to_string()))
+}
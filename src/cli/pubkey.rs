@@ -0,0 +1,95 @@
+//Public key encryption of the payload with age. Message is encrypted to the recipients before it is hidden
+//and decrypted with the identity file after extraction. The key still selects the hidding spots.
+//Besides age keys, SSH keys (ed25519 and rsa) can be used as recipients and identities.
+
+//asks for the passphrase of the encrypted SSH key on the terminal
+#[cfg(feature = "pubkey")]
+#[derive(Clone)]
+struct PromptCallbacks;
+
+#[cfg(feature = "pubkey")]
+impl age::Callbacks for PromptCallbacks {
+    fn display_message(&self, message: &str) {
+        println!("{}", message);
+    }
+
+    fn confirm(&self, _message: &str, _yes_string: &str, _no_string: Option<&str>) -> Option<bool> {
+        None
+    }
+
+    fn request_public_string(&self, _description: &str) -> Option<String> {
+        None
+    }
+
+    fn request_passphrase(&self, description: &str) -> Option<age::secrecy::SecretString> {
+        rpassword::prompt_password(format!("{}: ", description)).ok().map(age::secrecy::SecretString::from)
+    }
+}
+
+//encrypt the data to the age recipients ("age1...") and the SSH public keys from the files
+#[cfg(feature = "pubkey")]
+pub fn encrypt(data: &[u8], recipients: &[String], ssh_files: &[String]) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    use std::str::FromStr;
+
+    let mut parsed: Vec<Box<dyn age::Recipient>> = Vec::new();
+    for r in recipients {
+        match age::x25519::Recipient::from_str(r) {
+            Ok(r) => parsed.push(Box::new(r)),
+            Err(s) => return Err(format!("Invalid recipient '{}'. {}", r, s)),
+        }
+    }
+
+    //one public key per line, as in the .pub and authorized_keys files
+    for path in ssh_files {
+        let keys = std::fs::read_to_string(path).map_err(|s| format!("Can't read SSH public key '{}'. {}", path, s))?;
+        for line in keys.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            match age::ssh::Recipient::from_str(line) {
+                Ok(r) => parsed.push(Box::new(r)),
+                Err(s) => return Err(format!("Unsupported SSH public key in '{}'. {:?}", path, s)),
+            }
+        }
+    }
+
+    let encryptor = age::Encryptor::with_recipients(parsed.iter().map(|r| r.as_ref())).map_err(|s| s.to_string())?;
+    let mut out = Vec::with_capacity(data.len() + 256);
+    let mut writer = encryptor.wrap_output(&mut out).map_err(|s| s.to_string())?;
+    writer.write_all(data).and_then(|_| writer.finish()).map_err(|s| s.to_string())?;
+    Ok(out)
+}
+
+//decrypt the data with the identities from the age identity files or SSH private keys
+#[cfg(feature = "pubkey")]
+pub fn decrypt(data: &[u8], identity_files: &[String]) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let mut identities: Vec<Box<dyn age::Identity>> = Vec::new();
+    for path in identity_files {
+        let content = std::fs::read(path).map_err(|s| format!("Can't read identity file '{}'. {}", path, s))?;
+
+        //SSH private keys are PEM files, encrypted ones ask for the passphrase
+        if content.starts_with(b"-----BEGIN") {
+            let identity = age::ssh::Identity::from_buffer(&content[..], Some(path.clone())).map_err(|s| format!("Can't read SSH private key '{}'. {}", path, s))?;
+            identities.push(Box::new(identity.with_callbacks(PromptCallbacks)));
+        } else {
+            let file = age::IdentityFile::from_buffer(&content[..]).map_err(|s| format!("Can't read identity file '{}'. {}", path, s))?;
+            identities.extend(file.into_identities().map_err(|s| format!("Can't read identity file '{}'. {}", path, s))?);
+        }
+    }
+
+    let decryptor = age::Decryptor::new_buffered(data).map_err(|s| format!("Extracted data is not age encrypted. {}", s))?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|i| i.as_ref())).map_err(|s| format!("Can't decrypt the message. {}", s))?;
+    let mut out = Vec::with_capacity(data.len());
+    reader.read_to_end(&mut out).map_err(|s| format!("Can't decrypt the message. {}", s))?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "pubkey"))]
+pub fn encrypt(_data: &[u8], _recipients: &[String], _ssh_files: &[String]) -> Result<Vec<u8>, String> {
+    Err("stegegg was built without the 'pubkey' feature.".to_string())
+}
+
+#[cfg(not(feature = "pubkey"))]
+pub fn decrypt(_data: &[u8], _identity_files: &[String]) -> Result<Vec<u8>, String> {
+    Err("stegegg was built without the 'pubkey' feature.".to_string())
+}
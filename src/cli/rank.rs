@@ -0,0 +1,99 @@
+use image::RgbImage;
+use stegegg::{capacity, TRAILER_LEN};
+use super::diag;
+use stegegg::files::open_image;
+use super::options::parse_size;
+
+//measured properties of one candidate cover
+struct CoverScore {
+    path: String,
+    capacity: usize,
+    fill: f64,
+    texture: f64,
+    detectability: f64,
+}
+
+//mean absolute difference between neighbouring samples (texture / noise level)
+//and the fraction of samples in flat areas where LSB changes are easier to detect
+fn texture_stats(image: &RgbImage) -> (f64, f64) {
+    let (width, height) = image.dimensions();
+    let mut sum = 0u64;
+    let mut flat = 0u64;
+    let mut count = 0u64;
+
+    for y in 0..height {
+        for x in 0..width {
+            let p = image.get_pixel(x, y);
+            let mut neighbours = Vec::with_capacity(2);
+            if x + 1 < width { neighbours.push(image.get_pixel(x + 1, y)); }
+            if y + 1 < height { neighbours.push(image.get_pixel(x, y + 1)); }
+
+            for n in neighbours {
+                for c in 0..3 {
+                    let d = (p[c] as i16 - n[c] as i16).unsigned_abs() as u64;
+                    sum += d;
+                    flat += (d <= 1) as u64;
+                    count += 1;
+                }
+            }
+        }
+    }
+
+    if count == 0 {
+        return (0.0, 1.0);
+    }
+    (sum as f64 / count as f64, flat as f64 / count as f64)
+}
+
+//score one cover for the payload of given size
+fn score_cover(path: &str, payload_size: usize) -> Result<CoverScore, String> {
+    let image = open_image(path)?.to_rgb8();
+    let capacity = capacity(image.width(), image.height());
+    let (texture, flat) = texture_stats(&image);
+
+    //ratio of modified spots to all spots. Every hidden bit changes a sample with 50% probability.
+    let spots = image.width() as f64 * image.height() as f64 * 3.0;
+    let fill = if spots > 0.0 { (payload_size + 3 + TRAILER_LEN) as f64 * 8.0 / spots } else { f64::INFINITY };
+
+    //changes in flat areas and in low-noise images are easier to spot
+    let detectability = fill * (0.5 + flat / 2.0) / (1.0 + texture / 8.0).sqrt();
+
+    Ok(CoverScore { path: path.to_string(), capacity, fill, texture, detectability })
+}
+
+//score all candidate covers and recommend the best one for the given payload size
+pub fn rank_covers(paths: &[String], payload_size: Option<String>) {
+
+    let payload_size = match payload_size.map(|s| parse_size(&s)) {
+        Some(Ok(n)) => n,
+        Some(Err(s)) => { diag::error(s); return; },
+        None => { println!("Payload size not specified!\nPlease specify it with --payload-size parameter."); return; },
+    };
+
+    if paths.is_empty() {
+        diag::error("No images to rank.");
+        return;
+    }
+
+    let mut scores = Vec::new();
+    for path in paths {
+        match score_cover(path, payload_size) {
+            Ok(s) => scores.push(s),
+            Err(s) => println!("Skipping '{}'. {}", path, s),
+        }
+    }
+
+    //covers which can hold the payload first, then the least detectable
+    scores.sort_by(|a, b| (a.capacity < payload_size).cmp(&(b.capacity < payload_size)).then(a.detectability.total_cmp(&b.detectability)));
+
+    println!("{:>4}  {:>12}  {:>7}  {:>7}  {:>13}  image", "rank", "capacity", "fill", "texture", "detectability");
+    for (i, s) in scores.iter().enumerate() {
+        let fits = if s.capacity >= payload_size { "" } else { "  (too small)" };
+        println!("{:>4}  {:>12}  {:>6.2}%  {:>7.2}  {:>13.4}  {}{}", i + 1, s.capacity, s.fill * 100.0, s.texture, s.detectability, s.path, fits);
+    }
+
+    match scores.first() {
+        Some(s) if s.capacity >= payload_size => println!("\nRecommended carrier: '{}'", s.path),
+        _ => println!("\nNone of the images is large enough for {} bytes payload.", payload_size),
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::json;
+use stegegg::capacity;
+use super::diag;
+
+//recursively collect all files in the directory in sorted order
+pub fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let mut entries = match fs::read_dir(dir) {
+        Ok(rd) => rd.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<PathBuf>>(),
+        Err(s) => return Err(format!("Can't read directory '{}'. {}", dir.display(), s)),
+    };
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+//print capacity of every image in the directory as CSV or JSON
+pub fn capacity_report(dir: Option<&String>, format: Option<String>) {
+
+    let dir = match dir {
+        Some(d) => d,
+        None => { println!("Directory not specified."); return; },
+    };
+
+    let json = match format.as_deref() {
+        None | Some("csv") => false,
+        Some("json") => true,
+        Some(f) => { diag::error(format!("Unknown report format '{}'. Use 'csv' or 'json'.", f)); return; },
+    };
+
+    let mut files = Vec::new();
+    if let Err(s) = collect_files(Path::new(dir), &mut files) {
+        diag::error(s);
+        return;
+    }
+
+    //only image header is read to get the dimensions, files which are not images are skipped
+    let mut records = Vec::new();
+    for path in files {
+        if let Ok((width, height)) = image::image_dimensions(&path) {
+            records.push((path.display().to_string(), width, height, capacity(width, height)));
+        }
+    }
+
+    if json {
+        let list: Vec<_> = records.iter().map(|(path, width, height, capacity)| json!({
+            "path": path,
+            "width": width,
+            "height": height,
+            "capacity": capacity,
+        })).collect();
+        println!("{}", serde_json::to_string_pretty(&list).unwrap());
+    } else {
+        println!("path,width,height,capacity");
+        for (path, width, height, capacity) in records {
+            //quote paths containing separators
+            let path = if path.contains([',', '"', '\n']) { format!("\"{}\"", path.replace('"', "\"\"")) } else { path };
+            println!("{},{},{},{}", path, width, height, capacity);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Arc;
+use serde_json::json;
+use stegegg::{buffer, ExtractError};
+use super::diag;
+
+//HTTP API server. Each endpoint takes a multipart/form-data request with the "image" field, embed and extract
+//also the "key" field and embed the "message" field. Messages are hidden in the default salted LSB format.
+//  POST /embed     -> stego image as PNG
+//  POST /extract   -> extracted message (422 when there is no message for the key)
+//  POST /capacity  -> JSON with the image size and capacity in bytes
+
+//maximal size of the request body
+const MAX_REQUEST_LEN: usize = 64 << 20;
+
+//HTTP status and the error message
+type HttpError = (u16, String);
+
+//split multipart/form-data body into the named fields
+fn parse_multipart(body: &[u8], boundary: &str) -> Result<HashMap<String, Vec<u8>>, String> {
+    let delimiter = format!("\r\n--{}", boundary).into_bytes();
+    let find = |data: &[u8], pattern: &[u8]| data.windows(pattern.len()).position(|w| w == pattern);
+
+    //body starts with the delimiter without the leading CRLF
+    let mut rest = body.strip_prefix(&delimiter[2..]).ok_or("Malformed multipart body.")?;
+    let mut fields = HashMap::new();
+    while !rest.starts_with(b"--") {
+        let headers_end = find(rest, b"\r\n\r\n").ok_or("Malformed multipart part.")?;
+        let headers = String::from_utf8_lossy(&rest[..headers_end]);
+        let content = &rest[headers_end + 4..];
+        let content_end = find(content, &delimiter).ok_or("Unterminated multipart part.")?;
+
+        let name = headers.lines()
+            .find(|l| l.to_ascii_lowercase().starts_with("content-disposition:"))
+            .and_then(|l| l.split(';').map(|p| p.trim()).find_map(|p| p.strip_prefix("name=")))
+            .map(|n| n.trim_matches('"').to_string())
+            .ok_or("Multipart part without the name.")?;
+        fields.insert(name, content[..content_end].to_vec());
+        rest = &content[content_end + delimiter.len()..];
+    }
+    Ok(fields)
+}
+
+//read the request body and parse its form fields
+fn read_form(request: &mut tiny_http::Request) -> Result<HashMap<String, Vec<u8>>, HttpError> {
+    let boundary = request.headers().iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .and_then(|h| h.value.as_str().split(';').map(|p| p.trim()).find_map(|p| p.strip_prefix("boundary=")).map(|b| b.trim_matches('"').to_string()))
+        .ok_or((400, "Request must be multipart/form-data.".to_string()))?;
+
+    let mut body = Vec::new();
+    request.as_reader().take(MAX_REQUEST_LEN as u64 + 1).read_to_end(&mut body).map_err(|s| (400, s.to_string()))?;
+    if body.len() > MAX_REQUEST_LEN {
+        return Err((413, format!("Request is larger than {} bytes.", MAX_REQUEST_LEN)));
+    }
+    parse_multipart(&body, &boundary).map_err(|s| (400, s))
+}
+
+//get the required form field
+fn field<'a>(form: &'a HashMap<String, Vec<u8>>, name: &str) -> Result<&'a [u8], HttpError> {
+    form.get(name).map(|v| v.as_slice()).ok_or((400, format!("Missing '{}' field.", name)))
+}
+
+//hide the message into the image and return it as PNG
+fn embed(form: &HashMap<String, Vec<u8>>) -> Result<(Vec<u8>, &'static str), HttpError> {
+    let key = form.get("key").map(|k| k.as_slice()).unwrap_or_default();
+    let png = buffer::embed_image(field(form, "image")?, key, field(form, "message")?).map_err(|s| (400, s))?;
+    Ok((png, "image/png"))
+}
+
+//extract the message from the image
+fn extract(form: &HashMap<String, Vec<u8>>) -> Result<(Vec<u8>, &'static str), HttpError> {
+    let key = form.get("key").map(|k| k.as_slice()).unwrap_or_default();
+    let msg = buffer::extract_image(field(form, "image")?, key).map_err(|e| match e {
+        ExtractError::NoPayload(s) => (422, s),
+        e => (400, e.to_string()),
+    })?;
+    Ok((msg, "application/octet-stream"))
+}
+
+//size and capacity of the image
+fn capacity_info(form: &HashMap<String, Vec<u8>>) -> Result<(Vec<u8>, &'static str), HttpError> {
+    let (width, height, capacity) = buffer::image_capacity(field(form, "image")?).map_err(|s| (400, s))?;
+    let info = json!({
+        "width": width,
+        "height": height,
+        "capacity_bytes": capacity,
+    });
+    Ok((info.to_string().into_bytes(), "application/json"))
+}
+
+//process one request and send the response
+fn handle(mut request: tiny_http::Request) {
+    let result = if *request.method() != tiny_http::Method::Post {
+        Err((405, "Only POST requests are supported.".to_string()))
+    } else {
+        let endpoint = request.url().split('?').next().unwrap_or_default().to_string();
+        read_form(&mut request).and_then(|form| match endpoint.as_str() {
+            "/embed" => embed(&form),
+            "/extract" => extract(&form),
+            "/capacity" => capacity_info(&form),
+            _ => Err((404, "Unknown endpoint. Use /embed, /extract or /capacity.".to_string())),
+        })
+    };
+
+    let (status, data, content_type) = match result {
+        Ok((data, content_type)) => (200, data, content_type),
+        Err((status, s)) => (status, format!("{}\n", s).into_bytes(), "text/plain; charset=utf-8"),
+    };
+    let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap();
+    let url = request.url().to_string();
+    if let Err(s) = request.respond(tiny_http::Response::from_data(data).with_status_code(status).with_header(header)) {
+        diag::error(format!("Error sending the response to '{}'. {}", url, s));
+    }
+}
+
+//'serve' command. Listens on the address and processes the requests by the pool of workers.
+pub fn serve(listen: Option<String>, workers: usize) {
+    let listen = listen.unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let server = match tiny_http::Server::http(&listen) {
+        Ok(s) => Arc::new(s),
+        Err(s) => { diag::error(format!("Can't listen on '{}'. {}", listen, s)); return; },
+    };
+    println!("Listening on http://{}", listen);
+
+    let workers: Vec<_> = (0..workers).map(|_| {
+        let server = server.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handle(request);
+            }
+        })
+    }).collect();
+
+    for w in workers {
+        let _ = w.join();
+    }
+}
@@ -0,0 +1,121 @@
+use image::{GrayImage, Luma, imageops::FilterType};
+use rustfft::{FftPlanner, num_complex::Complex};
+use super::diag;
+use stegegg::files::open_image;
+
+//Spectrogram steganography. The image is drawn into the upper half of the audio spectrum, each image column
+//is one frame of FRAME samples and each image row one frequency bin. Bins are exact FFT bins, so the tones
+//are periodic within the frame and the extractor reads them back without leakage.
+
+//samples in one frame (one image column)
+const FRAME: usize = 1024;
+
+//first and last used frequency bin (upper half of the spectrum)
+const FIRST_BIN: usize = FRAME / 4;
+const LAST_BIN: usize = FRAME / 2 - 1;
+
+//peak amplitude of the drawn image relative to the full scale
+const LEVEL: f32 = 0.1;
+
+//draw the image into the spectrogram of the cover audio
+fn hide_spectrogram(cover_path: &str, image_path: &str, out_path: &str) -> Result<usize, String> {
+
+    let mut reader = hound::WavReader::open(cover_path).map_err(|s| s.to_string())?;
+    let spec = reader.spec();
+    if spec.sample_format != hound::SampleFormat::Int || spec.bits_per_sample != 16 {
+        return Err("Only 16-bit PCM WAV covers are supported.".to_string());
+    }
+    let mut samples = reader.samples::<i16>().collect::<Result<Vec<i16>, _>>().map_err(|s| s.to_string())?;
+    let channels = spec.channels as usize;
+    let frames = samples.len() / channels / FRAME;
+
+    //scale the image to the available bins and keep the aspect ratio
+    let img = open_image(image_path)?.into_luma8();
+    let rows = LAST_BIN - FIRST_BIN + 1;
+    let columns = ((img.width() as usize * rows) / img.height().max(1) as usize).max(1);
+    if columns > frames {
+        return Err(format!("Cover audio is too short.\nImage needs {} frames of {} samples, cover has only {}!", columns, FRAME, frames));
+    }
+    let img = image::imageops::resize(&img, columns as u32, rows as u32, FilterType::Triangle);
+
+    //per bin random phase, so the tones don't add up into peaks
+    let mut phases = vec![0u8; rows];
+    getrandom::fill(&mut phases).map_err(|s| s.to_string())?;
+
+    //each frame is synthesized by the inverse FFT of the image column
+    let ifft = FftPlanner::<f32>::new().plan_fft_inverse(FRAME);
+    let amplitude = LEVEL * i16::MAX as f32 / (rows as f32).sqrt();
+    for x in 0..columns {
+        let mut buffer = vec![Complex::new(0.0, 0.0); FRAME];
+        for (r, phase) in phases.iter().enumerate() {
+            let lum = img.get_pixel(x as u32, (rows - 1 - r) as u32)[0] as f32 / 255.0;
+            buffer[FIRST_BIN + r] = Complex::from_polar(amplitude * lum / 2.0, 2.0 * std::f32::consts::PI * *phase as f32 / 256.0);
+            buffer[FRAME - FIRST_BIN - r] = buffer[FIRST_BIN + r].conj();
+        }
+        ifft.process(&mut buffer);
+
+        for (n, v) in buffer.iter().enumerate() {
+            let i = ((x * FRAME) + n) * channels;
+            for s in &mut samples[i..i + channels] {
+                *s = (*s as f32 + v.re).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+    }
+
+    let mut writer = hound::WavWriter::create(out_path, spec).map_err(|s| s.to_string())?;
+    for s in samples {
+        writer.write_sample(s).map_err(|s| s.to_string())?;
+    }
+    writer.finalize().map_err(|s| s.to_string())?;
+    Ok(columns)
+}
+
+//render the used part of the spectrogram of the first channel into the image
+fn extract_spectrogram(audio_path: &str, out_path: &str) -> Result<(u32, u32), String> {
+
+    let mut reader = hound::WavReader::open(audio_path).map_err(|s| s.to_string())?;
+    let spec = reader.spec();
+    if spec.sample_format != hound::SampleFormat::Int || spec.bits_per_sample != 16 {
+        return Err("Only 16-bit PCM WAV audio is supported.".to_string());
+    }
+    let samples: Vec<f32> = reader.samples::<i16>().step_by(spec.channels as usize).map(|s| s.map(|v| v as f32)).collect::<Result<_, _>>().map_err(|s| s.to_string())?;
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FRAME);
+    let rows = LAST_BIN - FIRST_BIN + 1;
+    let columns = samples.len() / FRAME;
+    if columns == 0 {
+        return Err("Audio is too short.".to_string());
+    }
+
+    let mut magnitudes = Vec::with_capacity(columns * rows);
+    for frame in samples.chunks_exact(FRAME) {
+        let mut buffer: Vec<Complex<f32>> = frame.iter().map(|&v| Complex::new(v, 0.0)).collect();
+        fft.process(&mut buffer);
+        magnitudes.extend(buffer[FIRST_BIN..=LAST_BIN].iter().map(|c| c.norm()));
+    }
+
+    //normalize magnitudes, high frequencies are at the top
+    let max = magnitudes.iter().cloned().fold(f32::MIN_POSITIVE, f32::max);
+    let mut img = GrayImage::new(columns as u32, rows as u32);
+    for (i, m) in magnitudes.iter().enumerate() {
+        let (x, r) = (i / rows, i % rows);
+        img.put_pixel(x as u32, (rows - 1 - r) as u32, Luma([(m / max * 255.0) as u8]));
+    }
+    img.save(out_path).map_err(|s| s.to_string())?;
+    Ok(img.dimensions())
+}
+
+//'spectrogram' command. Hiding takes the cover audio, the image and the output audio, extraction the audio and the output image.
+pub fn spectrogram(args: &[String], extract: bool) {
+    let result = match (extract, args) {
+        (false, [cover, image, out]) => hide_spectrogram(cover, image, out)
+            .map(|n| println!("Image drawn into {} frames of '{}'.", n, out)),
+        (true, [audio, out]) => extract_spectrogram(audio, out)
+            .map(|(w, h)| println!("Spectrogram {}x{} written to '{}'.", w, h, out)),
+        _ => Err("Usage: stegegg spectrogram <cover.wav> <image> <output.wav>\n       stegegg spectrogram -x <audio.wav> <output.png>".to_string()),
+    };
+
+    if let Err(s) = result {
+        diag::error(s);
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::{Cursor, Read};
+use getopts::{Matches, Options};
+use zeroize::Zeroizing;
+use stegegg::*;
+use super::{diag, extract_any_kdf, message_source, os_arg, output_name};
+use stegegg::files::write_vec_to_file;
+use super::manifest::{options_capacity, PayloadDigest};
+use super::options::encrypt;
+use super::pixel::{hide_file, read_message};
+
+//split the message into the shards and hide one shard into each cover. With --parity the last cover gets the XOR of
+//the other shards, so any single carrier of the set can be lost. Carriers are written into the output directory.
+pub fn split(matches: &Matches, args: &[String], key: &[u8]) {
+
+    let (covers, out_dir) = match args {
+        [covers @ .., out] if !covers.is_empty() => (covers.iter().map(|c| os_arg(c)).collect::<Vec<_>>(), os_arg(out)),
+        _ => { println!("Usage: stegegg split -k <key> -m|-M <message> [--parity] <covers...> <output directory>"); return; },
+    };
+    let parity = matches.opt_present("parity");
+    if parity && covers.len() < 2 {
+        diag::error("Option --parity needs at least two covers, the last one carries the parity.");
+        return;
+    }
+
+    let result = message_source(matches, &PayloadDigest::default()).and_then(|(mut reader, _)| {
+        let mut msg = Zeroizing::new(Vec::new());
+        reader.read_to_end(&mut msg).map_err(|s| s.to_string())?;
+        split::split(&msg, covers.len() - parity as usize, parity)
+    });
+    let shards = match result {
+        Ok(s) => s,
+        Err(s) => { diag::error(s); return; },
+    };
+
+    //all covers are checked before anything is written, so a cover too small doesn't leave a partial set
+    let overhead = if encrypt(matches) { aead::OVERHEAD } else { 0 };
+    for (cover, shard) in covers.iter().zip(&shards) {
+        let capacity = image::image_dimensions(cover).map_err(|s| format!("Can't read '{}'. {}", cover.display(), s))
+            .and_then(|(width, height)| options_capacity(matches, width, height));
+        match capacity {
+            Ok(c) if shard.len() + overhead <= c => {},
+            Ok(c) => {
+                diag::error(format!("Shard has {} bytes, the cover '{}' holds only {} bytes.", shard.len() + overhead, cover.display(), c));
+                diag::hint("use larger covers or more of them.");
+                return;
+            },
+            Err(s) => { diag::error(s); return; },
+        }
+    }
+    if let Err(s) = std::fs::create_dir_all(&out_dir) {
+        diag::error(format!("Can't create the directory '{}'. {}", out_dir.display(), s));
+        return;
+    }
+
+    for (i, (cover, shard)) in covers.iter().zip(&shards).enumerate() {
+        let mut name = cover.file_stem().unwrap_or_default().to_os_string();
+        name.push(".png");
+        let result = output_name(matches, cover, &out_dir.join(name))
+            .and_then(|out| hide_file(matches, cover, &out, key, &mut Cursor::new(shard.as_slice()), Some(shard.len())).map(|_| out));
+        match result {
+            Ok(out) if parity && i == shards.len() - 1 => println!("Parity shard hidden into '{}'.", out.display()),
+            Ok(out) => println!("Shard {} of {} hidden into '{}'.", i + 1, shards.len() - parity as usize, out.display()),
+            Err(s) => { diag::error(format!("Can't hide the shard into '{}'. {}", cover.display(), s)); return; },
+        }
+    }
+    diag::log("Split hiding finished.");
+}
+
+//extract the shards from the carriers and join them into the output. Carriers which can't be read are skipped,
+//one missing data shard is rebuilt from the parity shard.
+pub fn join(opts: &Options, matches: &Matches, args: &[String], key: &[u8]) -> Result<(), ExtractError> {
+
+    let (carriers, out_filename) = match args {
+        [carriers @ .., out] if !carriers.is_empty() => (carriers, os_arg(out)),
+        _ => { println!("Usage: stegegg join -k <key> [extraction options] <carriers...> <output>"); return Ok(()); },
+    };
+
+    let mut shards = Vec::new();
+    for carrier in carriers {
+        let path = os_arg(carrier);
+        match extract_any_kdf(opts, matches, key, |m, key| read_message(m, &path, key)) {
+            Ok(shard) => shards.push(shard),
+            Err(e) => diag::warning(format!("Carrier '{}' is skipped. {}", path.display(), e)),
+        }
+    }
+    let msg = split::join(&shards)?;
+    println!("Message with {} bytes joined from {} carriers.", msg.len(), shards.len());
+    let out_filename = output_name(matches, &os_arg(&carriers[0]), &out_filename)?;
+    write_vec_to_file(&out_filename, &msg)?;
+    Ok(())
+}
+
+//check the carriers of the split message: every carrier is extracted with its CRC check and its shard header is
+//printed, then the set membership and the completeness. The joined message isn't written.
+pub fn verify_set(opts: &Options, matches: &Matches, args: &[String], key: &[u8]) -> Result<(), ExtractError> {
+
+    if args.is_empty() {
+        println!("Usage: stegegg verify-set -k <key> [extraction options] <carriers...>");
+        return Ok(());
+    }
+
+    let mut shards = Vec::new();
+    for carrier in args {
+        let path = os_arg(carrier);
+        let result = extract_any_kdf(opts, matches, key, |m, key| read_message(m, &path, key)).and_then(|shard| {
+            let header = split::shard_header(&shard).ok_or_else(|| ExtractError::Decode("Carrier doesn't hold a shard of the split message.".into()))?;
+            Ok((header, shard))
+        });
+        match result {
+            Ok((header, shard)) => {
+                let shard_name = if header.is_parity() { "parity shard".to_string() } else { format!("shard {} of {}", header.index as usize + 1, header.data_shards) };
+                println!("'{}': {} of the set {}, CRC valid.", path.display(), shard_name, set_name(&header));
+                shards.push(shard);
+            },
+            Err(e) => diag::warning(format!("Carrier '{}' is damaged or not in the set. {}", path.display(), e)),
+        }
+    }
+
+    //joining checks that all shards are of one set and that no more than the parity can rebuild is missing
+    let msg = split::join(&shards)?;
+    let header = split::shard_header(&shards[0]).ok_or_else(|| ExtractError::Decode("Carrier doesn't hold a shard of the split message.".into()))?;
+    let present: Vec<u8> = shards.iter().filter_map(|s| split::shard_header(s)).map(|h| h.index).collect();
+    match (0..header.data_shards).find(|i| !present.contains(i)) {
+        None => println!("Set {} is complete, the message has {} bytes.", set_name(&header), msg.len()),
+        Some(i) => println!("Set {} is recoverable, shard {} is missing and is rebuilt from the parity shard. The message has {} bytes.", set_name(&header), i as usize + 1, msg.len()),
+    }
+    Ok(())
+}
+
+fn set_name(header: &split::ShardHeader) -> String {
+    header.set_id.iter().map(|b| format!("{:02x}", b)).collect()
+}
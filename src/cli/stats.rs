@@ -0,0 +1,49 @@
+use image::RgbImage;
+use serde_json::json;
+
+//Statistics of the pixel modifications made by the embedding, so the visibility and detectability of the
+//message can be judged. About half of the written bits already match the cover and change nothing.
+
+//number of changed samples in each channel
+fn changed_samples(cover: &RgbImage, image: &RgbImage) -> [usize; 3] {
+    let mut changed = [0; 3];
+    for (p, cp) in image.pixels().zip(cover.pixels()) {
+        for c in 0..3 {
+            if p[c] != cp[c] {
+                changed[c] += 1;
+            }
+        }
+    }
+    changed
+}
+
+//print the statistics as text or JSON. Used and capacity are numbers of hidding spots.
+pub fn report_stats(cover: &RgbImage, image: &RgbImage, used: usize, capacity: usize, format: Option<&str>) -> Result<(), String> {
+
+    let [r, g, b] = changed_samples(cover, image);
+    let changed = r + g + b;
+    let samples = image.len();
+    let percent = |n: usize, of: usize| if of > 0 { n as f64 / of as f64 * 100.0 } else { 0.0 };
+
+    match format {
+        None | Some("text") => {
+            println!("Samples changed: {} of {} ({:.3}%)", changed, samples, percent(changed, samples));
+            println!("Per channel: r {}, g {}, b {}", r, g, b);
+            println!("Spots used: {} of {} ({:.2}% of capacity)", used, capacity, percent(used, capacity));
+            println!("Change rate: {:.3}% of capacity, {:.3} changes per used spot", percent(changed, capacity), if used > 0 { changed as f64 / used as f64 } else { 0.0 });
+        },
+        Some("json") => {
+            let stats = json!({
+                "samples_total": samples,
+                "samples_changed": changed,
+                "changed_per_channel": { "r": r, "g": g, "b": b },
+                "spots_used": used,
+                "spots_capacity": capacity,
+                "change_rate": if capacity > 0 { changed as f64 / capacity as f64 } else { 0.0 },
+            });
+            println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        },
+        Some(f) => return Err(format!("Unknown statistics format '{}'. Use 'text' or 'json'.", f)),
+    }
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::io::{IsTerminal, Read, Write};
+use std::path::Path;
+use getopts::{Matches, Options};
+use zeroize::Zeroizing;
+use stegegg::*;
+use stegegg::audio::AudioCarrier;
+use super::i18n::tr;
+use super::{archive, preprocess};
+use super::{decrypt_output, extract_any_kdf, key_context, looks_binary, message_source, require_label};
+use super::manifest::{self, PayloadDigest};
+use stegegg::files::{write_atomic, write_vec_to_file};
+use super::options::{kdf, max_extract_size, max_fill};
+use super::pixel::pack_message;
+
+//options of the image layouts and modes which don't apply to the audio carriers
+pub const IMAGE_OPTIONS: [&str; 15] = ["redundant", "depth", "legacy", "sequential", "tiled", "banded", "low-memory", "use-alpha", "lsb-matching",
+    "restore-histogram", "auto", "range", "resume", "seed", "in-place"];
+
+//carrier is a WAV file, by the extension or by the RIFF/WAVE magic
+pub fn is_wav(path: &Path) -> bool {
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("wav")) {
+        return true;
+    }
+    let mut magic = [0; 12];
+    std::fs::File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_ok() && &magic[..4] == b"RIFF" && &magic[8..] == b"WAVE"
+}
+
+//open the audio and check it holds the salt
+fn open_audio(path: &Path) -> Result<AudioCarrier, String> {
+    let audio = AudioCarrier::open(path).map_err(|s| format!("Error decoding the audio '{}'. {}", path.display(), s))?;
+    if audio.spot_count() < RESERVED_SPOTS + 3 * 8 {
+        return Err("Audio is too short!".to_string());
+    }
+    Ok(audio)
+}
+
+//hide message from the reader into the sample LSBs of the WAV cover
+pub fn hide_wav(matches: &Matches, in_filename: &Path, out_filename: &Path, key: &[u8], reader: &mut dyn Read, len: Option<usize>) -> Result<(), String> {
+
+    let (mut packed, params) = pack_message(matches, key, reader)?;
+    let (reader, len): (&mut dyn Read, Option<usize>) = match &mut packed {
+        Some(p) => { let len = p.get_ref().len(); (p, Some(len)) },
+        None => (reader, len),
+    };
+
+    let mut audio = open_audio(in_filename)?;
+    let context = key_context(matches, out_filename)?;
+    let salt = random_salt()?;
+    let indices = audio.spots(&kdf(matches)?.derive(key, &salt)?, &salt, &context);
+    carrier::write_salt(&mut audio, &salt);
+
+    let max_fill = max_fill(matches)?;
+    let used = carrier::hide_message(reader, len, &mut audio, &indices, params)?;
+    let capacity = audio.capacity();
+    let payload = used / 8 - 3 - TRAILER_LEN;
+    let fill = payload as f64 / capacity.max(1) as f64;
+    println!("Payload of {} hidden, {:.1}% of the {} capacity used.", human_size(payload), fill * 100.0, human_size(capacity));
+    if fill > max_fill {
+        return Err(format!("Payload uses {:.1}% of the capacity, above the maximum {:.1}%. Output is not written, use a shorter message or a longer cover.", fill * 100.0, max_fill * 100.0));
+    }
+
+    write_atomic(out_filename, |tmp| audio.save(tmp)).map_err(|s| tr!("file-error", file = out_filename.display().to_string(), error = s))?;
+    println!("{}", tr!("message-hidden", file = out_filename.display().to_string()));
+    Ok(())
+}
+
+//read the whole message from the sample LSBs of the WAV file, verified and unpacked
+pub fn read_wav(matches: &Matches, in_filename: &Path, key: &[u8]) -> Result<Zeroizing<Vec<u8>>, ExtractError> {
+
+    let audio = open_audio(in_filename).map_err(ExtractError::Decode)?;
+    let context = key_context(matches, in_filename)?;
+    let salt = carrier::read_salt(&audio);
+    let indices = audio.spots(&kdf(matches)?.derive(key, &salt)?, &salt, &context);
+
+    let max_len = max_extract_size(matches)?;
+    let msg_len = carrier::read_header_limited(&audio, &indices, stored_limit(max_len))?;
+    let trailer = carrier::verify_payload(&audio, &indices, msg_len)?;
+    require_label(matches, Some(&trailer))?;
+    let msg = Zeroizing::new(carrier::read_bytes(&audio, &indices[3 * 8..], 0, msg_len));
+    unpack_message(key, msg, Some(&trailer), max_len)
+}
+
+//extract the message from the sample LSBs of the WAV file into the output file
+pub fn extract_wav(matches: &Matches, in_filename: &Path, out_filename: &Path, key: &[u8]) -> Result<(), ExtractError> {
+
+    let msg = read_wav(matches, in_filename, key)?;
+
+    //"-" writes to stdout. Binary data isn't printed to the terminal, as it may corrupt it.
+    if out_filename == Path::new("-") {
+        if std::io::stdout().is_terminal() && !matches.opt_present("force-binary") && looks_binary(&msg[..msg.len().min(8192)]) {
+            return Err(tr!("binary-to-terminal").into());
+        }
+        std::io::stdout().lock().write_all(&msg).map_err(|s| tr!("file-error", file = "-", error = s.to_string()))?;
+        eprintln!("{}", tr!("bytes-written-stdout", count = msg.len()));
+    } else {
+        let n = write_vec_to_file(out_filename, &msg).map_err(|s| tr!("file-error", file = out_filename.display().to_string(), error = s))?;
+        println!("{}", tr!("bytes-written", count = n, file = out_filename.display().to_string()));
+    }
+    decrypt_output(matches, out_filename)
+}
+
+//hide or extract the message of the WAV carrier, the archive included
+pub fn process_wav(opts: &Options, matches: &Matches, in_filename: &Path, out_filename: &Path, key: &[u8]) -> Result<(), ExtractError> {
+    if IMAGE_OPTIONS.iter().any(|o| matches.opt_present(o)) || preprocess::requested(matches) || matches.opt_str("mode").as_deref() == Some("chunk") {
+        return Err(ExtractError::Failed("WAV carriers are hidden in the sample LSBs, the image layout and mode options can't be used with them.".to_string()));
+    }
+    if matches.opt_present("x") {
+        extract_any_kdf(opts, matches, key, |m, key| if m.opt_present("archive") {
+            archive::extract(m, in_filename, out_filename, key)
+        } else {
+            extract_wav(m, in_filename, out_filename, key)
+        })
+    } else {
+        let digest = PayloadDigest::default();
+        let (mut reader, len) = message_source(matches, &digest)?;
+        hide_wav(matches, in_filename, out_filename, key, &mut reader, len)?;
+        manifest::write_manifest(matches, in_filename, out_filename, &digest);
+        Ok(())
+    }
+}
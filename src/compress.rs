@@ -0,0 +1,200 @@
+//! Compression of the message before the embedding (and the optional encryption). Fewer message bytes
+//! mean fewer modified samples. The message is stored compressed only when it gets smaller, and the id of
+//! the codec is stored in the trailer flags, so the extraction needs no option.
+
+use std::io::{Read, Write};
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use zeroize::Zeroizing;
+use crate::{ExtractError, Trailer};
+
+/// Codec of the compressed message
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Codec {
+    /// Deflate at the best compression
+    Deflate,
+    /// LZ4 block with the prepended size, the fastest
+    Lz4,
+    /// Brotli at the quality 9, the best ratio and the slowest
+    Brotli,
+}
+
+/// Codecs in the order of their speed, the automatic choice prefers the earlier ones
+pub const CODECS: [Codec; 3] = [Codec::Lz4, Codec::Deflate, Codec::Brotli];
+
+//size of the message sample compressed by each codec for the automatic choice
+const SAMPLE_LEN: usize = 1 << 16;
+
+//faster codec is chosen when its output is at most this much larger than the smallest one
+const SPEED_TOLERANCE: f64 = 1.05;
+
+//brotli quality and window, quality 9 is several times faster than the maximal 11 and close in ratio
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW: u32 = 22;
+
+impl Codec {
+
+    /// Id of the codec in the trailer flags, 0 is the uncompressed message
+    pub fn id(&self) -> u8 {
+        match self {
+            Codec::Deflate => 1,
+            Codec::Lz4 => 2,
+            Codec::Brotli => 3,
+        }
+    }
+
+    /// Codec of the id, None for the ids of the newer versions
+    pub fn from_id(id: u8) -> Option<Codec> {
+        CODECS.into_iter().find(|c| c.id() == id)
+    }
+
+    /// Name of the codec for --codec
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Deflate => "deflate",
+            Codec::Lz4 => "lz4",
+            Codec::Brotli => "brotli",
+        }
+    }
+
+    /// Codec of the name, case insensitive
+    pub fn from_name(name: &str) -> Option<Codec> {
+        CODECS.into_iter().find(|c| c.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Compressed data, None when the encoder fails
+    pub fn compress(&self, msg: &[u8]) -> Option<Zeroizing<Vec<u8>>> {
+        match self {
+            Codec::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+                encoder.write_all(msg).ok()?;
+                Some(Zeroizing::new(encoder.finish().ok()?))
+            },
+            Codec::Lz4 => Some(Zeroizing::new(lz4_flex::block::compress_prepend_size(msg))),
+            Codec::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                encoder.write_all(msg).ok()?;
+                encoder.flush().ok()?;
+                Some(Zeroizing::new(encoder.into_inner()))
+            },
+        }
+    }
+
+    /// Decompress the data, which must not grow above max_len
+    pub fn decompress(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, ExtractError> {
+        let error = |s: String| ExtractError::Decode(format!("Can't decompress the message. {}", s));
+        let mut msg = Vec::new();
+        match self {
+            Codec::Deflate => {
+                DeflateDecoder::new(data).take(max_len as u64 + 1).read_to_end(&mut msg).map_err(|s| error(s.to_string()))?;
+            },
+            Codec::Lz4 => {
+                //size is checked against the limit before anything is allocated by it
+                let size = data.get(..4).ok_or_else(|| error("Data is truncated.".to_string()))?;
+                let size = u32::from_le_bytes(size.try_into().unwrap()) as usize;
+                if size > max_len {
+                    return Err(too_large(max_len));
+                }
+                msg = lz4_flex::block::decompress(&data[4..], size).map_err(|s| error(s.to_string()))?;
+            },
+            Codec::Brotli => {
+                brotli::Decompressor::new(data, 4096).take(max_len as u64 + 1).read_to_end(&mut msg).map_err(|s| error(s.to_string()))?;
+            },
+        }
+        if msg.len() > max_len {
+            return Err(too_large(max_len));
+        }
+        Ok(msg)
+    }
+}
+
+fn too_large(max_len: usize) -> ExtractError {
+    ExtractError::Failed(format!("Decompressed message is above the extraction limit of {} bytes.", max_len))
+}
+
+/// Codec with the best ratio on the sample of the message, a faster one when it is nearly as good
+pub fn select(msg: &[u8]) -> Codec {
+    let sample = &msg[..msg.len().min(SAMPLE_LEN)];
+    let sizes: Vec<(Codec, usize)> = CODECS.iter().filter_map(|c| Some((*c, c.compress(sample)?.len()))).collect();
+    let best = sizes.iter().map(|(_, n)| *n).min().unwrap_or(0);
+    sizes.iter().find(|(_, n)| *n as f64 <= best as f64 * SPEED_TOLERANCE).map_or(Codec::Deflate, |(c, _)| *c)
+}
+
+/// Message compressed by the codec (chosen by select when None), None when the compression doesn't make it smaller
+pub fn compress(msg: &[u8], codec: Option<Codec>) -> Option<(Codec, Zeroizing<Vec<u8>>)> {
+    let codec = codec.unwrap_or_else(|| select(msg));
+    let compressed = codec.compress(msg)?;
+    (compressed.len() < msg.len()).then_some((codec, compressed))
+}
+
+/// Extracted (and decrypted) message as it was given for hiding, decompressed by the codec in the trailer flags
+pub fn unpack(msg: Zeroizing<Vec<u8>>, trailer: Option<&Trailer>, max_len: usize) -> Result<Zeroizing<Vec<u8>>, ExtractError> {
+    match trailer.and_then(|t| t.codec()) {
+        Some(codec) => Ok(Zeroizing::new(codec.decompress(&msg, max_len)?)),
+        None => Ok(msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(len: usize) -> Vec<u8> {
+        b"the message is compressed before hiding, ".iter().cycle().take(len).copied().collect()
+    }
+
+    #[test]
+    fn every_codec_id_round_trips() {
+        let msg = text(10_000);
+        for id in 1..=3 {
+            let codec = Codec::from_id(id).unwrap();
+            assert_eq!(codec.id(), id);
+            assert_eq!(Codec::from_name(&codec.name().to_uppercase()), Some(codec));
+            let compressed = codec.compress(&msg).unwrap();
+            assert!(compressed.len() < msg.len(), "{}", codec.name());
+            assert_eq!(codec.decompress(&compressed, msg.len()).unwrap(), msg, "{}", codec.name());
+        }
+    }
+
+    #[test]
+    fn unknown_codec_id_is_refused() {
+        //0 is the uncompressed message, the rest of the 2 bit field is free for the newer versions
+        assert_eq!(Codec::from_id(0), None);
+        assert_eq!(Codec::from_id(4), None);
+        assert_eq!(Codec::from_name("zstd"), None);
+    }
+
+    #[test]
+    fn select_chooses_a_codec_which_shrinks_the_message() {
+        let msg = text(100_000);
+        let codec = select(&msg);
+        let (used, compressed) = compress(&msg, None).unwrap();
+        assert_eq!(used, codec);
+        assert_eq!(codec.decompress(&compressed, msg.len()).unwrap(), msg);
+
+        //random data doesn't get smaller and is stored as it is
+        let mut random = vec![0u8; 4096];
+        getrandom::fill(&mut random).unwrap();
+        assert!(compress(&random, None).is_none());
+        assert!(compress(&random, Some(Codec::Brotli)).is_none());
+    }
+
+    #[test]
+    fn bomb_above_the_limit_is_refused() {
+        //1 MB of zeros compresses to a few hundred bytes (brotli and deflate) or a few kB (lz4)
+        let zeros = vec![0u8; 1 << 20];
+        for codec in CODECS {
+            let bomb = codec.compress(&zeros).unwrap();
+            assert!(bomb.len() < 5000, "{}", codec.name());
+            assert!(matches!(codec.decompress(&bomb, 1000), Err(ExtractError::Failed(_))), "{}", codec.name());
+            assert_eq!(codec.decompress(&bomb, zeros.len()).unwrap().len(), zeros.len());
+        }
+
+        //lz4 size prefix is checked before the allocation
+        let mut forged = u32::MAX.to_le_bytes().to_vec();
+        forged.extend_from_slice(&[0; 8]);
+        assert!(matches!(Codec::Lz4.decompress(&forged, 1000), Err(ExtractError::Failed(_))));
+        assert!(matches!(Codec::Lz4.decompress(&[1, 2], 1000), Err(ExtractError::Decode(_))));
+    }
+}
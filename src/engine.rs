@@ -0,0 +1,302 @@
+//! Salted LSB engine for Rust programs. Images are in the format of the command line pixel mode, so they are
+//! readable by `stegegg -x` with the same key, context and layout options and vice versa.
+//!
+//! ```
+//! # use stegegg::StegoEngine;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # let mut image = image::RgbImage::new(64, 64);
+//! let engine = StegoEngine::new(b"key").redundant(true);
+//! engine.embed(&mut image, b"message")?;
+//! let message = engine.extract(&image)?;
+//! # assert_eq!(message, b"message");
+//! # Ok(())
+//! # }
+//! ```
+
+use image::RgbImage;
+use zeroize::Zeroizing;
+use crate::*;
+
+/// Reason of the failed embedding
+#[derive(Debug)]
+pub enum EmbedError {
+    /// Image is smaller than the salt area
+    TooSmall,
+    /// Message doesn't fit into the image, both in bytes
+    TooLarge {
+        /// Length of the message
+        len: usize,
+        /// Capacity of the image
+        capacity: usize,
+    },
+    /// Streamed message of unknown length has more bytes than the capacity
+    Overflow {
+        /// Capacity of the image
+        capacity: usize,
+    },
+    /// Reading the message failed
+    Io(std::io::Error),
+    /// Random generator and other errors
+    Failed(String),
+}
+
+impl std::fmt::Display for EmbedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EmbedError::TooSmall => write!(f, "Image is too small!"),
+            EmbedError::TooLarge { len, capacity } => write!(f, "{}", too_large(*len, *capacity)),
+            EmbedError::Overflow { capacity } => write!(f, "Input message is too large.\nMessage has more than {}, which is the capacity of the image.", human_size(*capacity)),
+            EmbedError::Io(e) => write!(f, "{}", e),
+            EmbedError::Failed(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl std::error::Error for EmbedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EmbedError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for EmbedError {
+    fn from(e: std::io::Error) -> EmbedError {
+        EmbedError::Io(e)
+    }
+}
+
+impl From<EmbedError> for String {
+    fn from(e: EmbedError) -> String {
+        e.to_string()
+    }
+}
+
+/// Key, context and layout of the embedding. The key is wiped from the memory when the engine is dropped.
+pub struct StegoEngine {
+    key: Zeroizing<Vec<u8>>,
+    context: Vec<u8>,
+    layout: SpotLayout,
+    encrypt: bool,
+    kdf: Kdf,
+    matching: bool,
+    compress: bool,
+    codec: Option<compress::Codec>,
+    label: Option<String>,
+    required_label: Option<String>,
+}
+
+impl StegoEngine {
+
+    /// Engine with the key, no context and the default layout (one bit in each channel). The message is encrypted by
+    /// the key as `stegegg` does by default, an empty key hides it plain.
+    pub fn new(key: &[u8]) -> StegoEngine {
+        StegoEngine { key: Zeroizing::new(key.to_vec()), context: Vec::new(), layout: SpotLayout { redundant: false, depth: DEFAULT_DEPTH }, encrypt: !key.is_empty(), kdf: Kdf::Scrypt, matching: false, compress: false, codec: None,
+            label: None, required_label: None }
+    }
+
+    /// Context mixed into the key, e.g. the carrier file name as --salt-from-name does
+    pub fn with_context(mut self, context: &[u8]) -> StegoEngine {
+        self.context = context.to_vec();
+        self
+    }
+
+    /// Spots are whole pixels with the bit in all three channels, majority voted on extraction (--redundant)
+    pub fn redundant(mut self, redundant: bool) -> StegoEngine {
+        self.layout.redundant = redundant;
+        self
+    }
+
+    /// Bits used in each channel (--depth). It is recorded in the image, so the extraction doesn't need it.
+    pub fn with_depth(mut self, depth: [u8; 3]) -> StegoEngine {
+        self.layout.depth = depth;
+        self
+    }
+
+    /// Message is encrypted and authenticated by the key (on by default, `false` is --no-encrypt). The trailer flag
+    /// tells the extraction to decrypt it, so the option is needed only for hiding.
+    pub fn encrypted(mut self, encrypt: bool) -> StegoEngine {
+        self.encrypt = encrypt;
+        self
+    }
+
+    /// Derivation of the spot order and encryption key from the password (--kdf), Kdf::Scrypt by default.
+    /// Kdf::Sha256 reads the carriers of the older versions.
+    pub fn with_kdf(mut self, kdf: Kdf) -> StegoEngine {
+        self.kdf = kdf;
+        self
+    }
+
+    /// Samples are changed by +-1 instead of the LSB replacement (--lsb-matching), only with the default depth
+    pub fn lsb_matching(mut self, matching: bool) -> StegoEngine {
+        self.matching = matching;
+        self
+    }
+
+    /// Message is compressed when it gets smaller (--compress), extraction decompresses it in any case
+    pub fn compressed(mut self, compress: bool) -> StegoEngine {
+        self.compress = compress;
+        self
+    }
+
+    /// Codec of the compression (--codec) instead of the one chosen by compress::select
+    pub fn codec(mut self, codec: compress::Codec) -> StegoEngine {
+        self.codec = Some(codec);
+        self
+    }
+
+    /// Label stored with the message (--label), at most MAX_LABEL_LEN bytes. It is kept in the trailer, not in the
+    /// encrypted envelope, so it shouldn't tell what the message is.
+    pub fn with_label(mut self, label: &str) -> StegoEngine {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Extraction fails unless the message has this label (--require-label)
+    pub fn require_label(mut self, label: &str) -> StegoEngine {
+        self.required_label = Some(label.to_string());
+        self
+    }
+
+    /// Capacity of the image in message bytes, less the encryption overhead of the encrypted messages
+    pub fn capacity(&self, image: &RgbImage) -> usize {
+        self.layout.spot_count(image).map_or(0, |spots| capacity_for(self.layout, spots)).saturating_sub(if self.encrypt { aead::OVERHEAD } else { 0 })
+    }
+
+    //hidding spots in the key, salt and context dependent order
+    fn indices(&self, image: &RgbImage, layout: SpotLayout, spot_key: &[u8], salt: &[u8]) -> Result<Vec<u32>, String> {
+        let mut prng_state = init_prng(spot_key, salt, &self.context);
+        hidding_spots(image, layout, layout.first_spot(), &mut prng_state)
+    }
+
+    /// Hide the message into the image with the new random salt
+    pub fn embed(&self, image: &mut RgbImage, message: &[u8]) -> Result<(), EmbedError> {
+        let layout = self.layout;
+        if layout.depth.iter().any(|&d| d > 8) || layout.depth == [0; 3] {
+            return Err(EmbedError::Failed("Depth must be 0 to 8 bits per channel with at least one channel carrying bits.".to_string()));
+        }
+        if self.matching && !layout.redundant && layout.depth != DEFAULT_DEPTH {
+            return Err(EmbedError::Failed("LSB matching can be used only with the default depth.".to_string()));
+        }
+        if layout.spot_count(image).map_err(EmbedError::Failed)? < layout.first_spot() {
+            return Err(EmbedError::TooSmall);
+        }
+        if let Some(label) = &self.label {
+            check_label(label).map_err(EmbedError::Failed)?;
+        }
+        let compressed = if self.compress { compress::compress(message, self.codec) } else { None };
+        let (message, mut flags) = match &compressed {
+            Some((codec, c)) => (&c[..], codec_flags(*codec)),
+            None => (message, 0),
+        };
+        let capacity = self.capacity(image).saturating_sub(self.label.as_ref().map_or(0, |l| 1 + l.len()));
+        if message.len() > capacity {
+            return Err(EmbedError::TooLarge { len: message.len(), capacity });
+        }
+        let sealed;
+        let message = if self.encrypt {
+            sealed = Zeroizing::new(aead::seal(&self.key, message, self.kdf).map_err(EmbedError::Failed)?);
+            flags |= FLAG_ENCRYPTED;
+            &sealed[..]
+        } else {
+            message
+        };
+
+        let cover = if self.matching { Some(image.clone()) } else { None };
+        let salt = random_salt().map_err(EmbedError::Failed)?;
+        let spot_key = self.kdf.derive(&self.key, &salt).map_err(EmbedError::Failed)?;
+        let indices = self.indices(image, layout, &spot_key, &salt).map_err(EmbedError::Failed)?;
+        write_salt(image, layout, &salt);
+        if !layout.redundant && layout.depth != DEFAULT_DEPTH {
+            write_depth(image, &spot_key, &salt, &self.context, layout.depth);
+        }
+        carrier::hide_message(&mut &message[..], Some(message.len()), &mut ImageCarrier { image: &mut *image, layout }, &indices, TrailerParams { flags, kdf: Some(self.kdf), label: self.label.clone() })?;
+        if let Some(cover) = cover {
+            let mut prng_state = init_prng(&random_salt().map_err(EmbedError::Failed)?, &[], &[]);
+            lsb_matching(&cover, image, &mut prng_state);
+        }
+        Ok(())
+    }
+
+    /// Extract the message from the image
+    pub fn extract(&self, image: &RgbImage) -> Result<Vec<u8>, ExtractError> {
+        self.extract_limited(image, MAX_MSG_LEN)
+    }
+
+    /// Extract the message, which must not be larger than max_len, from the image
+    pub fn extract_limited(&self, image: &RgbImage, max_len: usize) -> Result<Vec<u8>, ExtractError> {
+        let mut layout = self.layout;
+        layout.depth = DEFAULT_DEPTH;
+        if layout.spot_count(image).map_err(ExtractError::Decode)? < layout.first_spot() {
+            return Err(ExtractError::Decode("Image is too small!".to_string()));
+        }
+
+        //custom depth is read from the descriptor after the salt
+        let salt = read_salt(image, layout);
+        let spot_key = self.kdf.derive(&self.key, &salt)?;
+        if !layout.redundant {
+            layout.depth = read_depth(image, &spot_key, &salt, &self.context).unwrap_or(DEFAULT_DEPTH);
+        }
+        let indices = self.indices(image, layout, &spot_key, &salt).map_err(ExtractError::Decode)?;
+        let len = read_header_limited(image, layout, &indices, stored_limit(max_len))?;
+        let trailer = verify_payload(image, layout, &indices, len)?;
+        if let Some(label) = &self.required_label {
+            trailer.require_label(label)?;
+        }
+        let data = Zeroizing::new(extract_bytes(image, layout, &indices[3 * 8..], 0, len));
+        Ok(unpack_message(&self.key, data, Some(&trailer), max_len)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //image with distinct pixel values, so the written bits can't match it by chance
+    fn noise(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 7 + y) as u8, (x * 13 + y * 3) as u8, (x + y * 11) as u8]))
+    }
+
+    #[test]
+    fn message_round_trip_through_the_builder() {
+        let msg: Vec<u8> = b"message hidden by the engine ".iter().cycle().take(300).copied().collect();
+        let engines = [
+            StegoEngine::new(b"key"),
+            StegoEngine::new(b"key").encrypted(false).with_context(b"name.png"),
+            StegoEngine::new(b"key").redundant(true),
+            StegoEngine::new(b"key").with_depth([2, 0, 1]),
+            StegoEngine::new(b"key").lsb_matching(true).compressed(true).with_label("note").require_label("note"),
+        ];
+        for (i, engine) in engines.iter().enumerate() {
+            let mut image = noise(64, 64);
+            engine.embed(&mut image, &msg).unwrap();
+            assert_eq!(engine.extract(&image).unwrap(), msg, "engine {}", i);
+        }
+    }
+
+    #[test]
+    fn wrong_key_or_context_has_no_payload() {
+        let mut image = noise(64, 64);
+        StegoEngine::new(b"key").with_context(b"name.png").embed(&mut image, b"message").unwrap();
+        assert!(matches!(StegoEngine::new(b"other").with_context(b"name.png").extract(&image), Err(ExtractError::NoPayload(_))));
+        assert!(matches!(StegoEngine::new(b"key").extract(&image), Err(ExtractError::NoPayload(_))));
+    }
+
+    #[test]
+    fn size_limits_are_checked() {
+        let engine = StegoEngine::new(b"key");
+        let mut image = noise(32, 32);
+        let msg = vec![7u8; 200];
+        engine.embed(&mut image, &msg).unwrap();
+        assert_eq!(engine.extract_limited(&image, 200).unwrap(), msg);
+        assert!(matches!(engine.extract_limited(&image, 199), Err(ExtractError::Failed(_))));
+
+        let capacity = engine.capacity(&image);
+        let result = engine.embed(&mut image, &vec![0; capacity + 1]);
+        assert!(matches!(result, Err(EmbedError::TooLarge { len, capacity: c }) if len == capacity + 1 && c == capacity));
+        assert!(matches!(engine.embed(&mut noise(4, 4), b"x"), Err(EmbedError::TooSmall)));
+        assert!(matches!(engine.extract(&noise(4, 4)), Err(ExtractError::Decode(_))));
+        assert!(matches!(StegoEngine::new(b"key").require_label("note").extract(&image), Err(ExtractError::Failed(_))));
+    }
+}
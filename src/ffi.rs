@@ -0,0 +1,135 @@
+//! C ABI of the bytes in / bytes out API, declared in include/stegegg.h. Functions return STEGEGG_OK (0),
+//! STEGEGG_ERROR (1) or STEGEGG_NO_PAYLOAD (2), as the exit codes of the command line tool. Output buffers are
+//! allocated by the library and must be released with stegegg_free.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use crate::{buffer, ExtractError};
+
+/// Success
+pub const STEGEGG_OK: c_int = 0;
+/// Invalid input, decoding or other error (panics of the library included), stegegg_last_error has the message
+pub const STEGEGG_ERROR: c_int = 1;
+/// There is no message for the key (wrong key or clean image)
+pub const STEGEGG_NO_PAYLOAD: c_int = 2;
+
+thread_local! {
+    //message of the last error in this thread
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(s: String) {
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(s.replace('\0', " ")).unwrap_or_default());
+}
+
+//run the call with the panic turned into STEGEGG_ERROR, unwinding into the C caller is undefined behavior
+fn guarded(call: impl FnOnce() -> c_int) -> c_int {
+    catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|panic| {
+        let reason = panic.downcast_ref::<&str>().map(|s| s.to_string()).or_else(|| panic.downcast_ref::<String>().cloned());
+        set_error(format!("Internal error. {}", reason.unwrap_or_default()));
+        STEGEGG_ERROR
+    })
+}
+
+//slice from the C pointer and length. Null pointer is accepted for the empty buffer.
+unsafe fn input<'a>(data: *const u8, len: usize) -> Result<&'a [u8], String> {
+    if len == 0 {
+        Ok(&[])
+    } else if data.is_null() {
+        Err("Null buffer with non-zero length.".to_string())
+    } else {
+        Ok(std::slice::from_raw_parts(data, len))
+    }
+}
+
+//hand the buffer over to the caller
+unsafe fn output(data: Vec<u8>, out: *mut *mut u8, out_len: *mut usize) -> c_int {
+    if out.is_null() || out_len.is_null() {
+        set_error("Null output pointer.".to_string());
+        return STEGEGG_ERROR;
+    }
+    let data = data.into_boxed_slice();
+    *out_len = data.len();
+    *out = Box::into_raw(data) as *mut u8;
+    STEGEGG_OK
+}
+
+/// Hide the message into the encoded image. The stego image is returned as PNG in `out`.
+///
+/// # Safety
+/// Input pointers must be valid for their lengths, `out` and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn stegegg_hide(image: *const u8, image_len: usize, key: *const u8, key_len: usize,
+                                      message: *const u8, message_len: usize, out: *mut *mut u8, out_len: *mut usize) -> c_int {
+    guarded(|| {
+        let result = input(image, image_len).and_then(|image| {
+            buffer::embed_image(image, input(key, key_len)?, input(message, message_len)?)
+        });
+        match result {
+            Ok(png) => output(png, out, out_len),
+            Err(s) => { set_error(s); STEGEGG_ERROR },
+        }
+    })
+}
+
+/// Extract the message from the encoded image into `out`.
+///
+/// # Safety
+/// Input pointers must be valid for their lengths, `out` and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn stegegg_extract(image: *const u8, image_len: usize, key: *const u8, key_len: usize,
+                                         out: *mut *mut u8, out_len: *mut usize) -> c_int {
+    guarded(|| {
+        let result = input(image, image_len).and_then(|image| Ok((image, input(key, key_len)?)))
+            .map_err(ExtractError::Failed)
+            .and_then(|(image, key)| buffer::extract_image(image, key));
+        match result {
+            Ok(msg) => output(msg, out, out_len),
+            Err(ExtractError::NoPayload(s)) => { set_error(s); STEGEGG_NO_PAYLOAD },
+            Err(e) => { set_error(e.to_string()); STEGEGG_ERROR },
+        }
+    })
+}
+
+/// Release the buffer returned by stegegg_hide or stegegg_extract.
+///
+/// # Safety
+/// `data` and `len` must come from the library, each buffer can be released only once.
+#[no_mangle]
+pub unsafe extern "C" fn stegegg_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// Message of the last error in the calling thread. Valid until the next call from the same thread.
+#[no_mangle]
+pub extern "C" fn stegegg_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(stegegg_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn panic_is_error_code() {
+        assert_eq!(guarded(|| panic!("boom")), STEGEGG_ERROR);
+        assert_eq!(last_error(), "Internal error. boom");
+        assert_eq!(guarded(|| STEGEGG_NO_PAYLOAD), STEGEGG_NO_PAYLOAD);
+    }
+
+    #[test]
+    fn null_buffers_are_refused() {
+        let (mut out, mut out_len) = (std::ptr::null_mut(), 0);
+        assert_eq!(unsafe { stegegg_extract(std::ptr::null(), 10, std::ptr::null(), 0, &mut out, &mut out_len) }, STEGEGG_ERROR);
+        assert_eq!(last_error(), "Null buffer with non-zero length.");
+        assert!(out.is_null());
+    }
+}
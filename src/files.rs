@@ -0,0 +1,58 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use image::DynamicImage;
+
+/// Read the whole file into the vector
+pub fn read_file_to_vec<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, String> {
+    match File::open(path) {
+        Err(s) => Err(s.to_string()),
+        Ok(mut f) => {
+            let mut file_data = Vec::<u8>::new();
+            match f.read_to_end(&mut file_data) {
+                Err(s) => Err(s.to_string()),
+                Ok(_) => Ok(file_data),
+            }
+        }
+    }
+}
+
+/// Write the output into the temporary file in the destination directory and rename it over the target when
+/// it is complete, so an interrupted run never leaves a truncated output at the target path
+pub fn write_atomic<T, F: FnOnce(&Path) -> Result<T, String>>(path: &Path, write: F) -> Result<T, String> {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().ok_or(format!("'{}' is not a file name.", path.display()))?);
+    name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = path.with_file_name(name);
+
+    let result = write(&tmp).and_then(|v| {
+        File::open(&tmp).and_then(|f| f.sync_all()).and_then(|_| std::fs::rename(&tmp, path)).map_err(|s| s.to_string())?;
+        Ok(v)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Write the data to the file atomically, returns the number of bytes written
+pub fn write_vec_to_file<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<usize, String> {
+    write_atomic(path.as_ref(), |tmp| {
+        File::create(tmp).and_then(|mut f| f.write_all(data)).map_err(|s| s.to_string())?;
+        Ok(data.len())
+    })
+}
+
+/// Open the image and decode it with the guessed format
+pub fn open_image<P: AsRef<Path>>(path: P) -> Result<DynamicImage, String> {
+    match image::io::Reader::open(path) {
+        Err(s) => Err(s.to_string()),
+        Ok(r) => {
+            match r.with_guessed_format() {
+                Err(s) => Err(s.to_string()),
+                Ok(r) => r.decode().map_err(|s| s.to_string()),
+            }
+        },
+    }
+}
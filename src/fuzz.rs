@@ -0,0 +1,48 @@
+//! Entry points for fuzzers (cargo-fuzz, AFL, honggfuzz). They take arbitrary bytes, must not panic on any input
+//! and cap all allocations, so the fuzzer finds bugs in the parsing instead of running out of memory. The first
+//! byte of the input is the length of the key, the key follows and the rest is the carrier.
+
+use crate::{buffer, raw, tiled};
+
+//allocation caps of the fuzz targets
+const FUZZ_LIMITS: buffer::Limits = buffer::Limits { max_decode_alloc: 64 << 20, max_message: 1 << 20 };
+
+//largest raw carrier of the fuzz targets in samples
+const FUZZ_MAX_SAMPLES: usize = 1 << 22;
+
+//split the input into the key and the carrier
+fn split_key(data: &[u8]) -> (&[u8], &[u8]) {
+    match data.split_first() {
+        Some((&len, rest)) => rest.split_at((len as usize).min(rest.len())),
+        None => (&[], &[]),
+    }
+}
+
+/// Decode the encoded image (any supported format) and extract the message
+pub fn extract_image(data: &[u8]) {
+    let (key, image) = split_key(data);
+    let _ = buffer::extract_image_limited(image, key, FUZZ_LIMITS);
+}
+
+/// Extract the message from the raw RGB samples by the raw engine
+pub fn extract_raw(data: &[u8]) {
+    let (key, samples) = split_key(data);
+    let samples = &samples[..samples.len().min(FUZZ_MAX_SAMPLES)];
+    let mut scratch = vec![0; raw::scratch_len(samples.len())];
+    if let Ok(header) = raw::read_header_raw_with(&raw::XoshiroSelector, samples, key, &mut scratch) {
+        if header.len <= FUZZ_LIMITS.max_message {
+            let _ = raw::read_message_raw(samples, &header, &scratch, &mut vec![0; header.len]);
+        }
+    }
+}
+
+/// Extract the tiled message from the raw RGB samples of the 64 pixels wide image with 16 pixels tiles
+pub fn extract_tiled(data: &[u8]) {
+    const WIDTH: u32 = 64;
+    let (key, samples) = split_key(data);
+    let height = (samples.len().min(FUZZ_MAX_SAMPLES) / (WIDTH as usize * 3)) as u32;
+    if let Some(image) = image::RgbImage::from_raw(WIDTH, height, samples[..(WIDTH * height * 3) as usize].to_vec()) {
+        let mut prng_state = crate::init_prng(key, &[], &[]);
+        let _ = tiled::extract_tiled(&image, 16, &mut prng_state);
+    }
+}
@@ -0,0 +1,99 @@
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use zeroize::Zeroizing;
+
+//Passphrase protected keyfile. The key is encrypted by ChaCha20-Poly1305 with the key derived from the passphrase
+//by scrypt. Layout: magic, scrypt log2(N), salt, nonce, ciphertext with the tag. The header is authenticated too.
+const MAGIC: &[u8; 8] = b"stegkey1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+const TAG_LEN: usize = 16;
+
+//scrypt cost of the new keyfiles (N = 2^17, r = 8, p = 1 takes ~128MiB) and the maximum accepted when reading
+const LOG_N: u8 = 17;
+const MAX_LOG_N: u8 = 22;
+
+/// Is the keyfile protected by the passphrase
+pub fn is_protected(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN + TAG_LEN && data.starts_with(MAGIC)
+}
+
+//derive the encryption key from the passphrase
+fn derive_key(passphrase: &[u8], log_n: u8, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+    if log_n > MAX_LOG_N {
+        return Err(format!("Keyfile asks for scrypt cost 2^{}, above the maximum of 2^{}.", log_n, MAX_LOG_N));
+    }
+    let params = scrypt::Params::new(log_n, 8, 1, 32).map_err(|s| format!("Invalid scrypt parameters. {}", s))?;
+    let mut key = Zeroizing::new([0; 32]);
+    scrypt::scrypt(passphrase, salt, &params, key.as_mut()).map_err(|s| s.to_string())?;
+    Ok(key)
+}
+
+/// Encrypt the key with the passphrase into the keyfile format
+pub fn protect(key: &[u8], passphrase: &[u8]) -> Result<Vec<u8>, String> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(LOG_N);
+    header.resize(HEADER_LEN, 0);
+    getrandom::fill(&mut header[MAGIC.len() + 1..]).map_err(|s| format!("Can't generate random salt. {}", s))?;
+
+    let salt = &header[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &header[MAGIC.len() + 1 + SALT_LEN..];
+    let derived = derive_key(passphrase, LOG_N, salt)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(derived.as_ref()));
+    let ciphertext = cipher.encrypt(Nonce::from_slice(nonce), Payload { msg: key, aad: &header }).map_err(|_| "Can't encrypt the key.".to_string())?;
+
+    header.extend_from_slice(&ciphertext);
+    Ok(header)
+}
+
+/// Decrypt the key from the protected keyfile
+pub fn unprotect(data: &[u8], passphrase: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_protected(data) {
+        return Err("Not a passphrase protected keyfile.".to_string());
+    }
+    let (header, ciphertext) = data.split_at(HEADER_LEN);
+    let salt = &header[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &header[MAGIC.len() + 1 + SALT_LEN..];
+    let derived = derive_key(passphrase, header[MAGIC.len()], salt)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(derived.as_ref()));
+    cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header }).map_err(|_| "Wrong passphrase or damaged keyfile.".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyfile_round_trip() {
+        let keyfile = protect(b"the key", b"passphrase").unwrap();
+        assert!(is_protected(&keyfile));
+        assert_eq!(keyfile.len(), HEADER_LEN + b"the key".len() + TAG_LEN);
+        assert_eq!(keyfile[MAGIC.len()], LOG_N);
+        assert_eq!(unprotect(&keyfile, b"passphrase").unwrap(), b"the key");
+        assert!(unprotect(&keyfile, b"wrong passphrase").is_err());
+    }
+
+    #[test]
+    fn plain_keys_are_not_protected() {
+        assert!(!is_protected(b"stegkey1 is a plain key file"));
+        assert!(!is_protected(&[b"stegkey0".as_slice(), &[0; HEADER_LEN + TAG_LEN]].concat()));
+        assert!(unprotect(b"plain key", b"passphrase").is_err());
+    }
+
+    #[test]
+    fn modified_header_is_refused() {
+        let keyfile = protect(b"the key", b"passphrase").unwrap();
+        let mut modified = keyfile.clone();
+        modified[HEADER_LEN - 1] ^= 1;
+        assert!(unprotect(&modified, b"passphrase").is_err());
+    }
+
+    #[test]
+    fn excessive_cost_is_refused() {
+        //cost is checked before any memory is allocated by scrypt
+        let keyfile = [MAGIC.as_slice(), &[MAX_LOG_N + 1], &[0; SALT_LEN + NONCE_LEN + TAG_LEN]].concat();
+        assert!(unprotect(&keyfile, b"passphrase").unwrap_err().contains("maximum"));
+    }
+}
@@ -0,0 +1,79 @@
+#![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
+//! Library of stegegg, hiding messages in the LSBs of the random pixels and colors of images (and of WAV
+//! samples). StegoEngine is the entry point for Rust programs, buffer is the bytes in / bytes out API for the
+//! servers and bindings, raw is the allocation free core. The functions of the crate root work with the
+//! hidding spots of the command line pixel mode.
+//! Without the default `std` feature the crate is `no_std` and has only the raw core.
+
+/// Authenticated encryption of the message
+#[cfg(feature = "std")]
+pub mod aead;
+/// Tokio variants of the bytes in / bytes out API
+#[cfg(feature = "async")]
+pub mod async_api;
+/// WAV audio carrier
+#[cfg(feature = "std")]
+pub mod audio;
+/// Banded processing of PNG images row by row, without loading them whole
+#[cfg(feature = "std")]
+pub mod banded;
+/// Bytes in / bytes out API for the servers and bindings
+#[cfg(feature = "std")]
+pub mod buffer;
+/// Carriers of the hidden bits and the message format on them
+#[cfg(feature = "std")]
+pub mod carrier;
+/// Message in the ancillary PNG chunks instead of the pixels
+#[cfg(feature = "std")]
+pub mod chunk;
+/// Compression of the message
+#[cfg(feature = "std")]
+pub mod compress;
+/// StegoEngine, the salted LSB engine for Rust programs
+#[cfg(feature = "std")]
+pub mod engine;
+/// C ABI of the bytes in / bytes out API
+#[cfg(feature = "std")]
+pub mod ffi;
+/// Reading and atomic writing of the carrier and message files
+#[cfg(feature = "std")]
+pub mod files;
+/// Entry points for fuzzers
+#[cfg(feature = "std")]
+pub mod fuzz;
+/// Passphrase protected keyfiles
+#[cfg(feature = "std")]
+pub mod keyfile;
+//the napi registration is left out of the test builds, which would see the exported functions unused
+#[cfg(all(feature = "node", not(test)))]
+mod node;
+#[cfg(feature = "python")]
+mod python;
+/// WebAssembly API
+#[cfg(all(feature = "std", target_arch = "wasm32"))]
+pub mod wasm;
+/// Parallel PNG encoder
+#[cfg(feature = "std")]
+pub mod pngenc;
+/// Allocation free no_std core of the embedding
+pub mod raw;
+#[cfg(feature = "std")]
+mod spots;
+/// Splitting of the message over several carriers with the parity carrier
+#[cfg(feature = "std")]
+pub mod split;
+/// Steganalysis detectors of LSB replacement
+#[cfg(feature = "std")]
+pub mod steganalysis;
+/// Crop-resilient tiled embedding
+#[cfg(feature = "std")]
+pub mod tiled;
+
+#[cfg(feature = "std")]
+pub use carrier::{Carrier, CarrierMut, ImageCarrier};
+#[cfg(feature = "std")]
+pub use engine::{EmbedError, StegoEngine};
+pub use raw::{get_bit, init_prng, seeded_prng, shuffle, xoshiro256pp, PrngState, FORMAT_VERSION, MAX_MSG_LEN, NO_PAYLOAD, RESERVED_SPOTS, SALT_LEN, TRAILER_LEN, TRAILER_MAGIC};
+#[cfg(feature = "std")]
+pub use spots::*;
@@ -1,282 +1,212 @@
-use std::{env, io::Read, io::Write};
-use std::fs::File;
-use getopts::Options;
-use sha2::{Sha256, Digest};
-use image::{ImageFormat, RgbImage};
-
-//code for PRNG adapted from https://prng.di.unimi.it/xoshiro256plusplus.c
-struct PrngState(u64, u64, u64, u64);
-
-fn rotl(x: u64, k: u64) -> u64 {
-    (x << k) | (x >> (64 - k))
-}
-
-fn xoshiro256pp(s: &mut PrngState) -> u64 {
-    let result = rotl(s.0 + s.3, 23) + s.0;
-    let t = s.1 << 17;
-    s.2 ^= s.0;
-    s.3 ^= s.1;
-    s.1 ^= s.2;
-    s.0 ^= s.3;
-    s.2 ^= t;
-    s.3 = rotl(s.3, 45);
-    result
-}
-
-//shuffle vector
-fn shuffle(v: &mut Vec<u32>, prng_state: &mut PrngState) {
-    for i in 0..v.len() {
-        let j = (xoshiro256pp(prng_state) as usize) % v.len();
-        v.swap(i, j);
+use std::path::Path;
+use getopts::Matches;
+use stegegg::ExtractError;
+use zeroize::Zeroizing;
+use cli::*;
+use cli::i18n::tr;
+use cli::manifest::PayloadDigest;
+use cli::options::threads;
+use cli::pixel::{extract_file, hide_file, process_tiled};
+
+mod cli;
+
+//every command which printed an error exits with code 1, the failed extraction exits in report_result
+fn main() {
+    run();
+    if diag::failed() {
+        std::process::exit(1);
     }
 }
 
-//returns nth bit from the byte
-fn get_bit(b: u8, n: u8) -> u8 {
-    (b >> n) & 1
-}
-
-//extract data from image
-fn extract_data(image: &RgbImage, prng_state: &mut PrngState) -> Result<Vec<u8>, String> {
-
-    let channels = 3;
-    let width = image.width();
-    let hidding_spots = width * image.height() * channels ;
-    let mut iidx = 0;
-
-    //suffle vector of indices to get correct random sequence
-    let mut indices: Vec<u32> = (0..hidding_spots).collect();
-    shuffle(&mut indices, prng_state);
+fn run() {
 
-    //recover header
-    let mut header: [u8; 3] = [0, 0, 0];
-    for b in &mut header {
-        for n in 0..8 {
-            let spot_idx = indices[iidx];
-            let color_offset = (spot_idx % channels) as usize;
-            let pixel_idx = spot_idx / channels;
-            let x = pixel_idx % width;
-            let y = pixel_idx / width;
+    //parse command line
+    let opts = options::command_line();
 
-            *b |= (image.get_pixel(x, y)[color_offset] & 1) << n;
-            iidx += 1;
+    let matches = match opts.parse(&utf8_args()[1..]) {
+        Ok(m) => m,
+        Err(s) => { diag::error(s); return; },
+    };
+    diag::init(matches.opt_present("no-color"));
+    if let Some(path) = matches.opt_str("log-file") {
+        if let Err(s) = diag::open_log(&os_arg(&path)) {
+            diag::error(s);
+            return;
         }
+        diag::log(format!("Arguments: {}", logged_args()));
     }
 
-    //try to recover message length and check if is possible to fit it into the image. (may not, when wrong password is used)
-    let msg_len = header[0] as usize | (header[1] as usize) << 8 | (header[2] as usize) << 16;
-    if msg_len * 8 + 3 > hidding_spots as usize {
-        return Err("Message length from extracted header is to large to fit into this image!\nDid you use the correct key?!".to_string());
-    }
-
-    //extract message
-    let mut msg = vec![0; msg_len];
-    for b in &mut msg {
-        for n in 0..8 {
-            let spot_idx = indices[iidx];
-            let color_offset = (spot_idx % channels) as usize;
-            let pixel_idx = spot_idx / channels;
-            let x = pixel_idx % width;
-            let y = pixel_idx / width;
-
-            *b |= (image.get_pixel(x, y)[color_offset] & 1) << n;
-            iidx += 1;
-        }
-    }
-    Ok(msg)
-}
-
-//hide data into the random pixels and random colors
-fn hide_data(data: &Vec<u8>, image: &mut RgbImage, prng_state: &mut PrngState) -> Result<(), String> {
-
-    let channels = 3;
-    let width = image.width();
-    let hidding_spots = width * image.height() * channels;
-
-    if data.len() * 8 > hidding_spots as usize {
-        return Err(format!("Input message is too large.\nCan't hide {} bits into {} hidding spots!", data.len() * 8, hidding_spots));
+    //print help end exit 
+    if matches.opt_present("h") {
+        println!("stegegg v{}\n\nUsage: {}", env!("CARGO_PKG_VERSION"), opts.usage("stegegg [Options] <input> <output>\n       stegegg --in-place [--backup] [Options] <image>\n       stegegg rank --payload-size SIZE <images...>\n       stegegg capacity [-k <key>] [layout options] <image>\n       stegegg capacity-report [--report-format csv|json] <directory>\n       stegegg estimate [--max-fill RATE] [Options] -m|-M <payload>\n       stegegg keygen [--protect] -o <key file> [--size 32]\n       stegegg features <output.csv|output.npy> <images...>\n       stegegg dataset [--rates R1,R2..] [--algorithms A1,A2..] <covers> <output>\n       stegegg attack -k <key> [--ops OP:PARAM,..] <image>\n       stegegg migrate -k <key> <old image> <new image>\n       stegegg inspect-header -k <key> <image>\n       stegegg list -k <key> [extraction options] <carrier>\n       stegegg nest -k <outer key> --inner-key <key> -m|-M <message> <inner cover> <outer cover> <output>\n       stegegg split -k <key> -m|-M <message> [--parity] <covers...> <output directory>\n       stegegg join -k <key> [extraction options] <carriers...> <output>\n       stegegg verify-set -k <key> [extraction options] <carriers...>\n       stegegg export-lsb [-k <key>] <image> <output prefix>\n       stegegg analyze [--lsb-plane <output.png>] [--cost-map <output.png>] <image>\n       stegegg spectrogram <cover.wav> <image> <output.wav>\n       stegegg spectrogram -x <audio.wav> <output.png>\n       stegegg serve [--listen ADDR]\n       stegegg grpc [--listen ADDR]"));
+        return;
     }
 
-    //suffle vector of indices to get random hiding spots
-    let mut indices: Vec<u32> = (0..hidding_spots).collect();
-    shuffle(&mut indices, prng_state);
-
-    //hide message
-    let mut iidx: usize = 0;
-    for b in data {
-
-        //hide each bit starting with LSB bit
-        for n in 0..8 {
-            let spot_idx = indices[iidx];
-            let color_offset = (spot_idx % channels) as usize;
-            let pixel_idx = spot_idx / channels;
-            let x = pixel_idx % width;
-            let y = pixel_idx / width;
-
-            let pixel = image.get_pixel_mut(x, y);
-            pixel[color_offset] = pixel[color_offset] & 0xfe | get_bit(*b, n);
-            iidx += 1;
-        }
-    }
-    Ok(())
-}
+    let threads = match threads(&matches) {
+        Ok(t) => t,
+        Err(s) => { diag::error(s); return; },
+    };
 
-//read file and returns content as vector
-fn read_file_to_vec(path: &String) -> Result<Vec<u8>, String> {
-    match File::open(path) {
-        Err(s) => Err(s.to_string()),
-        Ok(mut f) => {
-            let mut file_data = Vec::<u8>::new();
-            match f.read_to_end(&mut file_data) {
-                Err(s) => Err(s.to_string()),
-                Ok(_) => Ok(file_data),
-            }
+    //run the command if the first free argument is one
+    if let Some(cmd) = matches.free.first() {
+        match cmd.as_str() {
+            "rank" => { rank::rank_covers(&matches.free[1..], matches.opt_str("payload-size")); return; },
+            "dataset" => { dataset::make_dataset(matches.free.get(1), matches.free.get(2), matches.opt_str("rates"), matches.opt_str("algorithms"), threads); return; },
+            "spectrogram" => { spectrogram::spectrogram(&matches.free[1..], matches.opt_present("x")); return; },
+            "keygen" => { keygen::keygen(&matches); return; },
+            "estimate" => { estimate::estimate(&matches); return; },
+            "features" => { features::export_features(&matches.free[1..]); return; },
+            "analyze" => { analyze::analyze(&matches, matches.free.get(1)); return; },
+            "capacity-report" => { report::capacity_report(matches.free.get(1), matches.opt_str("report-format")); return; },
+            #[cfg(feature = "serve")]
+            "serve" => { serve::serve(matches.opt_str("listen"), threads); return; },
+            #[cfg(not(feature = "serve"))]
+            "serve" => { diag::error("stegegg was built without the 'serve' feature."); return; },
+            #[cfg(feature = "grpc")]
+            "grpc" => { grpc::grpc(matches.opt_str("listen"), threads); return; },
+            #[cfg(not(feature = "grpc"))]
+            "grpc" => { diag::error("stegegg was built without the 'grpc' feature."); return; },
+            _ => {},
         }
     }
-}
 
-//write vec to the file
-fn write_vec_to_file(path: &String, data: &[u8]) -> Result<usize, String> {
-    match File::create(path) {
-        Err(s) => Err(s.to_string()),
-        Ok(mut f) => {
-            match f.write(data) {
-                Err(s) => Err(s.to_string()),
-                Ok(n) => Ok(n),
-            }
+    //get key from the user. Key is wiped from the memory when dropped.
+    let mut user_key = match user_key(&matches) {
+        Ok(k) => Zeroizing::new(k),
+        Err(s) => { diag::error(s); return; },
+    };
+    let mut _key_lock = if matches.opt_present("lock-memory") { lock_secret(&user_key) } else { None };
+
+    //run the command which needs the key
+    if let Some(cmd) = matches.free.first() {
+        let has_key = |options: &[&str]| options.iter().any(|o| matches.opt_present(o));
+        match cmd.as_str() {
+            //dump LSB planes, keyed plane only when the key is given
+            "export-lsb" => {
+                let key = if has_key(&["k", "K", "ask-key"]) { Some(user_key.as_slice()) } else { None };
+                export::export_lsb(&matches, matches.free.get(1), matches.free.get(2), key);
+                return;
+            },
+            //print the capacity of the carrier, header presence only when the key is given
+            "capacity" => {
+                let key = if has_key(&["k", "K", "ask-key", "seed"]) { Some(user_key.as_slice()) } else { None };
+                info::capacity_info(&matches, matches.free.get(1), key);
+                return;
+            },
+            "attack" => { attack::attack(&opts, &matches, matches.free.get(1), &user_key, matches.opt_str("ops")); return; },
+            "inspect-header" => { report_result(inspect::inspect_header(&matches, matches.free.get(1), &user_key), true); return; },
+            "list" => {
+                let in_filename = matches.free.get(1).cloned();
+                report_result(extract_any_kdf(&opts, &matches, &user_key, |m, key| archive::list(m, in_filename.as_ref(), key)), true);
+                return;
+            },
+            "migrate" => { migrate::migrate(&matches, matches.free.get(1), matches.free.get(2), &user_key); return; },
+            "nest" => { nest::nest(&matches, &matches.free[1..], &user_key); return; },
+            "split" => { split::split(&matches, &matches.free[1..], &user_key); return; },
+            "join" => { report_result(split::join(&opts, &matches, &matches.free[1..], &user_key), true); return; },
+            "verify-set" => { report_result(split::verify_set(&opts, &matches, &matches.free[1..], &user_key), true); return; },
+            _ => {},
         }
     }
-}
-
-fn main() {
 
-    //parse command line
-    let mut opts = Options::new();
-    opts.optflag("b","bmp", "Output image in BMP format instead of default PNG.");
-    opts.optflag("x","extract", "Extract message from the image. Requires correct key.");
-    opts.optopt("k", "key", "Key for embedding or extracting data.", "");
-    opts.optopt("K", "key-file", "Key file for embedding or extracting data.", "");
-    opts.optopt("m", "message", "Data / message to hide into the image.", "");
-    opts.optopt("M", "message-file", "File with data / message to hide into the image.", "");
-    opts.optflag("h","help", "Print this help and exit.");
-
-    let matches = match opts.parse(&env::args().collect::<Vec<String>>()[1..]) {
-        Ok(m) => m,
-        Err(s) => { println!("{}", s); return; },
+    //get input file name
+    let in_filename = &match matches.free.first() {
+        Some(f) => os_arg(f),
+        None => { diag::error(tr!("input-not-specified")); return; },
     };
 
-    //print help end exit 
-    if matches.opt_present("h") {
-        println!("stegegg v{}\n\nUsage: {}", env!("CARGO_PKG_VERSION"), opts.usage("stegegg [Options] <input> <output>"));
+    //get output file name and refuse the options which can't be used together
+    let out_filename = &match output_file(&matches, in_filename) {
+        Ok(f) => f,
+        Err(s) => { diag::error(s); return; },
+    };
+    if let Err(s) = options::check_combinations(&matches, out_filename) {
+        diag::error(s);
         return;
     }
 
-    //get key from the user or use empty one if not specified
-    let user_key = if let Some(k) = matches.opt_str("k") {
-        Vec::from(k.as_bytes())
+    //hide into the sample LSBs of the WAV audio
+    if wav::is_wav(in_filename) {
+        report_result(wav::process_wav(&opts, &matches, in_filename, out_filename, &user_key), matches.opt_present("x"));
+        return;
+    }
 
-    } else if let Some(file_path) = matches.opt_str("K") {
-        match read_file_to_vec(&file_path){
-            Ok(v) => v,
-            Err(s) => { println!("{}", s); return; },
+    //find the layout options the message was hidden with
+    let matches = if matches.opt_present("auto") {
+        match probe::probe_options(&opts, &matches, in_filename, &user_key, out_filename == Path::new("-")) {
+            Ok(m) => m,
+            Err(e) => { report_result(Err(e), true); return; },
         }
     } else {
-        Vec::new()
+        matches
     };
 
-    //get input file name
-    let in_filename = match matches.free.first() {
-        Some(f) => f,
-        None => { println!("Input file not specified."); return; },
-    };
+    let (format, algorithm, ecc) = manifest::parameters(&matches);
+    diag::log(format!("Input '{}', output '{}', format {}, algorithm {}, ecc {}.", in_filename.display(), out_filename.display(), format, algorithm, ecc));
 
-    //get output file name
-    let out_filename = match matches.free.get(1) {
-        Some(f) => f,
-        None => { println!("Output file not specified."); return; },
-    };
+    //process the image in row bands without loading it whole
+    if matches.opt_present("banded") || matches.opt_present("low-memory") {
+        report_result(banded::process_banded(&matches, in_filename, out_filename, &user_key), matches.opt_present("x"));
+        return;
+    }
 
-    //open image and get the format
-    let mut img = match image::io::Reader::open(in_filename) {
-        Err(s) => { println!("{}", s); return; },
-        Ok(r) => {
-            match r.with_guessed_format() {
-                Err(s) => { println!("{}", s); return; },
-                Ok(r) => {
-                    match r.decode() {
-                        Err(s) => { println!("{}", s); return; },
-                        Ok(r) => r,
-                    }
-                },
-            }
+    //hide into the ancillary PNG chunks instead of the pixels
+    match matches.opt_str("mode").as_deref() {
+        None | Some("pixel") => {},
+        Some("chunk") => {
+            report_result(chunk::process_chunk(&matches, in_filename, out_filename, &user_key), matches.opt_present("x"));
+            return;
         },
-    };
-
-    //convert it into rgb8 image
-    let rgb_img = match img.as_mut_rgb8() {
-        Some(r) => r,
-        None => { println!("Can't convert image to rgb8!"); return; },
-    };
-    
-    //init random generator with SHA256 frim the user key
-    let mut hasher = Sha256::new();
-    hasher.update(&user_key);
-    let key_hash = hasher.finalize();
-
-    //convert 32 SHA256 bytes into 4 u64.
-    let mut prng_state = PrngState(
-        u64::from_be_bytes(key_hash[0..8].try_into().unwrap()),
-        u64::from_be_bytes(key_hash[8..16].try_into().unwrap()),
-        u64::from_be_bytes(key_hash[16..24].try_into().unwrap()),
-        u64::from_be_bytes(key_hash[24..32].try_into().unwrap())
-    );
-
-    //extract data from the image
-    if matches.opt_present("x") {
-        match extract_data(rgb_img, &mut prng_state) {
-            Err(s) => println!("{}", s),
-            Ok(v) => {
-                match write_vec_to_file(out_filename, &v) {
-                    Ok(n) => println!("{} bytes written to '{}'", n, out_filename),
-                    Err(s) => println!("Error accessing the file '{}'. {}", out_filename, s),
-                }
-            }
-        }
+        Some(m) => { diag::error(format!("Unknown mode '{}'. Use 'pixel' or 'chunk'.", m)); return; },
+    }
 
-    //hide data into the image
-    } else {
+    //crop-resilient tiled embedding
+    if matches.opt_present("tiled") {
+        report_result(process_tiled(&matches, in_filename, out_filename, &user_key), matches.opt_present("x"));
+        return;
+    }
 
-        //get message / data from the user
-        let mut msg = if let Some(m) = matches.opt_str("m") {
-            Vec::from(m.as_bytes())
+    //hide the same message into all images in the input directory
+    if !matches.opt_present("x") && in_filename.is_dir() {
+        batch::hide_batch(&matches, in_filename, out_filename, &user_key);
+        return;
+    }
 
-        } else if let Some(file_path) = matches.opt_str("M") {
-            match read_file_to_vec(&file_path){
-                Ok(v) => v,
-                Err(s) => { println!("{}", s); return; },
-            }
+    let result = if matches.opt_present("x") {
+        let extract = |m: &Matches, key: &[u8]| if m.opt_present("archive") {
+            archive::extract(m, in_filename, out_filename, key)
         } else {
-            println!("Input data / message not specified!\nPlease specify it with -m or -M parameter.");
-            return;
+            extract_file(m, in_filename, out_filename, key)
         };
-
-        //create 3 byte for message length in little endian format. This limit max message length to 16Mbytes.
-        //append message to this "header"
-        let mut data: Vec<u8> = vec![0, 0, 0];
-        data[0] = (msg.len() & 0xff) as u8;
-        data[1] = ((msg.len() >> 8) & 0xff) as u8;
-        data[2] = ((msg.len() >> 16) & 0xff) as u8;
-        data.append(&mut msg);
-
-        if let Err(s) = hide_data(&data, rgb_img, &mut prng_state) {
-            println!("{}", s);
-            return;
+        let mut result = extract_any_kdf(&opts, &matches, &user_key, extract);
+
+        //typos are the most common failure, so the prompted key can be entered again
+        let mut attempt = 1;
+        while matches.opt_present("ask-key") && attempt < KEY_ATTEMPTS {
+            match result {
+                //the key can be entered again, so the failed attempt doesn't fail the run
+                Err(ExtractError::NoPayload(s)) => diag::warning(tr!("no-payload", detail = s)),
+                _ => break,
+            }
+            _key_lock = None;
+            user_key = match ask_key() {
+                Ok(k) => Zeroizing::new(k),
+                Err(s) => { diag::error(s); return; },
+            };
+            if matches.opt_present("lock-memory") {
+                _key_lock = lock_secret(&user_key);
+            }
+            result = extract_any_kdf(&opts, &matches, &user_key, extract);
+            attempt += 1;
         }
+        result
+    } else {
+        let digest = PayloadDigest::default();
+        message_source(&matches, &digest)
+            .and_then(|(mut reader, len)| hide_file(&matches, in_filename, out_filename, &user_key, &mut reader, len))
+            .map(|_| manifest::write_manifest(&matches, in_filename, out_filename, &digest))
+            .map_err(ExtractError::from)
+    };
 
-        //save output image
-        match img.save_with_format(out_filename, if matches.opt_present("b"){ ImageFormat::Bmp } else { ImageFormat::Png }) {
-            Ok(_) => println!("Message hidden in the '{}'.", out_filename),
-            Err(s) => println!("Error accessing the file '{}'. {}", out_filename, s),
-        }
-    }
+    report_result(result, matches.opt_present("x"));
 }
@@ -0,0 +1,35 @@
+use napi::bindgen_prelude::Buffer;
+use napi_derive::napi;
+use crate::{buffer, ExtractError};
+
+//Node.js native module for Electron and server-side tools. Images are encoded files (PNG, BMP, ...) in Buffers
+//and embed returns the stego image as PNG. Errors have the code "NoPayload" when there is no message for the key
+//and "InvalidArg" otherwise.
+
+fn error(code: &str, reason: String) -> napi::Error<String> {
+    napi::Error::new(code.to_string(), reason)
+}
+
+//hide the message into the image
+#[napi]
+pub fn embed(image: Buffer, key: Buffer, message: Buffer) -> napi::Result<Buffer, String> {
+    let png = buffer::embed_image(&image, &key, &message).map_err(|s| error("InvalidArg", s))?;
+    Ok(png.into())
+}
+
+//extract the message from the image
+#[napi]
+pub fn extract(image: Buffer, key: Buffer) -> napi::Result<Buffer, String> {
+    let msg = buffer::extract_image(&image, &key).map_err(|e| match e {
+        ExtractError::NoPayload(s) => error("NoPayload", s),
+        e => error("InvalidArg", e.to_string()),
+    })?;
+    Ok(msg.into())
+}
+
+//capacity of the image in bytes
+#[napi]
+pub fn capacity(image: Buffer) -> napi::Result<u32, String> {
+    let (_, _, capacity) = buffer::image_capacity(&image).map_err(|s| error("InvalidArg", s))?;
+    u32::try_from(capacity).map_err(|_| error("InvalidArg", format!("Capacity of {} bytes doesn't fit into u32.", capacity)))
+}
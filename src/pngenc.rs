@@ -0,0 +1,227 @@
+//! PNG encoder compressing bands of rows in parallel (same idea as mtpng). Each band is filtered and deflated
+//! independently and ends with a sync flush, so the compressed bands can be simply concatenated into one zlib stream.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use flate2::{Compress, Compression, FlushCompress, Status};
+use image::{RgbImage, RgbaImage};
+
+//paeth predictor from the PNG specification
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+//filter one row with all five filters and append the one with the smallest sum of absolute values
+fn filter_row(row: &[u8], prev: Option<&[u8]>, bpp: usize, out: &mut Vec<u8>) {
+    let mut best: Option<(u64, u8, Vec<u8>)> = None;
+
+    for filter in 0..5u8 {
+        let mut f = Vec::with_capacity(row.len());
+        for i in 0..row.len() {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prev.map_or(0, |p| p[i]);
+            let c = if i >= bpp { prev.map_or(0, |p| p[i - bpp]) } else { 0 };
+            f.push(match filter {
+                0 => row[i],
+                1 => row[i].wrapping_sub(a),
+                2 => row[i].wrapping_sub(b),
+                3 => row[i].wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+                _ => row[i].wrapping_sub(paeth(a, b, c)),
+            });
+        }
+
+        let sum = f.iter().map(|&v| (v as i8).unsigned_abs() as u64).sum::<u64>();
+        if best.as_ref().is_none_or(|b| sum < b.0) {
+            best = Some((sum, filter, f));
+        }
+    }
+
+    let (_, filter, f) = best.unwrap();
+    out.push(filter);
+    out.extend_from_slice(&f);
+}
+
+//raw deflate of one band. Last band finishes the stream, others end with a sync flush on the byte boundary.
+fn deflate_band(data: &[u8], last: bool) -> Result<Vec<u8>, String> {
+    let mut c = Compress::new(Compression::default(), false);
+    let mut out = Vec::with_capacity(data.len() / 2 + 1024);
+    let flush = if last { FlushCompress::Finish } else { FlushCompress::Sync };
+
+    loop {
+        if out.capacity() - out.len() < 1024 {
+            out.reserve(out.capacity());
+        }
+        let consumed = c.total_in() as usize;
+        let before = out.len();
+        let status = c.compress_vec(&data[consumed..], &mut out, flush).map_err(|s| format!("Can't compress the image. {}", s))?;
+
+        let done = if last {
+            status == Status::StreamEnd
+        } else {
+            c.total_in() as usize == data.len() && out.len() - before < out.capacity() - before
+        };
+        if done {
+            return Ok(out);
+        }
+    }
+}
+
+//adler32 checksum of the uncompressed data for the zlib trailer
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &v in chunk {
+            a += v as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+//write PNG chunk with its length and CRC
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    w.write_all(&crc.finalize().to_be_bytes())
+}
+
+/// Save RGB8 image as PNG compressed by the given number of threads
+pub fn save_png<P: AsRef<Path>>(image: &RgbImage, path: P, threads: usize) -> Result<(), String> {
+    save_raw(image.as_raw(), image.dimensions(), 3, path.as_ref(), threads)
+}
+
+/// Save RGBA8 image as PNG compressed by the given number of threads
+pub fn save_png_rgba(image: &RgbaImage, path: &Path, threads: usize) -> Result<(), String> {
+    save_raw(image.as_raw(), image.dimensions(), 4, path, threads)
+}
+
+//save 8-bit RGB or RGBA samples as PNG
+fn save_raw(raw: &[u8], (width, height): (u32, u32), channels: usize, path: &Path, threads: usize) -> Result<(), String> {
+
+    let stride = width as usize * channels;
+
+    //split rows into one band per thread
+    let band_rows = (height as usize).div_ceil(threads.max(1)).max(1);
+    let bands: Vec<(usize, usize)> = (0..height as usize).step_by(band_rows).map(|s| (s, (s + band_rows).min(height as usize))).collect();
+
+    //filter and compress bands in parallel
+    let results: Vec<(Vec<u8>, u32, usize)> = thread::scope(|scope| -> Result<_, String> {
+        let handles: Vec<_> = bands.iter().enumerate().map(|(i, &(start, end))| {
+            let last = i + 1 == bands.len();
+            scope.spawn(move || {
+                let mut filtered = Vec::with_capacity((end - start) * (stride + 1));
+                for y in start..end {
+                    let prev = if y > 0 { Some(&raw[(y - 1) * stride..y * stride]) } else { None };
+                    filter_row(&raw[y * stride..(y + 1) * stride], prev, channels, &mut filtered);
+                }
+                Ok((deflate_band(&filtered, last)?, adler32(&filtered), filtered.len()))
+            })
+        }).collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    })?;
+
+    //combine band checksums into the checksum of the whole stream
+    let adler = results.iter().fold(1u32, |adler, (_, a, len)| adler32_combine(adler, *a, *len));
+
+    let mut idat = vec![0x78, 0x9c];
+    for (data, _, _) in &results {
+        idat.extend_from_slice(data);
+    }
+    if results.is_empty() {
+        idat.extend_from_slice(&deflate_band(&[], true)?);
+    }
+    idat.extend_from_slice(&adler.to_be_bytes());
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, if channels == 4 { 6 } else { 2 }, 0, 0, 0]);
+
+    let f = File::create(path).map_err(|s| s.to_string())?;
+    let mut w = BufWriter::new(f);
+    let result = w.write_all(b"\x89PNG\r\n\x1a\n")
+        .and_then(|_| write_chunk(&mut w, b"IHDR", &ihdr))
+        .and_then(|_| idat.chunks(1 << 20).try_for_each(|c| write_chunk(&mut w, b"IDAT", c)))
+        .and_then(|_| write_chunk(&mut w, b"IEND", &[]))
+        .and_then(|_| w.flush());
+    result.map_err(|s| s.to_string())
+}
+
+//adler32 of the concatenated data from the adler32 of both parts (as zlib's adler32_combine)
+fn adler32_combine(adler1: u32, adler2: u32, len2: usize) -> u32 {
+    const BASE: u64 = 65521;
+    let rem = len2 as u64 % BASE;
+    let a1 = adler1 as u64 & 0xffff;
+    let b1 = (adler1 as u64 >> 16) & 0xffff;
+    let a2 = adler2 as u64 & 0xffff;
+    let b2 = (adler2 as u64 >> 16) & 0xffff;
+
+    let a = (a1 + a2 + BASE - 1) % BASE;
+    let b = (rem * a1 % BASE + b1 + b2 + BASE - rem) % BASE;
+    ((b << 16) | a) as u32
+}
+
+/// Losslessly optimize the saved PNG file with oxipng. Returns the original and the optimized size.
+#[cfg(feature = "optimize")]
+pub fn optimize_png(path: &Path) -> Result<(usize, usize), String> {
+    let data = std::fs::read(path).map_err(|s| s.to_string())?;
+    let optimized = oxipng::optimize_from_memory(&data, &oxipng::Options::from_preset(2)).map_err(|s| s.to_string())?;
+    if optimized.len() < data.len() {
+        std::fs::write(path, &optimized).map_err(|s| s.to_string())?;
+        return Ok((data.len(), optimized.len()));
+    }
+    Ok((data.len(), data.len()))
+}
+
+/// Losslessly optimize the saved PNG file, fails as stegegg was built without oxipng
+#[cfg(not(feature = "optimize"))]
+pub fn optimize_png(_path: &Path) -> Result<(usize, usize), String> {
+    Err("stegegg was built without the 'optimize' feature.".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("stegegg-pngenc-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn parallel_png_decodes_to_the_same_pixels() {
+        let image = RgbImage::from_fn(57, 43, |x, y| image::Rgb([(x * 5 + y) as u8, (x * y) as u8, (x ^ (y * 3)) as u8]));
+        //one band, bands of several rows and more threads than rows
+        for threads in [1, 4, 7, 64] {
+            let path = temp_path(&format!("rgb-{}.png", threads));
+            save_png(&image, &path, threads).unwrap();
+            assert_eq!(image::open(&path).unwrap().into_rgb8(), image, "{} threads", threads);
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let image = RgbaImage::from_fn(31, 20, |x, y| image::Rgba([x as u8, y as u8, (x + y) as u8, (x * 8) as u8]));
+        let path = temp_path("rgba.png");
+        save_png_rgba(&image, &path, 3).unwrap();
+        assert_eq!(image::open(&path).unwrap().into_rgba8(), image);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn adler32_of_the_parts_combines_to_the_whole() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 + i / 13) as u8).collect();
+        for split in [0, 1, 5552, 65521, 100_000, data.len()] {
+            let (a, b) = data.split_at(split);
+            assert_eq!(adler32_combine(adler32(a), adler32(b), b.len()), adler32(&data), "split at {}", split);
+        }
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+}
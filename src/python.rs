@@ -0,0 +1,80 @@
+use image::RgbImage;
+use pyo3::buffer::PyBuffer;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use crate::{buffer, ExtractError};
+
+//Python module for the steganalysis pipelines. Encoded images are passed as bytes. Pixel functions take any
+//writable buffer of HxWx3 uint8 RGB samples (e.g. numpy array), so the images don't have to be encoded.
+
+create_exception!(stegegg, NoPayloadError, PyException, "There is no message for the key (wrong key or clean image).");
+
+fn extract_error(e: ExtractError) -> PyErr {
+    match e {
+        ExtractError::NoPayload(s) => NoPayloadError::new_err(s),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+//copy of the HxWx3 uint8 buffer as the image
+fn buffer_image(pixels: &PyBuffer<u8>, py: Python) -> PyResult<RgbImage> {
+    let (height, width) = match pixels.shape() {
+        [h, w, 3] => (*h, *w),
+        _ => return Err(PyValueError::new_err("Pixels must be HxWx3 RGB samples.")),
+    };
+    let dimension = |d: usize| u32::try_from(d).map_err(|_| PyValueError::new_err(format!("Image dimension {} is too large.", d)));
+    let (height, width) = (dimension(height)?, dimension(width)?);
+    RgbImage::from_raw(width, height, pixels.to_vec(py)?).ok_or_else(|| PyValueError::new_err("Invalid pixel buffer."))
+}
+
+/// Hide the message into the encoded image and return the stego image as PNG.
+#[pyfunction]
+fn embed<'py>(py: Python<'py>, image: &[u8], key: &[u8], message: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let png = py.allow_threads(|| buffer::embed_image(image, key, message)).map_err(PyValueError::new_err)?;
+    Ok(PyBytes::new(py, &png))
+}
+
+/// Extract the message from the encoded image. Raises NoPayloadError when there is no message for the key.
+#[pyfunction]
+fn extract<'py>(py: Python<'py>, image: &[u8], key: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let msg = py.allow_threads(|| buffer::extract_image(image, key)).map_err(extract_error)?;
+    Ok(PyBytes::new(py, &msg))
+}
+
+/// Capacity of the encoded image in bytes.
+#[pyfunction]
+fn capacity(py: Python, image: &[u8]) -> PyResult<usize> {
+    py.allow_threads(|| buffer::image_capacity(image)).map(|(_, _, capacity)| capacity).map_err(PyValueError::new_err)
+}
+
+/// Hide the message in place into the writable HxWx3 uint8 buffer (e.g. numpy array).
+#[pyfunction]
+fn embed_pixels(py: Python, pixels: PyBuffer<u8>, key: &[u8], message: &[u8]) -> PyResult<()> {
+    if pixels.readonly() {
+        return Err(PyValueError::new_err("Pixel buffer is read-only."));
+    }
+    let mut img = buffer_image(&pixels, py)?;
+    py.allow_threads(|| buffer::embed_rgb(&mut img, key, message)).map_err(PyValueError::new_err)?;
+    pixels.copy_from_slice(py, img.as_raw())
+}
+
+/// Extract the message from the HxWx3 uint8 buffer (e.g. numpy array).
+#[pyfunction]
+fn extract_pixels<'py>(py: Python<'py>, pixels: PyBuffer<u8>, key: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let img = buffer_image(&pixels, py)?;
+    let msg = py.allow_threads(|| buffer::extract_rgb(&img, key)).map_err(extract_error)?;
+    Ok(PyBytes::new(py, &msg))
+}
+
+#[pymodule]
+fn stegegg(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("NoPayloadError", m.py().get_type::<NoPayloadError>())?;
+    m.add_function(wrap_pyfunction!(embed, m)?)?;
+    m.add_function(wrap_pyfunction!(extract, m)?)?;
+    m.add_function(wrap_pyfunction!(capacity, m)?)?;
+    m.add_function(wrap_pyfunction!(embed_pixels, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_pixels, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,549 @@
+//! No_std core of the embedding engine. It uses only core, sha2, zeroize and crc32fast (all no_std) and never
+//! allocates, so it can be built for embedded devices or copied into other programs without the image crate.
+//! Images are caller provided buffers of interleaved RGB samples in raster order (3 bytes per pixel). The message
+//! is stored in the salted LSB format with the trailer, the spots are ordered by the key as it is (Kdf::Sha256).
+//! The buffers are readable by `stegegg -x` (which falls back to --kdf sha256) and images hidden with --kdf sha256
+//! are readable here, unless they are compressed, encrypted or labelled. scrypt needs too much memory for this core.
+
+use core::fmt;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+/// Length of the random per-message salt stored in front of the hidden data
+pub const SALT_LEN: usize = 16;
+
+/// Number of spots reserved for the salt
+pub const RESERVED_SPOTS: u32 = SALT_LEN as u32 * 8;
+
+/// Maximal message length which fits into the 3 bytes header
+pub const MAX_MSG_LEN: usize = 0xff_ffff;
+
+/// Error text of all the extraction paths when the header or trailer doesn't match the key
+pub const NO_PAYLOAD: &str = "No message for this key (wrong key or not a stegegg image).";
+
+/// Magic of the trailer stored behind the message, with the format version and CRC32 of the message. Every salted
+/// image has it, only the legacy images of the version 0 are read without it. Readers stop at the length, so old
+/// ones read new images too. Version 2 added the Kdf id byte in front of the checksum, version 1 trailers are
+/// one byte shorter and still read.
+pub const TRAILER_MAGIC: [u8; 3] = *b"sEg";
+/// Format version of the new messages
+pub const FORMAT_VERSION: u8 = 2;
+/// Length of the trailer without the label
+pub const TRAILER_LEN: usize = 9;
+
+//id of Kdf::Sha256 in the trailer, the derivation of the raw spot order
+const KDF_SHA256_ID: u8 = 1;
+
+/// Code for PRNG adapted from <https://prng.di.unimi.it/xoshiro256plusplus.c>
+pub struct PrngState(pub u64, pub u64, pub u64, pub u64);
+
+//state is derived from the key, don't leave it in the freed memory
+impl Drop for PrngState {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        self.1.zeroize();
+        self.2.zeroize();
+        self.3.zeroize();
+    }
+}
+
+fn rotl(x: u64, k: u32) -> u64 {
+    x.rotate_left(k)
+}
+
+/// Next number of the xoshiro256++ generator
+pub fn xoshiro256pp(s: &mut PrngState) -> u64 {
+    let result = rotl(s.0.wrapping_add(s.3), 23).wrapping_add(s.0);
+    let t = s.1 << 17;
+    s.2 ^= s.0;
+    s.3 ^= s.1;
+    s.1 ^= s.2;
+    s.0 ^= s.3;
+    s.2 ^= t;
+    s.3 = rotl(s.3, 45);
+    result
+}
+
+/// Source of the random numbers for the spot permutation
+pub trait SpotRng {
+    /// Next random number
+    fn next_u64(&mut self) -> u64;
+}
+
+impl SpotRng for PrngState {
+    fn next_u64(&mut self) -> u64 {
+        xoshiro256pp(self)
+    }
+}
+
+/// Shuffle vector
+pub fn shuffle<R: SpotRng + ?Sized>(v: &mut [u32], rng: &mut R) {
+    for i in 0..v.len() {
+        let j = (rng.next_u64() as usize) % v.len();
+        v.swap(i, j);
+    }
+}
+
+/// Keyed permutation of 0..n computed for each index, so the spot order of the low memory mode needs no memory.
+/// It is a balanced Feistel network over the smallest even power of two covering n, indices which fall outside
+/// 0..n are encrypted again (cycle walking). Its inverse tells which message bit a spot holds.
+pub struct FeistelPermutation {
+    n: u32,
+    half_bits: u32,
+    keys: [u64; FEISTEL_ROUNDS],
+}
+
+//rounds of the Feistel network
+const FEISTEL_ROUNDS: usize = 6;
+
+//round keys are derived from the key, don't leave them in the freed memory
+impl Drop for FeistelPermutation {
+    fn drop(&mut self) {
+        self.keys.zeroize();
+    }
+}
+
+impl FeistelPermutation {
+    /// Permutation of 0..n with the round keys drawn from the generator
+    pub fn new<R: SpotRng + ?Sized>(n: u32, rng: &mut R) -> FeistelPermutation {
+        let bits = (u32::BITS - n.saturating_sub(1).leading_zeros()).max(2);
+        FeistelPermutation { n, half_bits: bits.div_ceil(2), keys: core::array::from_fn(|_| rng.next_u64()) }
+    }
+
+    /// Number of the permuted positions
+    pub fn len(&self) -> u32 {
+        self.n
+    }
+
+    /// Permutation of no positions
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    //round function, SplitMix64 finalizer of the half block mixed with the round key
+    fn round(&self, key: u64, x: u64) -> u64 {
+        let mut z = (x ^ key).wrapping_mul(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        (z ^ (z >> 31)) & ((1 << self.half_bits) - 1)
+    }
+
+    fn encrypt(&self, x: u64) -> u64 {
+        let (mut l, mut r) = (x >> self.half_bits, x & ((1 << self.half_bits) - 1));
+        for &k in &self.keys {
+            (l, r) = (r, l ^ self.round(k, r));
+        }
+        l << self.half_bits | r
+    }
+
+    fn decrypt(&self, x: u64) -> u64 {
+        let (mut l, mut r) = (x >> self.half_bits, x & ((1 << self.half_bits) - 1));
+        for &k in self.keys.iter().rev() {
+            (l, r) = (r ^ self.round(k, l), l);
+        }
+        l << self.half_bits | r
+    }
+
+    /// Position of the index i (below n) in the permuted order
+    pub fn apply(&self, i: u32) -> u32 {
+        let mut x = self.encrypt(i as u64);
+        while x >= self.n as u64 {
+            x = self.encrypt(x);
+        }
+        x as u32
+    }
+
+    /// Index which apply maps to the position p (below n)
+    pub fn invert(&self, p: u32) -> u32 {
+        let mut x = self.decrypt(p as u64);
+        while x >= self.n as u64 {
+            x = self.decrypt(x);
+        }
+        x as u32
+    }
+}
+
+/// Permutation of the hidding spots. Implementations must derive the order from the key and the salt only,
+/// so the extraction gets the same order. Library users can plug in e.g. a DRBG mandated by their compliance
+/// regime (images are then readable only by the same selector).
+pub trait SpotSelector {
+    /// Reorder the spots by the key and the salt
+    fn select(&self, key: &[u8], salt: &[u8], spots: &mut [u32]);
+}
+
+/// Default selector. Spots are shuffled by xoshiro256++ seeded with SHA256(key || salt).
+pub struct XoshiroSelector;
+
+impl SpotSelector for XoshiroSelector {
+    fn select(&self, key: &[u8], salt: &[u8], spots: &mut [u32]) {
+        shuffle(spots, &mut init_prng(key, salt, &[]));
+    }
+}
+
+/// Returns nth bit from the byte
+pub fn get_bit(b: u8, n: u8) -> u8 {
+    (b >> n) & 1
+}
+
+/// Init PRNG with SHA256 from the user key, the per-message salt and the optional context (e.g. carrier file name)
+pub fn init_prng(key: &[u8], salt: &[u8], context: &[u8]) -> PrngState {
+    let mut hasher = Sha256::new();
+    hasher.update(key);
+    hasher.update(salt);
+
+    //context is length prefixed, so it can't be confused with the key. Empty context keeps the hash unchanged.
+    if !context.is_empty() {
+        hasher.update((context.len() as u64).to_le_bytes());
+        hasher.update(context);
+    }
+    let mut key_hash = hasher.finalize();
+
+    //convert 32 SHA256 bytes into 4 u64.
+    let state = PrngState(
+        u64::from_be_bytes(key_hash[0..8].try_into().unwrap()),
+        u64::from_be_bytes(key_hash[8..16].try_into().unwrap()),
+        u64::from_be_bytes(key_hash[16..24].try_into().unwrap()),
+        u64::from_be_bytes(key_hash[24..32].try_into().unwrap())
+    );
+    key_hash.as_mut_slice().zeroize();
+    state
+}
+
+/// Insecure. PRNG state expanded from the plain number by SplitMix64, for reproducible spot orders in research and tests
+pub fn seeded_prng(seed: u64) -> PrngState {
+    let mut x = seed;
+    let mut next = || {
+        x = x.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = x;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+    PrngState(next(), next(), next(), next())
+}
+
+/// Producer of the message bits, LSB first in each byte. Message is stored in whole bytes,
+/// the last incomplete byte is padded with zero bits.
+pub trait BitSource {
+    /// Next bit (0 or 1), None when the source is exhausted
+    fn next_bit(&mut self) -> Option<u8>;
+}
+
+impl<S: BitSource + ?Sized> BitSource for &mut S {
+    fn next_bit(&mut self) -> Option<u8> {
+        (**self).next_bit()
+    }
+}
+
+/// Bits of the bytes from the iterator
+pub struct ByteBits<I: Iterator<Item = u8>> {
+    bytes: I,
+    byte: u8,
+    n: u8,
+}
+
+impl<I: Iterator<Item = u8>> ByteBits<I> {
+    /// Bits of the bytes, LSB first
+    pub fn new<T: IntoIterator<IntoIter = I>>(bytes: T) -> ByteBits<I> {
+        ByteBits { bytes: bytes.into_iter(), byte: 0, n: 8 }
+    }
+
+    /// The underlying iterator
+    pub fn bytes_mut(&mut self) -> &mut I {
+        &mut self.bytes
+    }
+}
+
+impl<I: Iterator<Item = u8>> BitSource for ByteBits<I> {
+    fn next_bit(&mut self) -> Option<u8> {
+        if self.n == 8 {
+            self.byte = self.bytes.next()?;
+            self.n = 0;
+        }
+        self.n += 1;
+        Some(get_bit(self.byte, self.n - 1))
+    }
+}
+
+/// Errors of the raw embedding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawError {
+    /// Image has less samples than the salt needs
+    TooSmall,
+    /// Message doesn't fit into the image
+    TooLarge,
+    /// Scratch buffer for the spots must have at least the given number of entries
+    Scratch(usize),
+    /// Output buffer must have at least the given number of bytes
+    Output(usize),
+    /// Header or trailer doesn't describe a message (wrong key or clean image)
+    NoPayload,
+    /// Checksum of the message doesn't match, the key is right but the image was modified
+    Checksum,
+    /// Message has a newer format version or flags (compression, encryption, label) which need the std API
+    Unsupported(u8),
+}
+
+impl fmt::Display for RawError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RawError::TooSmall => write!(f, "Image is too small!"),
+            RawError::TooLarge => write!(f, "Input message is too large."),
+            RawError::Scratch(n) => write!(f, "Scratch buffer must have at least {} entries.", n),
+            RawError::Output(n) => write!(f, "Output buffer must have at least {} bytes.", n),
+            RawError::NoPayload => write!(f, "{}", NO_PAYLOAD),
+            RawError::Checksum => write!(f, "Message checksum doesn't match. The key is right, but the carrier was modified or damaged."),
+            RawError::Unsupported(v) => write!(f, "Message has format version {} (flags {:#04x}), which the raw engine can't read. Use the std API.", v & 0x0f, v & 0xf0),
+        }
+    }
+}
+
+/// Number of scratch entries needed for the image with given number of samples
+pub fn scratch_len(samples: usize) -> usize {
+    samples.saturating_sub(RESERVED_SPOTS as usize)
+}
+
+//fill the scratch with the message samples (after the salt) ordered by the selector
+fn message_spots<'a, S: SpotSelector + ?Sized>(selector: &S, samples: usize, key: &[u8], salt: &[u8], scratch: &'a mut [u32]) -> Result<&'a [u32], RawError> {
+    if samples < RESERVED_SPOTS as usize || samples > u32::MAX as usize {
+        return Err(RawError::TooSmall);
+    }
+    let spots = scratch.get_mut(..scratch_len(samples)).ok_or(RawError::Scratch(scratch_len(samples)))?;
+    for (i, s) in spots.iter_mut().enumerate() {
+        *s = RESERVED_SPOTS + i as u32;
+    }
+    selector.select(key, salt, spots);
+    Ok(spots)
+}
+
+//write the bits of the byte into the samples, LSB first
+fn write_byte(samples: &mut [u8], spots: &[u32], b: u8) {
+    for (n, &spot) in spots.iter().enumerate() {
+        let s = &mut samples[spot as usize];
+        *s = *s & 0xfe | get_bit(b, n as u8);
+    }
+}
+
+//read the byte from the LSBs of the samples
+fn read_byte(samples: &[u8], spots: &[u32]) -> u8 {
+    spots.iter().enumerate().fold(0, |b, (n, &spot)| b | (samples[spot as usize] & 1) << n)
+}
+
+/// Hide the message into the samples with the caller's random salt. Scratch must have scratch_len(samples.len())
+/// entries. Returns number of used samples (without the salt).
+pub fn embed_raw(samples: &mut [u8], key: &[u8], salt: &[u8; SALT_LEN], message: &[u8], scratch: &mut [u32]) -> Result<usize, RawError> {
+    embed_raw_with(&XoshiroSelector, samples, key, salt, message, scratch)
+}
+
+/// Embed_raw with the spots ordered by the given selector
+pub fn embed_raw_with<S: SpotSelector + ?Sized>(selector: &S, samples: &mut [u8], key: &[u8], salt: &[u8; SALT_LEN], message: &[u8], scratch: &mut [u32]) -> Result<usize, RawError> {
+    if message.len() > MAX_MSG_LEN || (message.len() + 3 + TRAILER_LEN) * 8 > scratch_len(samples.len()) {
+        return Err(RawError::TooLarge);
+    }
+    embed_bits_with(selector, samples, key, salt, &mut ByteBits::new(message.iter().copied()), scratch)
+}
+
+/// Hide the bits from the source, so the message doesn't have to be in the memory. Trailer and the length header
+/// are written when the source is exhausted. When the source has more bits than fit into the image, the samples are already
+/// modified and TooLarge is returned. Returns number of used samples (without the salt).
+pub fn embed_bits_with<S: SpotSelector + ?Sized, B: BitSource + ?Sized>(selector: &S, samples: &mut [u8], key: &[u8], salt: &[u8; SALT_LEN], bits: &mut B, scratch: &mut [u32]) -> Result<usize, RawError> {
+    let spots = message_spots(selector, samples.len(), key, salt, scratch)?;
+    if spots.len() < (3 + TRAILER_LEN) * 8 {
+        return Err(RawError::TooSmall);
+    }
+
+    let salt_spots: [u32; RESERVED_SPOTS as usize] = core::array::from_fn(|i| i as u32);
+    for (b, s) in salt.iter().zip(salt_spots.chunks_exact(8)) {
+        write_byte(samples, s, *b);
+    }
+
+    //message bits behind the header, the last byte is padded with zeros. Checksum is computed over the whole bytes.
+    let (header, body) = spots.split_at(3 * 8);
+    let limit = (body.len() / 8 - TRAILER_LEN).min(MAX_MSG_LEN) * 8;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut byte = 0;
+    let mut used = 0;
+    let mut exhausted = false;
+    loop {
+        let bit = match exhausted { true => None, false => bits.next_bit() };
+        exhausted = bit.is_none();
+        if exhausted && used % 8 == 0 {
+            break;
+        }
+        if used == limit {
+            return Err(RawError::TooLarge);
+        }
+        let bit = bit.unwrap_or(0) & 1;
+        let s = &mut samples[body[used] as usize];
+        *s = *s & 0xfe | bit;
+        byte |= bit << (used % 8);
+        used += 1;
+        if used % 8 == 0 {
+            hasher.update(&[byte]);
+            byte = 0;
+        }
+    }
+
+    //trailer with the format version, the Kdf id and the checksum behind the message
+    let crc = hasher.finalize().to_le_bytes();
+    let trailer = [TRAILER_MAGIC[0], TRAILER_MAGIC[1], TRAILER_MAGIC[2], FORMAT_VERSION, KDF_SHA256_ID, crc[0], crc[1], crc[2], crc[3]];
+    for (b, s) in trailer.iter().zip(body[used..].chunks_exact(8)) {
+        write_byte(samples, s, *b);
+    }
+
+    let len = ((used / 8) as u32).to_le_bytes();
+    for (b, s) in len[..3].iter().zip(header.chunks_exact(8)) {
+        write_byte(samples, s, *b);
+    }
+    Ok(used + (3 + TRAILER_LEN) * 8)
+}
+
+/// Extract the message from the samples into the output buffer. Scratch must have scratch_len(samples.len()) entries.
+pub fn extract_raw<'a>(samples: &[u8], key: &[u8], scratch: &mut [u32], out: &'a mut [u8]) -> Result<&'a [u8], RawError> {
+    extract_raw_with(&XoshiroSelector, samples, key, scratch, out)
+}
+
+/// Extract_raw with the spots ordered by the given selector
+pub fn extract_raw_with<'a, S: SpotSelector + ?Sized>(selector: &S, samples: &[u8], key: &[u8], scratch: &mut [u32], out: &'a mut [u8]) -> Result<&'a [u8], RawError> {
+    let header = read_header_raw_with(selector, samples, key, scratch)?;
+    read_message_raw(samples, &header, scratch, out)
+}
+
+/// Validated header and trailer of the message. The message spots stay in the scratch for read_message_raw.
+pub struct RawHeader {
+    /// Length of the message in bytes
+    pub len: usize,
+    crc: u32,
+    spots: usize,
+}
+
+/// Read and validate the header and the trailer, so the caller can size the output by the message length
+pub fn read_header_raw_with<S: SpotSelector + ?Sized>(selector: &S, samples: &[u8], key: &[u8], scratch: &mut [u32]) -> Result<RawHeader, RawError> {
+    if samples.len() < RESERVED_SPOTS as usize {
+        return Err(RawError::TooSmall);
+    }
+    let mut salt = [0u8; SALT_LEN];
+    for (i, b) in salt.iter_mut().enumerate() {
+        *b = samples[i * 8..i * 8 + 8].iter().enumerate().fold(0, |b, (n, s)| b | (s & 1) << n);
+    }
+
+    let spots = message_spots(selector, samples.len(), key, &salt, scratch)?;
+    if spots.len() < 3 * 8 {
+        return Err(RawError::TooSmall);
+    }
+    let len = (0..3).fold(0usize, |len, i| len | (read_byte(samples, &spots[i * 8..i * 8 + 8]) as usize) << (i * 8));
+    if (len + 3 + TRAILER_LEN - 1) * 8 > spots.len() {
+        return Err(RawError::NoPayload);
+    }
+
+    //trailer must follow the message, version 1 trailers have no Kdf id
+    let body = &spots[3 * 8..];
+    let trailer_byte = |i: usize| read_byte(samples, &body[(len + i) * 8..(len + i + 1) * 8]);
+    if (0..3).any(|i| trailer_byte(i) != TRAILER_MAGIC[i]) {
+        return Err(RawError::NoPayload);
+    }
+    let version = trailer_byte(3);
+    if version & 0x0f > FORMAT_VERSION || version & 0xf0 != 0 {
+        return Err(RawError::Unsupported(version));
+    }
+    let crc_at = if version & 0x0f < 2 { 4 } else { 5 };
+    if (len + 3 + crc_at + 4) * 8 > spots.len() {
+        return Err(RawError::NoPayload);
+    }
+    let crc = u32::from_le_bytes(core::array::from_fn(|i| trailer_byte(crc_at + i)));
+    Ok(RawHeader { len, crc, spots: spots.len() })
+}
+
+/// Read the message of the header from the spots left in the scratch by read_header_raw_with and check its checksum
+pub fn read_message_raw<'a>(samples: &[u8], header: &RawHeader, scratch: &[u32], out: &'a mut [u8]) -> Result<&'a [u8], RawError> {
+    let body = scratch.get(3 * 8..header.spots).ok_or(RawError::Scratch(header.spots))?;
+    let out = out.get_mut(..header.len).ok_or(RawError::Output(header.len))?;
+    for (b, s) in out.iter_mut().zip(body.chunks_exact(8)) {
+        *b = read_byte(samples, s);
+    }
+    if crc32fast::hash(out) != header.crc {
+        return Err(RawError::Checksum);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feistel_is_a_permutation() {
+        for n in [1, 2, 3, 5, 64, 100, 1000, 4097, 12_345] {
+            let perm = FeistelPermutation::new(n, &mut seeded_prng(n as u64));
+            let mut seen = vec![false; n as usize];
+            for i in 0..n {
+                let p = perm.apply(i);
+                assert!(p < n && !seen[p as usize], "n {} index {} maps to {}", n, i, p);
+                seen[p as usize] = true;
+                assert_eq!(perm.invert(p), i);
+            }
+        }
+    }
+
+    #[test]
+    fn feistel_order_depends_on_the_key() {
+        let order = |key: &[u8]| {
+            let perm = FeistelPermutation::new(1000, &mut init_prng(key, b"salt", &[]));
+            (0..1000).map(|i| perm.apply(i)).collect::<Vec<_>>()
+        };
+        assert_eq!(order(b"key"), order(b"key"));
+        assert_ne!(order(b"key"), order(b"other key"));
+        assert_ne!(order(b"key"), (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn feistel_of_no_positions_is_empty() {
+        assert!(FeistelPermutation::new(0, &mut seeded_prng(1)).is_empty());
+        assert_eq!(FeistelPermutation::new(7, &mut seeded_prng(1)).len(), 7);
+    }
+
+    #[test]
+    fn shuffle_is_a_permutation() {
+        let mut v: Vec<u32> = (0..500).collect();
+        shuffle(&mut v, &mut seeded_prng(7));
+        assert_ne!(v, (0..500).collect::<Vec<_>>());
+        v.sort_unstable();
+        assert_eq!(v, (0..500).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let mut samples: Vec<u8> = (0..3000u32).map(|i| (i * 31) as u8).collect();
+        let mut scratch = vec![0; scratch_len(samples.len())];
+        let used = embed_raw(&mut samples, b"key", &[7; SALT_LEN], b"raw message", &mut scratch).unwrap();
+        assert_eq!(used, (3 + 11 + TRAILER_LEN) * 8);
+
+        let mut out = [0; 64];
+        assert_eq!(extract_raw(&samples, b"key", &mut scratch, &mut out).unwrap(), b"raw message");
+        assert!(matches!(extract_raw(&samples, b"other key", &mut scratch, &mut out), Err(RawError::NoPayload)));
+        assert!(matches!(extract_raw(&samples, b"key", &mut scratch, &mut [0; 4]), Err(RawError::Output(11))));
+    }
+
+    #[test]
+    fn raw_damaged_message_fails_checksum() {
+        let mut samples = vec![0x55; 3000];
+        let mut scratch = vec![0; scratch_len(samples.len())];
+        embed_raw(&mut samples, b"key", &[7; SALT_LEN], b"raw message", &mut scratch).unwrap();
+
+        //scratch holds the spot order after the extraction, flip the first message bit
+        let mut out = [0; 64];
+        extract_raw(&samples, b"key", &mut scratch, &mut out).unwrap();
+        samples[scratch[3 * 8] as usize] ^= 1;
+        assert!(matches!(extract_raw(&samples, b"key", &mut scratch, &mut out), Err(RawError::Checksum)));
+    }
+
+    #[test]
+    fn raw_buffers_are_checked() {
+        let mut samples = vec![0; 3000];
+        assert!(matches!(embed_raw(&mut samples, b"key", &[0; SALT_LEN], b"x", &mut [0; 10]), Err(RawError::Scratch(_))));
+        assert!(matches!(embed_raw(&mut samples[..100], b"key", &[0; SALT_LEN], b"x", &mut [0; 10]), Err(RawError::TooLarge)));
+        assert!(matches!(extract_raw(&samples[..100], b"key", &mut [0; 10], &mut [0; 10]), Err(RawError::TooSmall)));
+        let mut scratch = vec![0; scratch_len(samples.len())];
+        assert!(matches!(embed_raw(&mut samples, b"key", &[0; SALT_LEN], &[0; 400], &mut scratch), Err(RawError::TooLarge)));
+    }
+}
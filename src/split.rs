@@ -0,0 +1,184 @@
+//! Splitting of the message into the shards for several carriers. Every shard has the header with the random id
+//! of the set, its index, the number of the data shards and the message length. The optional parity shard is the
+//! XOR of the data shards, so the message is rebuilt even when any single carrier of the set is lost.
+
+use zeroize::Zeroizing;
+use crate::ExtractError;
+
+/// Magic of the shard header
+pub const SHARD_MAGIC: &[u8; 4] = b"sEgS";
+
+/// Length of the random id of the set
+pub const SET_ID_LEN: usize = 8;
+
+/// Length of the shard header: magic, set id, index, data shards, parity flag and the message length
+pub const SHARD_HEADER_LEN: usize = SHARD_MAGIC.len() + SET_ID_LEN + 3 + 4;
+
+/// Most data shards of one set
+pub const MAX_SHARDS: usize = 255;
+
+/// Header of the shard
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ShardHeader {
+    /// Random id of the set, shards of the different sets are never joined
+    pub set_id: [u8; SET_ID_LEN],
+    /// Index of the data shard, the parity shard has the index equal to the number of the data shards
+    pub index: u8,
+    /// Number of the data shards of the set
+    pub data_shards: u8,
+    /// Whether the set has the parity shard
+    pub parity: bool,
+    /// Length of the whole message
+    pub message_len: u32,
+}
+
+impl ShardHeader {
+
+    /// Whether it is the header of the parity shard
+    pub fn is_parity(&self) -> bool {
+        self.parity && self.index == self.data_shards
+    }
+}
+
+/// Length of every shard (without the header) of the message split into the data shards
+pub fn shard_len(message_len: usize, data_shards: usize) -> usize {
+    message_len.div_ceil(data_shards.max(1))
+}
+
+/// Split the message into the data shards, zero padded to the same length, and the parity shard when requested.
+/// Every shard starts with the header.
+pub fn split(message: &[u8], data_shards: usize, parity: bool) -> Result<Vec<Zeroizing<Vec<u8>>>, String> {
+
+    if data_shards == 0 || data_shards > MAX_SHARDS {
+        return Err(format!("Message can be split into 1 to {} data shards, not {}.", MAX_SHARDS, data_shards));
+    }
+    let message_len = u32::try_from(message.len()).map_err(|_| format!("Message has {} bytes, too many to split.", message.len()))?;
+    let mut set_id = [0u8; SET_ID_LEN];
+    getrandom::fill(&mut set_id).map_err(|s| format!("Can't generate random set id. {}", s))?;
+
+    let len = shard_len(message.len(), data_shards);
+    let header = |index: usize| {
+        let mut shard = Zeroizing::new(Vec::with_capacity(SHARD_HEADER_LEN + len));
+        shard.extend_from_slice(SHARD_MAGIC);
+        shard.extend_from_slice(&set_id);
+        shard.extend_from_slice(&[index as u8, data_shards as u8, parity as u8]);
+        shard.extend_from_slice(&message_len.to_le_bytes());
+        shard
+    };
+
+    let mut shards = Vec::with_capacity(data_shards + parity as usize);
+    let mut xor = Zeroizing::new(vec![0u8; len]);
+    for index in 0..data_shards {
+        let data = message.get(index * len..).unwrap_or_default();
+        let data = &data[..data.len().min(len)];
+        let mut shard = header(index);
+        shard.extend_from_slice(data);
+        shard.resize(SHARD_HEADER_LEN + len, 0);
+        xor.iter_mut().zip(&shard[SHARD_HEADER_LEN..]).for_each(|(x, b)| *x ^= b);
+        shards.push(shard);
+    }
+    if parity {
+        let mut shard = header(data_shards);
+        shard.extend_from_slice(&xor);
+        shards.push(shard);
+    }
+    Ok(shards)
+}
+
+/// Header of the shard, None when the data is not a shard
+pub fn shard_header(shard: &[u8]) -> Option<ShardHeader> {
+
+    if shard.len() < SHARD_HEADER_LEN || &shard[..SHARD_MAGIC.len()] != SHARD_MAGIC {
+        return None;
+    }
+    let mut set_id = [0u8; SET_ID_LEN];
+    set_id.copy_from_slice(&shard[SHARD_MAGIC.len()..SHARD_MAGIC.len() + SET_ID_LEN]);
+    let fields = &shard[SHARD_MAGIC.len() + SET_ID_LEN..];
+    let header = ShardHeader {
+        set_id,
+        index: fields[0],
+        data_shards: fields[1],
+        parity: fields[2] != 0,
+        message_len: u32::from_le_bytes([fields[3], fields[4], fields[5], fields[6]]),
+    };
+    let valid = header.data_shards > 0 && (header.index < header.data_shards || header.is_parity())
+        && shard.len() - SHARD_HEADER_LEN == shard_len(header.message_len as usize, header.data_shards as usize);
+    valid.then_some(header)
+}
+
+/// Join the shards of one set back into the message. One missing data shard is rebuilt from the parity shard.
+pub fn join<S: AsRef<[u8]>>(shards: &[S]) -> Result<Zeroizing<Vec<u8>>, ExtractError> {
+
+    let mut first: Option<ShardHeader> = None;
+    for shard in shards {
+        let header = shard_header(shard.as_ref()).ok_or_else(|| ExtractError::Decode("Carrier doesn't hold a shard of the split message.".into()))?;
+        match first {
+            None => first = Some(header),
+            Some(f) if f.set_id != header.set_id => return Err(ExtractError::Decode("Carriers belong to the different split messages.".into())),
+            Some(f) if (f.data_shards, f.parity, f.message_len) != (header.data_shards, header.parity, header.message_len) =>
+                return Err(ExtractError::Decode("Shard headers of the set don't match.".into())),
+            Some(_) => {},
+        }
+    }
+    let first = first.ok_or_else(|| ExtractError::NoPayload("There are no shards to join.".into()))?;
+
+    //data shards by the index, the last slot is the parity shard
+    let count = first.data_shards as usize;
+    let mut slots: Vec<Option<&[u8]>> = vec![None; count + 1];
+    for shard in shards {
+        let shard = shard.as_ref();
+        slots[shard[SHARD_MAGIC.len() + SET_ID_LEN] as usize] = Some(&shard[SHARD_HEADER_LEN..]);
+    }
+    let missing: Vec<usize> = (0..count).filter(|&i| slots[i].is_none()).collect();
+
+    let rebuilt = match missing.as_slice() {
+        [] => Zeroizing::new(Vec::new()),
+        [_] if slots[count].is_some() => {
+            let mut rebuilt = Zeroizing::new(slots[count].unwrap_or_default().to_vec());
+            for data in slots[..count].iter().flatten() {
+                rebuilt.iter_mut().zip(data.iter()).for_each(|(x, b)| *x ^= b);
+            }
+            rebuilt
+        },
+        _ => {
+            let missing = missing.iter().map(|i| (i + 1).to_string()).collect::<Vec<_>>().join(", ");
+            let parity = if first.parity && slots[count].is_none() { " and the parity shard" } else { "" };
+            return Err(ExtractError::Failed(format!("Shards {} of {}{} are missing.", missing, count, parity)));
+        },
+    };
+
+    let mut message = Zeroizing::new(Vec::with_capacity(count * shard_len(first.message_len as usize, count)));
+    for data in &slots[..count] {
+        message.extend_from_slice(data.unwrap_or(&rebuilt));
+    }
+    message.truncate(first.message_len as usize);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_single_lost_carrier_is_rebuilt_from_the_parity_shard() {
+        let message: Vec<u8> = (0..1001u32).map(|i| (i * 7) as u8).collect();
+        let shards = split(&message, 3, true).unwrap();
+        assert_eq!(shards.len(), 4);
+        assert_eq!(join(&shards).unwrap().as_slice(), message.as_slice());
+        for lost in 0..shards.len() {
+            let kept: Vec<&[u8]> = shards.iter().enumerate().filter(|(i, _)| *i != lost).map(|(_, s)| s.as_slice()).collect();
+            assert_eq!(join(&kept).unwrap().as_slice(), message.as_slice());
+        }
+    }
+
+    #[test]
+    fn two_lost_carriers_or_mixed_sets_are_refused() {
+        let message = b"message split over the carriers";
+        let shards = split(message, 3, true).unwrap();
+        assert!(matches!(join(&shards[2..]), Err(ExtractError::Failed(_))));
+        let without_parity = split(message, 2, false).unwrap();
+        assert!(matches!(join(&without_parity[1..]), Err(ExtractError::Failed(_))));
+        let mixed = [shards[0].as_slice(), without_parity[1].as_slice()];
+        assert!(matches!(join(&mixed), Err(ExtractError::Decode(_))));
+    }
+}
@@ -0,0 +1,837 @@
+//! Hidding spots of the images in the pixel mode, the message format on them and the key derivation
+
+use std::io::{Read, Write};
+use sha2::{Sha256, Digest};
+use image::RgbImage;
+use zeroize::{Zeroize, Zeroizing};
+use crate::*;
+
+/// Layout of the hidding spots in the image. Spot is a single bit of a color sample or, in the redundant mode,
+/// whole pixel carrying the same bit in the LSB of all three channels. Depth is the number of bits used in each channel.
+#[derive(Clone, Copy)]
+pub struct SpotLayout {
+    /// Spots are whole pixels, majority voted on extraction
+    pub redundant: bool,
+    /// Bits used in the red, green and blue channels
+    pub depth: [u8; 3],
+}
+
+/// Plain LSB embedding, one bit in each channel
+pub const DEFAULT_DEPTH: [u8; 3] = [1, 1, 1];
+
+/// Length of the depth descriptor written after the salt, two bytes of depths and four bytes of the keyed check
+pub const DEPTH_DESCRIPTOR_LEN: usize = 6;
+
+/// Pixels reserved for the salt and the depth descriptor in the images with custom depth
+pub const DEPTH_RESERVED_PIXELS: u32 = (RESERVED_SPOTS + 8 * DEPTH_DESCRIPTOR_LEN as u32).div_ceil(3);
+
+impl SpotLayout {
+
+    /// Number of hidding spots in one pixel
+    pub fn spots_per_pixel(&self) -> u32 {
+        if self.redundant { 1 } else { self.depth.iter().map(|&d| d as u32).sum() }
+    }
+
+    /// Number of all hidding spots in the image
+    pub fn spot_count(&self, image: &RgbImage) -> Result<u32, String> {
+        self.spots(image.width(), image.height())
+    }
+
+    /// Number of all hidding spots in the image of given size. Spots are indexed by u32, so larger images are refused.
+    pub fn spots(&self, width: u32, height: u32) -> Result<u32, String> {
+        let spots = width as u64 * height as u64 * self.spots_per_pixel() as u64;
+        u32::try_from(spots).map_err(|_| format!("Image {}x{} has {} hidding spots, at most {} are supported.", width, height, spots, u32::MAX))
+    }
+
+    /// First spot after the salt (and the depth descriptor of the custom depth)
+    pub fn first_spot(&self) -> u32 {
+        if self.redundant || self.depth == DEFAULT_DEPTH { RESERVED_SPOTS } else { DEPTH_RESERVED_PIXELS * self.spots_per_pixel() }
+    }
+
+    //layout of the salt area, which is always in the LSBs
+    fn salt_layout(&self) -> SpotLayout {
+        SpotLayout { redundant: self.redundant, depth: DEFAULT_DEPTH }
+    }
+
+    /// Pixel coordinates, color offsets and bit plane of the spot
+    pub fn position(&self, spot_idx: u32, width: u32) -> (u32, u32, std::ops::Range<usize>, u8) {
+        if self.redundant {
+            (spot_idx % width, spot_idx / width, 0..3, 0)
+        } else {
+            let spots_per_pixel = self.spots_per_pixel();
+            let pixel_idx = spot_idx / spots_per_pixel;
+            let (mut color_offset, mut plane) = (0, (spot_idx % spots_per_pixel) as u8);
+            while plane >= self.depth[color_offset] {
+                plane -= self.depth[color_offset];
+                color_offset += 1;
+            }
+            (pixel_idx % width, pixel_idx / width, color_offset..color_offset + 1, plane)
+        }
+    }
+
+    /// Number of samples with the bit set to 1 and the number of all samples in the spot
+    pub fn read_votes(&self, image: &RgbImage, spot_idx: u32) -> (u32, u32) {
+        let (x, y, colors, plane) = self.position(spot_idx, image.width());
+        let pixel = image.get_pixel(x, y);
+        let total = colors.len() as u32;
+        (colors.map(|c| (pixel[c] >> plane & 1) as u32).sum(), total)
+    }
+
+    /// Read bit from the spot. Redundant spots are majority voted.
+    pub fn read_bit(&self, image: &RgbImage, spot_idx: u32) -> u8 {
+        let (ones, total) = self.read_votes(image, spot_idx);
+        (ones * 2 > total) as u8
+    }
+
+    /// Write bit into the spot
+    pub fn write_bit(&self, image: &mut RgbImage, spot_idx: u32, bit: u8) {
+        let (x, y, colors, plane) = self.position(spot_idx, image.width());
+        let pixel = image.get_pixel_mut(x, y);
+        for c in colors {
+            pixel[c] = pixel[c] & !(1 << plane) | bit << plane;
+        }
+    }
+}
+
+/// Read random salt from the first spots in raster order. This area is key independent.
+pub fn read_salt(image: &RgbImage, layout: SpotLayout) -> Vec<u8> {
+    carrier::read_salt(&ImageCarrier { image, layout: layout.salt_layout() })
+}
+
+/// Write salt into the first spots in raster order
+pub fn write_salt(image: &mut RgbImage, layout: SpotLayout, salt: &[u8]) {
+    carrier::write_salt(&mut ImageCarrier { image, layout: layout.salt_layout() }, salt)
+}
+
+//descriptor of the custom depth, masked with the key so it looks like the salt. Zero check bytes tell
+//the descriptor apart from the data of the images with the default depth.
+fn depth_descriptor(key: &[u8], salt: &[u8], context: &[u8], mut descriptor: [u8; DEPTH_DESCRIPTOR_LEN]) -> [u8; DEPTH_DESCRIPTOR_LEN] {
+    mask_data(key, salt, &[context, b"depth"].concat(), &mut descriptor);
+    descriptor
+}
+
+/// Write the custom depth of the layout into the LSBs after the salt
+pub fn write_depth(image: &mut RgbImage, key: &[u8], salt: &[u8], context: &[u8], depth: [u8; 3]) {
+    let descriptor = depth_descriptor(key, salt, context, [depth[0] << 4 | depth[1], depth[2] << 4, 0, 0, 0, 0]);
+    let layout = SpotLayout { redundant: false, depth: DEFAULT_DEPTH };
+    for (i, b) in descriptor.iter().enumerate() {
+        for n in 0..8 {
+            layout.write_bit(image, RESERVED_SPOTS + (i * 8 + n) as u32, get_bit(*b, n as u8));
+        }
+    }
+}
+
+/// Read the custom depth written after the salt, None for the images with the default depth
+pub fn read_depth(image: &RgbImage, key: &[u8], salt: &[u8], context: &[u8]) -> Option<[u8; 3]> {
+    let layout = SpotLayout { redundant: false, depth: DEFAULT_DEPTH };
+    if layout.spot_count(image).ok()? < DEPTH_RESERVED_PIXELS * 3 {
+        return None;
+    }
+    let mut descriptor = [0; DEPTH_DESCRIPTOR_LEN];
+    for (i, b) in descriptor.iter_mut().enumerate() {
+        for n in 0..8 {
+            *b |= layout.read_bit(image, RESERVED_SPOTS + (i * 8 + n) as u32) << n;
+        }
+    }
+    let d = depth_descriptor(key, salt, context, descriptor);
+    let depth = [d[0] >> 4, d[0] & 0xf, d[1] >> 4];
+    (d[1] & 0xf == 0 && d[2..] == [0; 4] && depth.iter().all(|&b| b <= 8) && depth != [0; 3]).then_some(depth)
+}
+
+/// Create vector of hidding spot indices (skipping the reserved area) shuffled into the key dependent random sequence
+pub fn hidding_spots(image: &RgbImage, layout: SpotLayout, first_spot: u32, prng_state: &mut PrngState) -> Result<Vec<u32>, String> {
+    Ok(shuffled_spots(layout.spot_count(image)?, first_spot, prng_state))
+}
+
+/// Create vector of spot indices from first_spot to spot_count shuffled into the key dependent random sequence
+pub fn shuffled_spots(spot_count: u32, first_spot: u32, prng_state: &mut PrngState) -> Vec<u32> {
+    let mut indices: Vec<u32> = (first_spot..spot_count).collect();
+    shuffle(&mut indices, prng_state);
+    indices
+}
+
+/// Create new random salt
+pub fn random_salt() -> Result<Vec<u8>, String> {
+    let mut salt = vec![0; SALT_LEN];
+    getrandom::fill(&mut salt).map_err(|s| format!("Can't generate random salt. {}", s))?;
+    Ok(salt)
+}
+
+/// Mask data with the keystream SHA256(key || salt || context || counter). Masking again unmasks the data.
+pub fn mask_data(key: &[u8], salt: &[u8], context: &[u8], data: &mut [u8]) {
+    for (i, block) in data.chunks_mut(32).enumerate() {
+        let mut hasher = Sha256::new();
+        hasher.update(key);
+        hasher.update(salt);
+        hasher.update((context.len() as u64).to_le_bytes());
+        hasher.update(context);
+        hasher.update((i as u64).to_le_bytes());
+        let mut keystream = hasher.finalize();
+        for (b, k) in block.iter_mut().zip(keystream.iter()) {
+            *b ^= k;
+        }
+        keystream.as_mut_slice().zeroize();
+    }
+}
+
+/// Derivation of the spot order key from the password. Scrypt (the default) stretches it with the salt, so every
+/// guess of the password costs 32MiB and ~0.1s. Sha256 is the legacy derivation of the older carriers, which uses
+/// the password as it is, hashed with the salt by init_prng.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Kdf {
+    /// SHA-256 of the password with the salt, the derivation of the older versions and of the raw engine
+    Sha256,
+    /// scrypt with N = 2^15, r = 8, p = 1
+    #[default]
+    Scrypt,
+}
+
+//scrypt cost of Kdf::Scrypt, N = 2^15, r = 8, p = 1
+const SCRYPT_LOG_N: u8 = 15;
+
+impl Kdf {
+
+    /// Id of the derivation recorded in the trailer, 0 tells it wasn't recorded. The raw engine writes the Sha256 id.
+    pub fn id(&self) -> u8 {
+        match self {
+            Kdf::Sha256 => 1,
+            Kdf::Scrypt => 2,
+        }
+    }
+
+    /// Derivation of the id, None for the ids of the newer versions
+    pub fn from_id(id: u8) -> Option<Kdf> {
+        [Kdf::Sha256, Kdf::Scrypt].into_iter().find(|k| k.id() == id)
+    }
+
+    /// Name of the derivation for --kdf
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kdf::Sha256 => "sha256",
+            Kdf::Scrypt => "scrypt",
+        }
+    }
+
+    /// Key for the spot order and the depth descriptor of the message with the given salt
+    pub fn derive(&self, key: &[u8], salt: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+        match self {
+            Kdf::Sha256 => Ok(Zeroizing::new(key.to_vec())),
+            Kdf::Scrypt => scrypt_key(key, &[b"stegegg spots".as_slice(), salt].concat()),
+        }
+    }
+}
+
+/// 32 byte key stretched from the password by scrypt with the cost of Kdf::Scrypt
+pub(crate) fn scrypt_key(key: &[u8], salt: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let params = scrypt::Params::new(SCRYPT_LOG_N, 8, 1, 32).map_err(|s| s.to_string())?;
+    let mut derived = Zeroizing::new(vec![0; 32]);
+    scrypt::scrypt(key, salt, &params, &mut derived).map_err(|s| s.to_string())?;
+    Ok(derived)
+}
+
+/// Reason of the failed extraction, so scanners can tell images without a message from broken ones
+#[derive(Debug)]
+pub enum ExtractError {
+    /// There is no message for this key (wrong key or clean image)
+    NoPayload(String),
+    /// Carrier or payload can't be decoded
+    Decode(String),
+    /// Writing the extracted message failed
+    Io(std::io::Error),
+    /// Other errors
+    Failed(String),
+}
+
+impl std::fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExtractError::NoPayload(s) | ExtractError::Decode(s) | ExtractError::Failed(s) => write!(f, "{}", s),
+            ExtractError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExtractError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ExtractError {
+    fn from(e: std::io::Error) -> ExtractError {
+        ExtractError::Io(e)
+    }
+}
+
+impl From<String> for ExtractError {
+    fn from(s: String) -> ExtractError {
+        ExtractError::Failed(s)
+    }
+}
+
+impl From<ExtractError> for String {
+    fn from(e: ExtractError) -> String {
+        e.to_string()
+    }
+}
+
+/// Read header and return message length
+pub fn read_header(image: &RgbImage, layout: SpotLayout, indices: &[u32]) -> Result<usize, ExtractError> {
+    read_header_limited(image, layout, indices, MAX_MSG_LEN)
+}
+
+/// Read header and return message length, which must not be above the limit. Header is validated before
+/// anything is allocated by its length.
+pub fn read_header_limited(image: &RgbImage, layout: SpotLayout, indices: &[u32], max_len: usize) -> Result<usize, ExtractError> {
+    carrier::read_header_limited(&ImageCarrier { image, layout }, indices, max_len)
+}
+
+/// Extract len bytes starting at byte offset from the given hidding spots
+pub fn extract_bytes(image: &RgbImage, layout: SpotLayout, indices: &[u32], offset: usize, len: usize) -> Vec<u8> {
+    carrier::read_bytes(&ImageCarrier { image, layout }, indices, offset, len)
+}
+
+/// Flags of the message in the upper half of the version byte. Two lowest flag bits hold the compress::Codec id,
+/// 0 for the uncompressed message. All four bits are taken, so a later format change gets a new format version,
+/// which the older readers refuse.
+pub const CODEC_MASK: u8 = 0x30;
+/// Message is sealed by aead with the key, the extraction decrypts it without an option
+pub const FLAG_ENCRYPTED: u8 = 0x40;
+/// Trailer is followed by the label of the message, its length (1 byte) and UTF-8 text covered by the checksum.
+/// The label stays outside of the aead envelope even for the encrypted messages, so --require-label is checked before
+/// the message is read and decrypted. It is hidden in the spots shuffled by the key, but not encrypted.
+pub const FLAG_LABEL: u8 = 0x80;
+
+/// Longest label of the message in bytes
+pub const MAX_LABEL_LEN: usize = 255;
+
+/// Trailer flags of the message compressed by the codec
+pub fn codec_flags(codec: compress::Codec) -> u8 {
+    codec.id() << 4
+}
+
+/// Flags, key derivation and label recorded in the trailer of the hidden message
+#[derive(Clone, Debug, Default)]
+pub struct TrailerParams {
+    /// Codec and FLAG_ENCRYPTED flags, FLAG_LABEL is set by the label
+    pub flags: u8,
+    /// Derivation of the spot order key, None when the spots don't depend on it (legacy, sequential, seed)
+    pub kdf: Option<Kdf>,
+    /// Label of the message, at most MAX_LABEL_LEN bytes. FLAG_LABEL is set by Trailer::new.
+    pub label: Option<String>,
+}
+
+impl TrailerParams {
+    /// Bytes of the label behind the trailer, its length and text, empty without the label
+    pub fn label_bytes(&self) -> Vec<u8> {
+        label_bytes(self.label.as_deref())
+    }
+}
+
+fn label_bytes(label: Option<&str>) -> Vec<u8> {
+    match label {
+        Some(l) => [&[l.len() as u8][..], l.as_bytes()].concat(),
+        None => Vec::new(),
+    }
+}
+
+/// Check the label is short enough to be stored
+pub fn check_label(label: &str) -> Result<(), String> {
+    match label.len() {
+        0 => Err("Label can't be empty.".to_string()),
+        n if n > MAX_LABEL_LEN => Err(format!("Label has {} bytes, the maximum is {}.", n, MAX_LABEL_LEN)),
+        _ => Ok(()),
+    }
+}
+
+/// Format version, flags, key derivation, checksum and label of the message
+#[derive(Clone, Debug)]
+pub struct Trailer {
+    /// Format version, FORMAT_VERSION for the new messages
+    pub version: u8,
+    /// Codec, FLAG_ENCRYPTED and FLAG_LABEL flags
+    pub flags: u8,
+    /// None when it wasn't recorded (version 1 or unkeyed spots)
+    pub kdf: Option<Kdf>,
+    /// CRC32 of the message and the label
+    pub crc32: u32,
+    /// Label of the message, when FLAG_LABEL is set
+    pub label: Option<String>,
+}
+
+impl Trailer {
+
+    /// Trailer of the current format version with the params and the checksum of the message and the label
+    pub fn new(params: TrailerParams, crc32: u32) -> Trailer {
+        let flags = if params.label.is_some() { params.flags | FLAG_LABEL } else { params.flags & !FLAG_LABEL };
+        Trailer { version: FORMAT_VERSION, flags, kdf: params.kdf, crc32, label: params.label }
+    }
+
+    /// Bytes of the trailer of the current format version, followed by the label
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = TRAILER_MAGIC.to_vec();
+        bytes.push(self.version | self.flags);
+        bytes.push(self.kdf.map_or(0, |k| k.id()));
+        bytes.extend_from_slice(&self.crc32.to_le_bytes());
+        bytes.extend_from_slice(&self.label_bytes());
+        bytes
+    }
+
+    /// Bytes of the label behind the trailer, which the checksum covers after the message
+    pub fn label_bytes(&self) -> Vec<u8> {
+        label_bytes(self.label.as_deref())
+    }
+
+    /// Check the message has the required label, before anything is extracted
+    pub fn require_label(&self, required: &str) -> Result<(), ExtractError> {
+        match &self.label {
+            Some(l) if l == required => Ok(()),
+            Some(l) => Err(ExtractError::Failed(format!("Message has the label '{}', not the required '{}'.", l, required))),
+            None => Err(ExtractError::Failed(format!("Message has no label, '{}' is required.", required))),
+        }
+    }
+
+    /// Codec of the compressed message, None when it is stored uncompressed
+    pub fn codec(&self) -> Option<compress::Codec> {
+        compress::Codec::from_id((self.flags & CODEC_MASK) >> 4)
+    }
+
+    /// Message is sealed by aead
+    pub fn encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+}
+
+/// Extraction limit of the stored message, which is larger than the message by the encryption overhead when encrypted
+pub fn stored_limit(max_len: usize) -> usize {
+    max_len.saturating_add(aead::OVERHEAD)
+}
+
+/// Extracted message as it was given for hiding, decrypted and decompressed as the trailer flags tell. The stored
+/// message was read with stored_limit, so the plain message is checked against max_len here.
+pub fn unpack_message(key: &[u8], mut msg: Zeroizing<Vec<u8>>, trailer: Option<&Trailer>, max_len: usize) -> Result<Zeroizing<Vec<u8>>, ExtractError> {
+    if trailer.is_some_and(|t| t.encrypted()) {
+        if key.is_empty() {
+            return Err(ExtractError::Failed("Message is encrypted, it can't be extracted without the key.".to_string()));
+        }
+        msg = Zeroizing::new(aead::open(key, &msg)?);
+    }
+    if msg.len() > max_len && trailer.and_then(|t| t.codec()).is_none() {
+        return Err(ExtractError::Failed(format!("Message has {}, which is above the extraction limit of {}.", human_size(msg.len()), human_size(max_len))));
+    }
+    compress::unpack(msg, trailer, max_len)
+}
+
+/// Read the trailer behind the message of msg_len bytes, NoPayload when there is none (wrong key)
+pub fn read_trailer(image: &RgbImage, layout: SpotLayout, indices: &[u32], msg_len: usize) -> Result<Trailer, ExtractError> {
+    carrier::read_trailer(&ImageCarrier { image, layout }, indices, msg_len)
+}
+
+/// Check the message of msg_len bytes against the checksum in its trailer before it is extracted
+pub fn verify_payload(image: &RgbImage, layout: SpotLayout, indices: &[u32], msg_len: usize) -> Result<Trailer, ExtractError> {
+    carrier::verify_payload(&ImageCarrier { image, layout }, indices, msg_len)
+}
+
+/// Check the message of the legacy image, None for the version 0 images which have no trailer
+pub fn verify_legacy_payload(image: &RgbImage, layout: SpotLayout, indices: &[u32], msg_len: usize) -> Result<Option<Trailer>, ExtractError> {
+    carrier::verify_legacy_payload(&ImageCarrier { image, layout }, indices, msg_len)
+}
+
+/// Extract data from image, the message with the trailer is verified by its checksum
+pub fn extract_data(image: &RgbImage, layout: SpotLayout, indices: &[u32]) -> Result<Vec<u8>, ExtractError> {
+    carrier::extract_message(&ImageCarrier { image, layout }, indices, MAX_MSG_LEN)
+}
+
+/// Extract data from image and write it into the writer in chunks, so the whole message is never held in memory.
+/// The message is verified by its checksum before the first byte is written, a failed write is ExtractError::Io.
+/// Returns number of written bytes.
+pub fn extract_to_writer<W: Write>(image: &RgbImage, layout: SpotLayout, indices: &[u32], writer: &mut W) -> Result<usize, ExtractError> {
+    carrier::extract_message_to_writer(&ImageCarrier { image, layout }, indices, writer)
+}
+
+/// Confidence of each extracted message byte. Bit confidence is the margin of the majority vote
+/// (1.0 when all samples agree) and the byte confidence is the lowest confidence of its bits.
+pub fn byte_confidence(image: &RgbImage, layout: SpotLayout, indices: &[u32], msg_len: usize) -> Vec<f32> {
+    (0..msg_len).map(|i| {
+        (0..8).map(|n| {
+            let (ones, total) = layout.read_votes(image, indices[(i + 3) * 8 + n]);
+            (ones as f32 * 2.0 - total as f32).abs() / total as f32
+        }).fold(1.0, f32::min)
+    }).collect()
+}
+
+/// Size in bytes in human readable binary units
+pub fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {} ({} bytes)", size, UNITS[unit], bytes)
+}
+
+/// Error of the message which doesn't fit into the capacity, both in bytes
+pub fn too_large(len: usize, capacity: usize) -> String {
+    let limit = if len > MAX_MSG_LEN { " Messages are limited to 16 MiB by the 3 bytes length header of the format." } else { "" };
+    format!("Input message is too large.\nMessage has {}, the image holds only {}. It would fill {:.0}% of the capacity.{}",
+        human_size(len), human_size(capacity), len as f64 * 100.0 / capacity.max(1) as f64, limit)
+}
+
+/// Hide data into the random pixels and random colors. Returns number of used hidding spots.
+pub fn hide_data(data: &[u8], image: &mut RgbImage, layout: SpotLayout, indices: &[u32]) -> Result<usize, EmbedError> {
+    carrier::write_bytes(data, &mut ImageCarrier { image, layout }, indices)
+}
+
+/// Hide message read from the reader into the image. When the length is not known (e.g. pipe) the message
+/// is streamed first and the header with its length is written at the end. Returns number of used hidding spots.
+pub fn hide_from_reader<R: Read + ?Sized>(reader: &mut R, len: Option<usize>, image: &mut RgbImage, layout: SpotLayout, indices: &[u32]) -> Result<usize, EmbedError> {
+    carrier::hide_message(reader, len, &mut ImageCarrier { image, layout }, indices, TrailerParams::default())
+}
+
+/// Bits of the data from the reader for the bit source API. Read error ends the bits and is kept for take_error.
+pub struct ReadBits<R: Read> {
+    bits: raw::ByteBits<ReadBytes<R>>,
+}
+
+//bytes of the reader, stops at the first error
+struct ReadBytes<R: Read> {
+    bytes: std::io::Bytes<std::io::BufReader<R>>,
+    error: Option<std::io::Error>,
+}
+
+impl<R: Read> Iterator for ReadBytes<R> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        match self.bytes.next()? {
+            Ok(b) => Some(b),
+            Err(e) => { self.error = Some(e); None },
+        }
+    }
+}
+
+impl<R: Read> ReadBits<R> {
+    /// Bits of the bytes of the reader, LSB first
+    pub fn new(reader: R) -> ReadBits<R> {
+        ReadBits { bits: raw::ByteBits::new(ReadBytes { bytes: std::io::BufReader::new(reader).bytes(), error: None }) }
+    }
+
+    /// Error which ended the bits, if any
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.bits.bytes_mut().error.take()
+    }
+}
+
+impl<R: Read> raw::BitSource for ReadBits<R> {
+    fn next_bit(&mut self) -> Option<u8> {
+        self.bits.next_bit()
+    }
+}
+
+/// Hide the bits from the source behind the header, followed by the trailer, and write the header when the source
+/// is exhausted. The last incomplete byte is padded with zeros. Returns number of used hidding spots.
+pub fn hide_bits<B: raw::BitSource + ?Sized>(bits: &mut B, image: &mut RgbImage, layout: SpotLayout, indices: &[u32]) -> Result<usize, EmbedError> {
+
+    if indices.len() < (3 + TRAILER_LEN) * 8 {
+        return Err(EmbedError::TooSmall);
+    }
+    let (header, body) = indices.split_at(3 * 8);
+    let limit = message_capacity(indices.len()) * 8;
+
+    //checksum of the trailer is computed over the whole bytes as they are written
+    let mut hasher = crc32fast::Hasher::new();
+    let mut byte = 0;
+    let mut used = 0;
+    let mut exhausted = false;
+    loop {
+        //after the source is exhausted the last byte is padded with zeros
+        let bit = match exhausted { true => None, false => bits.next_bit() };
+        exhausted = bit.is_none();
+        if exhausted && used % 8 == 0 {
+            break;
+        }
+        let bit = bit.unwrap_or(0) & 1;
+        if used == limit {
+            return Err(EmbedError::Overflow { capacity: limit / 8 });
+        }
+        layout.write_bit(image, body[used], bit);
+        byte |= bit << (used % 8);
+        used += 1;
+        if used % 8 == 0 {
+            hasher.update(&[byte]);
+            byte = 0;
+        }
+    }
+
+    let msg_len = used / 8;
+    let trailer = Trailer::new(TrailerParams::default(), hasher.finalize());
+    hide_data(&trailer.to_bytes(), image, layout, &body[used..])?;
+    hide_data(&[(msg_len & 0xff) as u8, ((msg_len >> 8) & 0xff) as u8, ((msg_len >> 16) & 0xff) as u8], image, layout, header)?;
+    Ok(used + (3 + TRAILER_LEN) * 8)
+}
+
+/// Flip the bits of the unused hidding spots (in the key dependent order) to restore original per-channel histogram of the cover.
+/// Replacing the bit of the plane k only moves values between v and v ^ (1 << k), so the totals of the values sharing the bits
+/// above the depth stay the same and flipping the unused spots of any plane is enough.
+/// Returns number of samples which still differ from the original histogram.
+pub fn restore_histogram(cover: &RgbImage, image: &mut RgbImage, layout: SpotLayout, indices: &[u32], used: usize) -> usize {
+
+    let width = image.width();
+
+    //per-channel histograms of the cover and of the modified image
+    let mut cover_hist = [[0i64; 256]; 3];
+    let mut image_hist = [[0i64; 256]; 3];
+    for (cp, ip) in cover.pixels().zip(image.pixels()) {
+        for c in 0..3 {
+            cover_hist[c][cp[c] as usize] += 1;
+            image_hist[c][ip[c] as usize] += 1;
+        }
+    }
+
+    for &spot_idx in &indices[used..] {
+        let (x, y, colors, plane) = layout.position(spot_idx, width);
+        let pixel = image.get_pixel_mut(x, y);
+
+        for color_offset in colors {
+            let v = pixel[color_offset] as usize;
+            let p = v ^ (1 << plane);
+
+            //move one sample to the other value of the pair, if this value is in excess and its pair is missing
+            if image_hist[color_offset][v] > cover_hist[color_offset][v] && image_hist[color_offset][p] < cover_hist[color_offset][p] {
+                pixel[color_offset] = p as u8;
+                image_hist[color_offset][v] -= 1;
+                image_hist[color_offset][p] += 1;
+            }
+        }
+    }
+
+    //half of the absolute difference is the number of samples in wrong value
+    let mut diff = 0;
+    for c in 0..3 {
+        for v in 0..256 {
+            diff += (image_hist[c][v] - cover_hist[c][v]).unsigned_abs() as usize;
+        }
+    }
+    diff / 2
+}
+
+/// Turn the LSB replacement into LSB matching. Samples whose LSB was flipped by the embedding are instead incremented
+/// or decremented from the cover value in the random direction (toward the valid side at 0 and 255). The LSB carries
+/// the same bit, so the extraction is unchanged, but the pairs of values signature of the replacement is gone.
+/// Only the LSB plane may carry bits. Returns number of the adjusted samples.
+pub fn lsb_matching(cover: &RgbImage, image: &mut RgbImage, prng_state: &mut PrngState) -> usize {
+    let mut adjusted = 0;
+    for (cp, ip) in cover.pixels().zip(image.pixels_mut()) {
+        for c in 0..3 {
+            if cp[c] ^ ip[c] != 1 {
+                continue;
+            }
+            ip[c] = match cp[c] {
+                0 => 1,
+                255 => 254,
+                v => if xoshiro256pp(prng_state) & 1 == 0 { v - 1 } else { v + 1 },
+            };
+            adjusted += 1;
+        }
+    }
+    adjusted
+}
+
+/// Number of message bytes which fit into the given number of hidding spots, less the header and the trailer
+pub fn message_capacity(spots: usize) -> usize {
+    (spots / 8).saturating_sub(3 + TRAILER_LEN).min(MAX_MSG_LEN)
+}
+
+/// Number of message bytes which fit into the carrier of spots hidding spots with the layout. The salt (and the depth
+/// descriptor) take the spots before the layout's first spot. Every capacity reported or checked is computed here.
+pub fn capacity_for(layout: SpotLayout, spots: u32) -> usize {
+    message_capacity(spots.saturating_sub(layout.first_spot()) as usize)
+}
+
+/// Number of message bytes which fit into the image of given size with the default layout
+pub fn capacity(width: u32, height: u32) -> usize {
+    let layout = SpotLayout { redundant: false, depth: DEFAULT_DEPTH };
+    layout.spots(width, height).map_or(0, |spots| capacity_for(layout, spots))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LSB: SpotLayout = SpotLayout { redundant: false, depth: DEFAULT_DEPTH };
+
+    //image with distinct pixel values, so the written bits can't match it by chance
+    fn noise(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 7 + y) as u8, (x * 13 + y * 3) as u8, (x + y * 11) as u8]))
+    }
+
+    #[test]
+    fn salt_round_trip() {
+        for layout in [LSB, SpotLayout { redundant: true, depth: DEFAULT_DEPTH }, SpotLayout { redundant: false, depth: [2, 3, 1] }] {
+            let mut image = noise(16, 16);
+            let salt: Vec<u8> = (0..SALT_LEN as u8).map(|b| b.wrapping_mul(37)).collect();
+            write_salt(&mut image, layout, &salt);
+            assert_eq!(read_salt(&image, layout), salt);
+        }
+    }
+
+    #[test]
+    fn salt_is_in_raster_order_lsbs() {
+        let (cover, mut image) = (noise(16, 16), noise(16, 16));
+        write_salt(&mut image, LSB, &[0xff; SALT_LEN]);
+        assert!((0..RESERVED_SPOTS).all(|i| LSB.read_bit(&image, i) == 1));
+        assert!((RESERVED_SPOTS..16 * 16 * 3).all(|i| LSB.read_bit(&image, i) == LSB.read_bit(&cover, i)));
+    }
+
+    #[test]
+    fn spots_skip_the_salt() {
+        let image = noise(16, 16);
+        let mut spots = hidding_spots(&image, LSB, RESERVED_SPOTS, &mut init_prng(b"key", b"salt", &[])).unwrap();
+        assert_eq!(spots.len() as u32, 16 * 16 * 3 - RESERVED_SPOTS);
+        assert_ne!(spots[..16], (RESERVED_SPOTS..RESERVED_SPOTS + 16).collect::<Vec<_>>());
+        spots.sort_unstable();
+        assert_eq!(spots, (RESERVED_SPOTS..16 * 16 * 3).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn spot_order_depends_on_key_salt_and_context() {
+        let order = |key: &[u8], salt: &[u8], context: &[u8]| shuffled_spots(1000, RESERVED_SPOTS, &mut init_prng(key, salt, context));
+        let base = order(b"key", b"salt", b"");
+        assert_eq!(base, order(b"key", b"salt", b""));
+        assert_ne!(base, order(b"other", b"salt", b""));
+        assert_ne!(base, order(b"key", b"other", b""));
+        assert_ne!(base, order(b"key", b"salt", b"context"));
+    }
+
+    #[test]
+    fn spot_positions_follow_the_depth() {
+        let layout = SpotLayout { redundant: false, depth: [2, 0, 1] };
+        assert_eq!(layout.spots_per_pixel(), 3);
+        assert_eq!(layout.position(0, 10), (0, 0, 0..1, 0));
+        assert_eq!(layout.position(1, 10), (0, 0, 0..1, 1));
+        assert_eq!(layout.position(2, 10), (0, 0, 2..3, 0));
+        assert_eq!(layout.position(3 * 11, 10), (1, 1, 0..1, 0));
+        assert_eq!(SpotLayout { redundant: true, depth: DEFAULT_DEPTH }.position(12, 10), (2, 1, 0..3, 0));
+    }
+
+    #[test]
+    fn redundant_spot_is_majority_voted() {
+        let layout = SpotLayout { redundant: true, depth: DEFAULT_DEPTH };
+        let mut image = noise(4, 4);
+        layout.write_bit(&mut image, 5, 1);
+        image.get_pixel_mut(1, 1)[2] &= 0xfe;
+        assert_eq!(layout.read_votes(&image, 5), (2, 3));
+        assert_eq!(layout.read_bit(&image, 5), 1);
+    }
+
+    #[test]
+    fn too_many_spots_are_refused() {
+        assert!(LSB.spots(65536, 65536).is_err());
+        assert_eq!(LSB.spots(100, 100), Ok(30000));
+    }
+
+    #[test]
+    fn depth_descriptor_round_trip() {
+        let mut image = noise(32, 32);
+        write_depth(&mut image, b"key", b"salt", b"", [2, 0, 3]);
+        assert_eq!(read_depth(&image, b"key", b"salt", b""), Some([2, 0, 3]));
+    }
+
+    #[test]
+    fn depth_descriptor_is_keyed() {
+        let mut image = noise(32, 32);
+        write_depth(&mut image, b"key", b"salt", b"", [2, 0, 3]);
+        assert_eq!(read_depth(&image, b"other", b"salt", b""), None);
+        assert_eq!(read_depth(&image, b"key", b"other", b""), None);
+        assert_eq!(read_depth(&image, b"key", b"salt", b"context"), None);
+    }
+
+    #[test]
+    fn invalid_depth_descriptor_is_refused() {
+        //the default depth images have data after the salt, the check bytes tell it apart
+        let image = noise(32, 32);
+        assert_eq!(read_depth(&image, b"key", b"salt", b""), None);
+
+        for depth in [[0, 0, 0], [9, 1, 1]] {
+            let mut image = noise(32, 32);
+            write_depth(&mut image, b"key", b"salt", b"", depth);
+            assert_eq!(read_depth(&image, b"key", b"salt", b""), None);
+        }
+
+        //image too small for the descriptor
+        assert_eq!(read_depth(&noise(4, 4), b"key", b"salt", b""), None);
+    }
+
+    //copy of the cover with the message hidden into its shuffled spots
+    fn stego(cover: &RgbImage, msg: &[u8]) -> (RgbImage, Vec<u32>, usize) {
+        let mut image = cover.clone();
+        let indices = hidding_spots(&image, LSB, RESERVED_SPOTS, &mut init_prng(b"key", b"salt", &[])).unwrap();
+        let used = hide_from_reader(&mut &msg[..], Some(msg.len()), &mut image, LSB, &indices).unwrap();
+        (image, indices, used)
+    }
+
+    fn saturated(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| match (x + y) % 4 {
+            0 => image::Rgb([0, 255, 0]),
+            1 => image::Rgb([255, 0, 255]),
+            _ => image::Rgb([(x * 7 + y) as u8, (x * 13 + y * 3) as u8, (x + y * 11) as u8]),
+        })
+    }
+
+    #[test]
+    fn lsb_matching_keeps_the_bits_and_doesnt_wrap() {
+        let cover = saturated(32, 32);
+        let msg: Vec<u8> = (0..200u32).map(|i| (i * 31) as u8).collect();
+        let (mut image, indices, _) = stego(&cover, &msg);
+        let flipped = cover.as_raw().iter().zip(image.as_raw()).filter(|(c, i)| c != i).count();
+
+        assert_eq!(lsb_matching(&cover, &mut image, &mut init_prng(b"seed", &[], &[])), flipped);
+        assert_eq!(extract_data(&image, LSB, &indices).unwrap(), msg);
+        for (&c, &i) in cover.as_raw().iter().zip(image.as_raw()) {
+            assert!((c as i16 - i as i16).abs() <= 1, "{} became {}", c, i);
+        }
+        //both directions are used away from the ends
+        assert!(cover.as_raw().iter().zip(image.as_raw()).any(|(c, i)| *c != 0 && i < c));
+        assert!(cover.as_raw().iter().zip(image.as_raw()).any(|(c, i)| *c != 255 && i > c));
+    }
+
+    //number of samples in the wrong value of the per-channel histograms
+    fn histogram_diff(cover: &RgbImage, image: &RgbImage) -> usize {
+        let mut diff = [[0i64; 256]; 3];
+        for (cp, ip) in cover.pixels().zip(image.pixels()) {
+            for c in 0..3 {
+                diff[c][cp[c] as usize] += 1;
+                diff[c][ip[c] as usize] -= 1;
+            }
+        }
+        diff.iter().flatten().map(|d| d.unsigned_abs() as usize).sum::<usize>() / 2
+    }
+
+    #[test]
+    fn restore_histogram_keeps_the_payload() {
+        let cover = saturated(32, 32);
+        let msg: Vec<u8> = (0..100u32).map(|i| (i * 31) as u8).collect();
+        let (mut image, indices, used) = stego(&cover, &msg);
+        let before = histogram_diff(&cover, &image);
+        assert!(before > 0);
+
+        let left = restore_histogram(&cover, &mut image, LSB, &indices, used);
+        assert_eq!(left, histogram_diff(&cover, &image));
+        assert!(left < before, "{} samples off, {} before", left, before);
+        assert_eq!(extract_data(&image, LSB, &indices).unwrap(), msg);
+    }
+
+    #[test]
+    fn capacity_leaves_out_the_reserved_spots() {
+        assert_eq!(capacity(16, 16), (16 * 16 * 3 - RESERVED_SPOTS as usize) / 8 - 3 - TRAILER_LEN);
+        assert_eq!(capacity(4, 4), 0);
+    }
+}
@@ -0,0 +1,235 @@
+use image::RgbImage;
+
+//Steganalysis detectors of LSB replacement, run on the stego image to judge how detectable the message is.
+//RS analysis and sample pair analysis estimate the embedding rate (changed LSBs per sample times two),
+//the chi-square attack gives the probability that the pairs of values were equalized by the embedding.
+
+/// Results of the detectors and their composite score in 0..1, about 0 for clean images
+pub struct Detectability {
+    /// Embedding rate estimated by the RS analysis
+    pub rs: f64,
+    /// Embedding rate estimated by the sample pair analysis
+    pub spa: f64,
+    /// Probability of the equalized pairs of values given by the chi-square attack
+    pub chi_square: f64,
+    /// Composite score
+    pub score: f64,
+}
+
+//smaller root of a*x^2 + b*x + c = 0, -b/2a when there is no real root
+fn smaller_root(a: f64, b: f64, c: f64) -> f64 {
+    if a.abs() < 1e-12 {
+        return if b.abs() < 1e-12 { 0.0 } else { -c / b };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return -b / (2.0 * a);
+    }
+    let (r1, r2) = ((-b + discriminant.sqrt()) / (2.0 * a), (-b - discriminant.sqrt()) / (2.0 * a));
+    if r1.abs() <= r2.abs() { r1 } else { r2 }
+}
+
+//samples of one channel by rows
+fn channel_rows(image: &RgbImage, c: usize) -> impl Iterator<Item = Vec<u8>> + '_ {
+    image.rows().map(move |row| row.map(|p| p[c]).collect())
+}
+
+//sample pair analysis (Dumitrescu, Wu, Wang) on the horizontally adjacent samples
+fn sample_pair_analysis(image: &RgbImage) -> f64 {
+    let (mut x, mut y, mut z, mut w, mut pairs) = (0u64, 0u64, 0u64, 0u64, 0u64);
+    for c in 0..3 {
+        for row in channel_rows(image, c) {
+            for pair in row.windows(2) {
+                let (u, v) = (pair[0], pair[1]);
+                pairs += 1;
+                if u == v {
+                    z += 1;
+                } else if (v % 2 == 0) == (u < v) {
+                    x += 1;
+                } else {
+                    y += 1;
+                    if u >> 1 == v >> 1 {
+                        w += 1;
+                    }
+                }
+            }
+        }
+    }
+    smaller_root(0.5 * (w + z) as f64, 2.0 * x as f64 - pairs as f64, y as f64 - x as f64)
+}
+
+//smoothness of the group, sum of differences of the neighbouring samples
+fn smoothness(group: &[i32; 4]) -> i32 {
+    group.windows(2).map(|w| (w[1] - w[0]).abs()).sum()
+}
+
+//regular and singular groups for the mask, as differences R - S relative to the number of groups.
+//Samples are flipped by F1 (2n <-> 2n+1) for the positive mask and by F-1 (2n <-> 2n-1) for the negative one.
+fn regular_minus_singular(image: &RgbImage, invert_lsb: bool) -> (f64, f64) {
+    const MASK: [bool; 4] = [false, true, true, false];
+    let (mut positive, mut negative, mut groups) = (0i64, 0i64, 0i64);
+    for c in 0..3 {
+        for row in channel_rows(image, c) {
+            for chunk in row.chunks_exact(4) {
+                let group: [i32; 4] = std::array::from_fn(|i| (chunk[i] ^ invert_lsb as u8) as i32);
+                let f = smoothness(&group);
+                let f1 = smoothness(&std::array::from_fn(|i| if MASK[i] { group[i] ^ 1 } else { group[i] }));
+                let f_1 = smoothness(&std::array::from_fn(|i| if MASK[i] { ((group[i] + 1) ^ 1) - 1 } else { group[i] }));
+                positive += (f1 > f) as i64 - (f1 < f) as i64;
+                negative += (f_1 > f) as i64 - (f_1 < f) as i64;
+                groups += 1;
+            }
+        }
+    }
+    let groups = groups.max(1) as f64;
+    (positive as f64 / groups, negative as f64 / groups)
+}
+
+//RS analysis (Fridrich, Goljan, Du) estimating the embedding rate
+fn rs_analysis(image: &RgbImage) -> f64 {
+    let (d0, dn0) = regular_minus_singular(image, false);
+    let (d1, dn1) = regular_minus_singular(image, true);
+    let z = smaller_root(2.0 * (d1 + d0), dn0 - dn1 - d1 - 3.0 * d0, d0 - dn0);
+    if (z - 0.5).abs() < 1e-12 { 1.0 } else { z / (z - 0.5) }
+}
+
+//logarithm of the gamma function (Lanczos approximation)
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [76.18009172947146, -86.50532032941677, 24.01409824083091, -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS.iter().enumerate().fold(1.000000000190015, |s, (i, c)| s + c / (x + 1.0 + i as f64));
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+//regularized upper incomplete gamma function Q(a, x), by the series or the continued fraction
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let prefix = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        for _ in 0..1000 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-12 {
+                break;
+            }
+        }
+        1.0 - sum * prefix
+    } else {
+        let (mut b, mut c, mut d) = (x + 1.0 - a, 1.0 / 1e-300, 1.0 / (x + 1.0 - a));
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            d = if d.abs() < 1e-300 { 1.0 / 1e-300 } else { 1.0 / d };
+            c = b + an / c;
+            if c.abs() < 1e-300 {
+                c = 1e-300;
+            }
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-12 {
+                break;
+            }
+        }
+        prefix * h
+    }
+}
+
+//chi-square attack (Westfeld, Pfitzmann). Probability that the histogram pairs of values 2k and 2k+1 are equalized.
+fn chi_square_attack(image: &RgbImage) -> f64 {
+    let mut histogram = [0u64; 256];
+    for &v in image.as_raw() {
+        histogram[v as usize] += 1;
+    }
+
+    let (mut chi, mut categories) = (0.0, 0);
+    for pair in histogram.chunks_exact(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected > 4.0 {
+            chi += (pair[0] as f64 - expected).powi(2) / expected;
+            categories += 1;
+        }
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    gamma_q((categories - 1) as f64 / 2.0, chi / 2.0)
+}
+
+//estimated embedding rate above which the image likely carries hidden data. Natural images score up to about 0.15.
+const SUSPICIOUS_RATE: f64 = 0.25;
+
+impl Detectability {
+
+    /// LSB statistics are abnormal, both rate estimators agree or the pairs of values are equalized
+    pub fn suspicious(&self) -> bool {
+        self.rs.min(self.spa) > SUSPICIOUS_RATE || self.chi_square > 0.95
+    }
+}
+
+/// Run all detectors on the image
+pub fn detectability(image: &RgbImage) -> Detectability {
+    let rs = rs_analysis(image).clamp(0.0, 1.0);
+    let spa = sample_pair_analysis(image).clamp(0.0, 1.0);
+    let chi_square = chi_square_attack(image);
+    Detectability { rs, spa, chi_square, score: (rs + spa + chi_square) / 3.0 }
+}
+
+/// Embedding cost of each pixel in raster order, from 1 in flat areas where the detectors notice the LSB changes
+/// to about 0 in the textured ones. The residual of a sample is its difference from the mean of its 4 neighbours,
+/// the cost is 1 / (1 + mean absolute residual of the 3x3 window over all channels).
+pub fn embedding_costs(image: &RgbImage) -> Vec<f32> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let raw = image.as_raw();
+    let sample = |x: usize, y: usize, c: usize| raw[(y * width + x) * 3 + c] as f32;
+
+    let mut residuals = vec![0.0f32; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            //neighbours left of the first column and above the first row wrap around to the large values
+            let neighbours = [(x.wrapping_sub(1), y), (x + 1, y), (x, y.wrapping_sub(1)), (x, y + 1)];
+            let inside = || neighbours.iter().filter(|&&(nx, ny)| nx < width && ny < height);
+            let n = inside().count();
+            if n > 0 {
+                residuals[y * width + x] = (0..3).map(|c| {
+                    (sample(x, y, c) - inside().map(|&(nx, ny)| sample(nx, ny, c)).sum::<f32>() / n as f32).abs()
+                }).sum::<f32>() / 3.0;
+            }
+        }
+    }
+
+    let residuals = &residuals;
+    let mut costs = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (xs, ys) = (x.saturating_sub(1)..(x + 2).min(width), y.saturating_sub(1)..(y + 2).min(height));
+            let n = xs.len() * ys.len();
+            let sum: f32 = ys.flat_map(|wy| xs.clone().map(move |wx| residuals[wy * width + wx])).sum();
+            costs.push(1.0 / (1.0 + sum / n as f32));
+        }
+    }
+    costs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_areas_cost_the_most() {
+        let flat = RgbImage::from_pixel(8, 8, image::Rgb([100, 100, 100]));
+        assert!(embedding_costs(&flat).iter().all(|&c| c == 1.0));
+
+        //left half flat, right half a checkerboard
+        let image = RgbImage::from_fn(16, 8, |x, y| image::Rgb([if x >= 8 && (x + y) % 2 == 0 { 200 } else { 100 }; 3]));
+        let costs = embedding_costs(&image);
+        assert_eq!(costs.len(), 16 * 8);
+        assert_eq!(costs[4 * 16 + 2], 1.0);
+        assert!(costs[4 * 16 + 12] < 0.05);
+    }
+}
@@ -0,0 +1,449 @@
+//! Crop-resilient tiled embedding. The image is divided into square tiles and each tile carries a mini-header
+//! and one fragment of the message. Fragments are replicated over all tiles, so the message survives cropping
+//! as long as each fragment is left in at least one complete tile. Tile record is:
+//! magic (2 bytes), fragment index (2), fragment count (2), message length (3), CRC32 of the preceding fields and data (4), data.
+//! The tiles are also found after the changes of the geometry which keep the samples: rotations by quarter turns,
+//! mirroring and the nearest neighbor upscaling by an integer factor (screenshots of the zoomed image). Resampling
+//! changes the LSBs of all samples, so the other rotations and scales destroy the message whatever the sync.
+
+use std::borrow::Cow;
+use std::fmt;
+use image::RgbImage;
+use image::imageops::{flip_horizontal_in_place, rotate180, rotate270, rotate90};
+use crate::{get_bit, shuffle, ExtractError, PrngState};
+
+//magic value at the start of each tile
+const TILE_MAGIC: [u8; 2] = *b"SG";
+
+//size of the tile mini-header
+const TILE_HEADER: usize = 13;
+
+//largest upscaling factor tried by the extraction
+const MAX_SCALE: u32 = 4;
+
+/// Lossless change of the image geometry undone by the tiled extraction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    /// Clockwise quarter turns of the image
+    pub quarter_turns: u8,
+    /// Image was mirrored horizontally before the rotation
+    pub mirrored: bool,
+    /// Nearest neighbor upscaling factor
+    pub scale: u32,
+}
+
+impl Geometry {
+    /// Image as it was hidden into
+    pub const ORIGINAL: Geometry = Geometry { quarter_turns: 0, mirrored: false, scale: 1 };
+
+    //all changes tried by the extraction, the original geometry first and the larger scales last
+    fn all() -> impl Iterator<Item = Geometry> {
+        (1..=MAX_SCALE).flat_map(|scale| [false, true].into_iter()
+            .flat_map(move |mirrored| (0..4).map(move |quarter_turns| Geometry { quarter_turns, mirrored, scale })))
+    }
+
+    //image with the change undone
+    fn undo<'a>(&self, image: &'a RgbImage) -> Cow<'a, RgbImage> {
+        if *self == Geometry::ORIGINAL {
+            return Cow::Borrowed(image);
+        }
+        let s = self.scale;
+        let image = RgbImage::from_fn(image.width() / s, image.height() / s, |x, y| *image.get_pixel(x * s, y * s));
+        let mut image = match self.quarter_turns {
+            1 => rotate270(&image),
+            2 => rotate180(&image),
+            3 => rotate90(&image),
+            _ => image,
+        };
+        if self.mirrored {
+            flip_horizontal_in_place(&mut image);
+        }
+        Cow::Owned(image)
+    }
+}
+
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut changes = Vec::new();
+        if self.mirrored {
+            changes.push("mirrored".to_string());
+        }
+        if self.quarter_turns > 0 {
+            changes.push(format!("rotated by {} degrees", self.quarter_turns as u32 * 90));
+        }
+        if self.scale > 1 {
+            changes.push(format!("upscaled {}x", self.scale));
+        }
+        if changes.is_empty() {
+            changes.push("unchanged".to_string());
+        }
+        write!(f, "{}", changes.join(", "))
+    }
+}
+
+//tile geometry and the key dependent order of the spots inside each tile
+struct TileGrid {
+    size: u32,
+    indices: Vec<u32>,
+}
+
+impl TileGrid {
+
+    fn new(size: u32, prng_state: &mut PrngState) -> TileGrid {
+        let mut indices: Vec<u32> = (0..size * size * 3).collect();
+        shuffle(&mut indices, prng_state);
+        TileGrid { size, indices }
+    }
+
+    //number of bytes in one tile record
+    fn record_len(&self) -> usize {
+        self.indices.len() / 8
+    }
+
+    //sample position of the nth spot of the tile with the top-left corner at (ox, oy)
+    fn position(&self, ox: u32, oy: u32, n: usize) -> (u32, u32, usize) {
+        let s = self.indices[n];
+        let p = s / 3;
+        (ox + p % self.size, oy + p / self.size, (s % 3) as usize)
+    }
+
+    //read len bytes of the tile record
+    fn read(&self, image: &RgbImage, ox: u32, oy: u32, len: usize) -> Vec<u8> {
+        let mut data = vec![0; len];
+        for (i, b) in data.iter_mut().enumerate() {
+            for n in 0..8 {
+                let (x, y, c) = self.position(ox, oy, i * 8 + n);
+                *b |= (image.get_pixel(x, y)[c] & 1) << n;
+            }
+        }
+        data
+    }
+
+    //write the tile record
+    fn write(&self, image: &mut RgbImage, ox: u32, oy: u32, data: &[u8]) {
+        for (i, b) in data.iter().enumerate() {
+            for n in 0..8 {
+                let (x, y, c) = self.position(ox, oy, i * 8 + n);
+                let pixel = image.get_pixel_mut(x, y);
+                pixel[c] = pixel[c] & 0xfe | get_bit(*b, n as u8);
+            }
+        }
+    }
+
+    //grid offset with the most valid records and then the most tiles starting with the magic among the first few
+    //ones. Returns whether a valid record was found, the magic alone matches by chance now and then.
+    fn find_offset(&self, image: &RgbImage) -> Option<(bool, u32, u32)> {
+        let mut best: Option<((usize, usize), u32, u32)> = None;
+        for dy in 0..self.size {
+            for dx in 0..self.size {
+                let marked: Vec<(u32, u32)> = self.tiles(image, dx, dy).into_iter().take(4)
+                    .filter(|&(ox, oy)| self.read(image, ox, oy, 2) == TILE_MAGIC)
+                    .collect();
+                let valid = marked.iter().filter(|&&(ox, oy)| parse_record(&self.read(image, ox, oy, self.record_len())).is_some()).count();
+                if !marked.is_empty() && best.is_none_or(|b| (valid, marked.len()) > b.0) {
+                    best = Some(((valid, marked.len()), dx, dy));
+                }
+            }
+        }
+        best.map(|((valid, _), dx, dy)| (valid > 0, dx, dy))
+    }
+
+    //top-left corners of all complete tiles for the grid shifted by (dx, dy)
+    fn tiles(&self, image: &RgbImage, dx: u32, dy: u32) -> Vec<(u32, u32)> {
+        let (width, height) = image.dimensions();
+        let mut tiles = Vec::new();
+        let mut oy = dy;
+        while oy + self.size <= height {
+            let mut ox = dx;
+            while ox + self.size <= width {
+                tiles.push((ox, oy));
+                ox += self.size;
+            }
+            oy += self.size;
+        }
+        tiles
+    }
+}
+
+//parsed tile record
+struct TileRecord {
+    index: usize,
+    count: usize,
+    msg_len: usize,
+    data: Vec<u8>,
+}
+
+//parse and verify the tile record
+fn parse_record(record: &[u8]) -> Option<TileRecord> {
+    if record[0..2] != TILE_MAGIC {
+        return None;
+    }
+
+    let crc = u32::from_le_bytes(record[9..13].try_into().unwrap());
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&record[2..9]);
+    hasher.update(&record[TILE_HEADER..]);
+    if hasher.finalize() != crc {
+        return None;
+    }
+
+    Some(TileRecord {
+        index: u16::from_le_bytes([record[2], record[3]]) as usize,
+        count: u16::from_le_bytes([record[4], record[5]]) as usize,
+        msg_len: record[6] as usize | (record[7] as usize) << 8 | (record[8] as usize) << 16,
+        data: record[TILE_HEADER..].to_vec(),
+    })
+}
+
+/// Number of message bytes which fit into the tiles of the image of given size
+pub fn tiled_capacity(width: u32, height: u32, tile_size: u32) -> usize {
+    let tiles = (width / tile_size) as usize * (height / tile_size) as usize;
+    let fragment_len = (tile_size as usize * tile_size as usize * 3 / 8).saturating_sub(TILE_HEADER);
+    (tiles.min(u16::MAX as usize) * fragment_len).min(crate::MAX_MSG_LEN)
+}
+
+/// Hide the message into the tiles of the image. Returns the number of copies of each fragment.
+pub fn hide_tiled(data: &[u8], image: &mut RgbImage, tile_size: u32, prng_state: &mut PrngState) -> Result<usize, String> {
+
+    let grid = TileGrid::new(tile_size, prng_state);
+    let fragment_len = grid.record_len().saturating_sub(TILE_HEADER);
+    if fragment_len == 0 {
+        return Err(format!("Tile size {} is too small.", tile_size));
+    }
+
+    let tiles = grid.tiles(image, 0, 0);
+    let count = data.len().div_ceil(fragment_len).max(1);
+    if count > tiles.len() || count > u16::MAX as usize || data.len() > crate::MAX_MSG_LEN {
+        return Err(crate::too_large(data.len(), (tiles.len().min(u16::MAX as usize) * fragment_len).min(crate::MAX_MSG_LEN)));
+    }
+
+    //fragments are assigned to the tiles round-robin
+    for (i, &(ox, oy)) in tiles.iter().enumerate() {
+        let index = i % count;
+        let mut record = Vec::with_capacity(grid.record_len());
+        record.extend_from_slice(&TILE_MAGIC);
+        record.extend_from_slice(&(index as u16).to_le_bytes());
+        record.extend_from_slice(&(count as u16).to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes()[..3]);
+
+        let mut fragment = data[(index * fragment_len).min(data.len())..((index + 1) * fragment_len).min(data.len())].to_vec();
+        fragment.resize(fragment_len, 0);
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&record[2..9]);
+        hasher.update(&fragment);
+        record.extend_from_slice(&hasher.finalize().to_le_bytes());
+        record.extend_from_slice(&fragment);
+        grid.write(image, ox, oy, &record);
+    }
+    Ok(tiles.len() / count)
+}
+
+/// Statistics of the tiled extraction
+pub struct TileStats {
+    /// Number of the tiles in the image
+    pub tiles: usize,
+    /// Tiles with the valid record
+    pub valid_tiles: usize,
+    /// Number of the message fragments
+    pub fragments: usize,
+    /// Fragments recovered by the majority vote of the damaged replicas
+    pub voted_fragments: usize,
+    /// Fragments recovered neither from a valid tile nor by the vote
+    pub lost_fragments: Vec<usize>,
+    /// Change of the geometry the tiles were found after
+    pub geometry: Geometry,
+}
+
+//bitwise majority of the replicas
+fn majority(replicas: &[Vec<u8>]) -> Vec<u8> {
+    let len = replicas[0].len();
+    (0..len).map(|i| {
+        (0..8).fold(0u8, |b, n| {
+            let ones = replicas.iter().filter(|r| get_bit(r[i], n) == 1).count();
+            b | (((ones * 2 > replicas.len()) as u8) << n)
+        })
+    }).collect()
+}
+
+/// Extract the message from the (possibly cropped) tiled image. The grid offset is found by searching
+/// for the tile magic, in the image as it is and then with the lossless changes of the geometry undone.
+/// Valid replicas of all fragments are collected, when none of the replicas of a fragment is valid,
+/// the replicas are majority voted bit by bit. Returns the message and the statistics.
+pub fn extract_tiled(image: &RgbImage, tile_size: u32, prng_state: &mut PrngState) -> Result<(Vec<u8>, TileStats), ExtractError> {
+
+    let grid = TileGrid::new(tile_size, prng_state);
+    if grid.record_len() <= TILE_HEADER {
+        return Err(ExtractError::Failed(format!("Tile size {} is too small.", tile_size)));
+    }
+
+    //the first geometry with a valid record wins. When all tiles are damaged, the first one with the magic is voted.
+    let mut found = None;
+    let mut marked = None;
+    for geometry in Geometry::all() {
+        let undone = geometry.undo(image);
+        match grid.find_offset(&undone) {
+            Some((true, dx, dy)) => {
+                found = Some((geometry, undone, dx, dy));
+                break;
+            },
+            Some((false, dx, dy)) if marked.is_none() => marked = Some((geometry, undone, dx, dy)),
+            _ => {},
+        }
+    }
+    let (geometry, image, dx, dy) = match found.or(marked) {
+        Some(f) => f,
+        None => return Err(ExtractError::NoPayload("No valid tile found.\nDid you use the correct key and tile size?!".to_string())),
+    };
+    let image = image.as_ref();
+
+    //collect valid records and raw replicas of every tile
+    let records: Vec<Vec<u8>> = grid.tiles(image, dx, dy).iter().map(|&(ox, oy)| grid.read(image, ox, oy, grid.record_len())).collect();
+    let valid: Vec<TileRecord> = records.iter().filter_map(|r| parse_record(r)).collect();
+    let marked: Vec<Vec<u8>> = records.iter().filter(|r| r[0..2] == TILE_MAGIC).cloned().collect();
+    let (count, msg_len) = match valid.first() {
+        Some(r) => (r.count, r.msg_len),
+        None => {
+            //all tiles are damaged, fields shared by all tiles are majority voted
+            let h = majority(&marked);
+            (u16::from_le_bytes([h[4], h[5]]) as usize, h[6] as usize | (h[7] as usize) << 8 | (h[8] as usize) << 16)
+        },
+    };
+    if count == 0 || msg_len > count * (grid.record_len() - TILE_HEADER) || msg_len > crate::MAX_MSG_LEN {
+        return Err(ExtractError::Decode("Header of the tiles is corrupted.".to_string()));
+    }
+
+    let mut stats = TileStats { tiles: records.len(), valid_tiles: valid.len(), fragments: count, voted_fragments: 0, lost_fragments: Vec::new(), geometry };
+    let fragment_len = grid.record_len() - TILE_HEADER;
+    let mut msg = Vec::with_capacity(msg_len + fragment_len);
+    for index in 0..count {
+        if let Some(r) = valid.iter().find(|r| r.index == index && r.count == count) {
+            msg.extend_from_slice(&r.data);
+            continue;
+        }
+
+        //no replica passed the CRC check, majority vote all tiles claiming to carry this fragment
+        let replicas: Vec<Vec<u8>> = marked.iter()
+            .filter(|r| u16::from_le_bytes([r[2], r[3]]) as usize == index)
+            .cloned().collect();
+        match if replicas.is_empty() { None } else { parse_record(&majority(&replicas)) } {
+            Some(r) if r.index == index => {
+                msg.extend_from_slice(&r.data);
+                stats.voted_fragments += 1;
+            },
+            _ => stats.lost_fragments.push(index),
+        }
+    }
+
+    if !stats.lost_fragments.is_empty() {
+        return Err(ExtractError::Decode(format!("{} of {} fragments are lost or corrupted (first is fragment {}).", stats.lost_fragments.len(), count, stats.lost_fragments[0] + 1)));
+    }
+
+    msg.truncate(msg_len);
+    Ok((msg, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::seeded_prng;
+
+    //image with distinct pixel values, so the tiles can't be found by chance
+    fn noise(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 7 + y) as u8, (x * 13 + y * 3) as u8, (x + y * 11) as u8]))
+    }
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 89 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn majority_votes_each_bit() {
+        let replicas = vec![vec![0b1100, 0xff], vec![0b1010, 0x00], vec![0b1001, 0xff]];
+        assert_eq!(majority(&replicas), vec![0b1000, 0xff]);
+    }
+
+    #[test]
+    fn tiled_round_trip() {
+        let mut image = noise(128, 128);
+        let msg = message(200);
+        let copies = hide_tiled(&msg, &mut image, 32, &mut seeded_prng(1)).unwrap();
+        let (extracted, stats) = extract_tiled(&image, 32, &mut seeded_prng(1)).unwrap();
+        assert_eq!(extracted, msg);
+        assert_eq!((stats.tiles, stats.valid_tiles, stats.voted_fragments), (16, 16, 0));
+        assert_eq!(copies, 16 / stats.fragments);
+    }
+
+    #[test]
+    fn cropped_image_keeps_the_message() {
+        let mut image = noise(128, 128);
+        let msg = message(200);
+        hide_tiled(&msg, &mut image, 32, &mut seeded_prng(1)).unwrap();
+        let cropped = image::imageops::crop_imm(&image, 13, 5, 100, 110).to_image();
+        assert_eq!(extract_tiled(&cropped, 32, &mut seeded_prng(1)).unwrap().0, msg);
+    }
+
+    #[test]
+    fn rotated_mirrored_and_upscaled_image_keeps_the_message() {
+        let mut image = noise(128, 96);
+        let msg = message(100);
+        hide_tiled(&msg, &mut image, 32, &mut seeded_prng(1)).unwrap();
+
+        let mut changed = rotate90(&image);
+        flip_horizontal_in_place(&mut changed);
+        let changed = image::imageops::resize(&changed, 96 * 3, 128 * 3, image::imageops::FilterType::Nearest);
+        let cropped = image::imageops::crop_imm(&changed, 7, 11, 250, 350).to_image();
+        let (extracted, stats) = extract_tiled(&cropped, 32, &mut seeded_prng(1)).unwrap();
+        assert_eq!(extracted, msg);
+        assert_eq!(stats.geometry, Geometry { quarter_turns: 3, mirrored: true, scale: 3 });
+
+        let (_, stats) = extract_tiled(&image, 32, &mut seeded_prng(1)).unwrap();
+        assert_eq!(stats.geometry, Geometry::ORIGINAL);
+    }
+
+    #[test]
+    fn damaged_replicas_are_majority_voted() {
+        let mut image = noise(96, 96);
+        let msg = message(30);
+        hide_tiled(&msg, &mut image, 32, &mut seeded_prng(1)).unwrap();
+
+        //one message fits a single tile, so all 9 tiles are its replicas. Damage a different sample in each tile,
+        //so no replica passes the CRC check, but the vote restores every bit.
+        let grid = TileGrid::new(32, &mut seeded_prng(1));
+        for (i, (ox, oy)) in grid.tiles(&image, 0, 0).into_iter().enumerate() {
+            let (x, y, c) = grid.position(ox, oy, TILE_HEADER * 8 + i);
+            image.get_pixel_mut(x, y)[c] ^= 1;
+        }
+        let (extracted, stats) = extract_tiled(&image, 32, &mut seeded_prng(1)).unwrap();
+        assert_eq!(extracted, msg);
+        assert_eq!((stats.valid_tiles, stats.voted_fragments), (0, 1));
+    }
+
+    #[test]
+    fn lost_fragment_is_reported() {
+        let mut image = noise(64, 32);
+        let msg = message(tiled_capacity(64, 32, 32));
+        hide_tiled(&msg, &mut image, 32, &mut seeded_prng(1)).unwrap();
+
+        //each of the two fragments has one replica, damage the second one
+        let grid = TileGrid::new(32, &mut seeded_prng(1));
+        let (x, y, c) = grid.position(32, 0, TILE_HEADER * 8);
+        image.get_pixel_mut(x, y)[c] ^= 1;
+        let e = extract_tiled(&image, 32, &mut seeded_prng(1)).err().unwrap();
+        assert!(matches!(e, ExtractError::Decode(_)), "{}", e);
+    }
+
+    #[test]
+    fn wrong_key_finds_no_tile() {
+        let mut image = noise(64, 64);
+        hide_tiled(&message(10), &mut image, 32, &mut seeded_prng(1)).unwrap();
+        assert!(matches!(extract_tiled(&image, 32, &mut seeded_prng(2)), Err(ExtractError::NoPayload(_))));
+    }
+
+    #[test]
+    fn too_large_message_is_refused() {
+        let mut image = noise(64, 64);
+        assert!(hide_tiled(&message(tiled_capacity(64, 64, 32) + 1), &mut image, 32, &mut seeded_prng(1)).is_err());
+        assert!(hide_tiled(&message(1), &mut image, 2, &mut seeded_prng(1)).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+//! WebAssembly API for the web apps. Everything runs client-side on the byte arrays, images are encoded
+//! files (PNG, BMP, ...) and embed returns the stego image as PNG.
+
+use wasm_bindgen::prelude::*;
+use crate::buffer;
+
+/// Hide the message into the image
+#[wasm_bindgen]
+pub fn embed(image: &[u8], key: &[u8], message: &[u8]) -> Result<Vec<u8>, JsError> {
+    buffer::embed_image(image, key, message).map_err(|s| JsError::new(&s))
+}
+
+/// Extract the message from the image
+#[wasm_bindgen]
+pub fn extract(image: &[u8], key: &[u8]) -> Result<Vec<u8>, JsError> {
+    buffer::extract_image(image, key).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Capacity of the image in bytes
+#[wasm_bindgen]
+pub fn capacity(image: &[u8]) -> Result<usize, JsError> {
+    buffer::image_capacity(image).map(|(_, _, capacity)| capacity).map_err(|s| JsError::new(&s))
+}
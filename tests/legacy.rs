@@ -0,0 +1,64 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use stegegg::*;
+
+//legacy-0.1.png was hidden by the stegegg 0.1 release: no salt, no trailer and the spot order seeded by SHA256 of the key
+const KEY: &str = "baseline key";
+const MESSAGE: &[u8] = b"Hidden by stegegg 0.1.";
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("stegegg-{}-{}", std::process::id(), name))
+}
+
+//run stegegg with the arguments, panics with its output when it fails
+fn stegegg(args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_stegegg")).args(args).output().unwrap();
+    assert!(output.status.success(), "stegegg {:?} failed: {}{}", args, String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
+
+//message of the image read exactly as stegegg 0.1 reads it
+fn extract_as_0_1(path: &Path, key: &str) -> Vec<u8> {
+    let img = image::open(path).unwrap().to_rgb8();
+    let layout = SpotLayout { redundant: false, depth: DEFAULT_DEPTH };
+    let indices = hidding_spots(&img, layout, 0, &mut init_prng(key.as_bytes(), &[], &[])).unwrap();
+    let len = read_header(&img, layout, &indices).unwrap();
+    extract_bytes(&img, layout, &indices[3 * 8..], 0, len)
+}
+
+#[test]
+fn legacy_extracts_0_1_image() {
+    let out = temp_path("legacy.txt");
+    stegegg(&["-x", "--legacy", "--force", "-k", KEY, fixture("legacy-0.1.png").to_str().unwrap(), out.to_str().unwrap()]);
+    assert_eq!(std::fs::read(&out).unwrap(), MESSAGE);
+    std::fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn legacy_hides_for_0_1() {
+    let out = temp_path("legacy.png");
+    stegegg(&["--legacy", "--force", "-k", KEY, "-m", "readable by 0.1", fixture("legacy-0.1.png").to_str().unwrap(), out.to_str().unwrap()]);
+    assert_eq!(extract_as_0_1(&out, KEY), b"readable by 0.1");
+    std::fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn auto_extracts_0_1_image() {
+    let out = temp_path("auto.txt");
+    stegegg(&["-x", "--auto", "--force", "-k", KEY, fixture("legacy-0.1.png").to_str().unwrap(), out.to_str().unwrap()]);
+    assert_eq!(std::fs::read(&out).unwrap(), MESSAGE);
+    std::fs::remove_file(out).unwrap();
+}
+
+#[test]
+fn auto_finds_legacy_redundant_layout() {
+    let (hidden, out) = (temp_path("auto-redundant.png"), temp_path("auto-redundant.txt"));
+    stegegg(&["--legacy", "--redundant", "--force", "-k", KEY, "-m", "voted", fixture("legacy-0.1.png").to_str().unwrap(), hidden.to_str().unwrap()]);
+    stegegg(&["-x", "--auto", "--force", "-k", KEY, hidden.to_str().unwrap(), out.to_str().unwrap()]);
+    assert_eq!(std::fs::read(&out).unwrap(), b"voted");
+    std::fs::remove_file(hidden).unwrap();
+    std::fs::remove_file(out).unwrap();
+}